@@ -1,52 +1,217 @@
-use crate::services::{UserService, UserServiceError};
-use carapax::{access::AccessPolicy, types::Integer, HandlerInput};
-use futures_util::future::{BoxFuture, OptionFuture};
+use crate::{
+    api::Api,
+    config::{Locales, RateLimit},
+    services::{RateLimitService, RateLimitServiceError, RateLimitStatus, UserService, UserServiceError},
+};
+use carapax::{
+    access::AccessPolicy,
+    methods::SendMessage,
+    types::{Integer, ParseMode, UpdateKind},
+    ExecuteError, HandlerInput,
+};
+use chrono::Utc;
+use futures_util::future::BoxFuture;
+use std::{
+    collections::HashMap,
+    error::Error,
+    fmt,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tokio::sync::Mutex;
+
+// parts of a media group arrive as separate updates within a short interval
+const MEDIA_GROUP_TTL: Duration = Duration::from_secs(60);
 
 #[derive(Clone)]
 pub struct SubscriberAccessPolicy {
+    api: Api,
     user_service: UserService,
+    rate_limit_service: RateLimitService,
+    rate_limit: Option<RateLimit>,
+    locales: Option<Locales>,
     admin_chat_id: Integer,
+    admin_chat_ids: Vec<Integer>,
+    media_groups: Arc<Mutex<HashMap<String, (Instant, bool)>>>,
 }
 
 impl SubscriberAccessPolicy {
-    pub fn new(user_service: UserService, admin_chat_id: Integer) -> Self {
+    pub fn new(
+        api: Api,
+        user_service: UserService,
+        rate_limit_service: RateLimitService,
+        rate_limit: Option<RateLimit>,
+        locales: Option<Locales>,
+        admin_chat_id: Integer,
+        admin_chat_ids: Vec<Integer>,
+    ) -> Self {
         Self {
+            api,
             user_service,
+            rate_limit_service,
+            rate_limit,
+            locales,
             admin_chat_id,
+            admin_chat_ids,
+            media_groups: Default::default(),
+        }
+    }
+
+    async fn check_media_group_rate_limit(
+        &self,
+        rate_limit: &RateLimit,
+        user_id: Integer,
+        language_code: Option<&str>,
+        media_group_id: &str,
+    ) -> Result<bool, SubscriberAccessError> {
+        // the lock is held until the first part is checked, so a media group is counted once
+        let mut media_groups = self.media_groups.lock().await;
+        let now = Instant::now();
+        media_groups.retain(|_, (checked_at, _)| now.duration_since(*checked_at) < MEDIA_GROUP_TTL);
+        if let Some((_, is_granted)) = media_groups.get(media_group_id) {
+            return Ok(*is_granted);
+        }
+        let is_granted = self.check_rate_limit(rate_limit, user_id, language_code).await?;
+        media_groups.insert(String::from(media_group_id), (now, is_granted));
+        Ok(is_granted)
+    }
+
+    async fn check_rate_limit(
+        &self,
+        rate_limit: &RateLimit,
+        user_id: Integer,
+        language_code: Option<&str>,
+    ) -> Result<bool, SubscriberAccessError> {
+        let violations = match self
+            .rate_limit_service
+            .hit(user_id, rate_limit.messages(), rate_limit.window())
+            .await
+            .map_err(SubscriberAccessError::HitRateLimit)?
+        {
+            RateLimitStatus::Allowed => return Ok(true),
+            RateLimitStatus::Exceeded => return Ok(false),
+            RateLimitStatus::Violation(violations) => violations,
+        };
+        if let Some(warning) = rate_limit.warning() {
+            let warning = self
+                .locales
+                .as_ref()
+                .and_then(|locales| locales.get(language_code, "rate_limit_warning"))
+                .unwrap_or(warning);
+            self.api
+                .execute(SendMessage::new(user_id, warning).parse_mode(ParseMode::Html))
+                .await
+                .map_err(SubscriberAccessError::SendWarning)?;
         }
+        if let Some(block) = rate_limit.block().filter(|block| violations >= block.violations()) {
+            let until = Utc::now().naive_utc() + block.duration();
+            self.user_service
+                .block_until(user_id, until)
+                .await
+                .map_err(SubscriberAccessError::Block)?;
+            self.rate_limit_service
+                .reset_violations(user_id)
+                .await
+                .map_err(SubscriberAccessError::ResetViolations)?;
+            self.api
+                .execute(
+                    SendMessage::new(
+                        self.admin_chat_id,
+                        format!(
+                            r#"<a href="tg://user?id={id}">{id}</a> is blocked until {until} UTC for flooding"#,
+                            id = user_id,
+                            until = until.format("%d/%m/%y %H:%M:%S")
+                        ),
+                    )
+                    .parse_mode(ParseMode::Html),
+                )
+                .await
+                .map_err(SubscriberAccessError::SendNotice)?;
+        }
+        Ok(false)
     }
 }
 
 impl AccessPolicy for SubscriberAccessPolicy {
-    type Error = UserServiceError;
+    type Error = SubscriberAccessError;
     type Future = BoxFuture<'static, Result<bool, Self::Error>>;
 
     fn is_granted(&self, input: HandlerInput) -> Self::Future {
-        let user_service = self.user_service.clone();
-        let admin_chat_id = self.admin_chat_id;
+        let policy = self.clone();
         Box::pin(async move {
-            Ok(
-                if input
-                    .update
-                    .get_chat_id()
-                    .map(|chat_id| chat_id == admin_chat_id)
-                    .unwrap_or(false)
-                {
-                    // admins has no access to subscriber handlers
-                    false
-                } else {
-                    OptionFuture::from(
-                        input
-                            .update
-                            .get_user_id()
-                            .map(|user_id| user_service.is_blocked(user_id)),
-                    )
-                    .await
-                    .transpose()?
-                    .map(|is_blocked| !is_blocked)
-                    .unwrap_or(true) // allow for all other users by default
-                },
-            )
+            if input
+                .update
+                .get_chat_id()
+                .map(|chat_id| policy.admin_chat_ids.contains(&chat_id))
+                .unwrap_or(false)
+            {
+                // admins has no access to subscriber handlers
+                return Ok(false);
+            }
+            let user_id = match input.update.get_user_id() {
+                Some(user_id) => user_id,
+                None => return Ok(true), // allow for all other users by default
+            };
+            if policy
+                .user_service
+                .is_blocked(user_id)
+                .await
+                .map_err(SubscriberAccessError::CheckIsBlocked)?
+            {
+                return Ok(false);
+            }
+            match (&policy.rate_limit, &input.update.kind) {
+                (Some(rate_limit), UpdateKind::Message(message)) => {
+                    let language_code = message.get_user().and_then(|user| user.language_code.as_deref());
+                    match message.media_group_id {
+                        Some(ref media_group_id) => {
+                            policy
+                                .check_media_group_rate_limit(rate_limit, user_id, language_code, media_group_id)
+                                .await
+                        }
+                        None => policy.check_rate_limit(rate_limit, user_id, language_code).await,
+                    }
+                }
+                _ => Ok(true),
+            }
+        })
+    }
+}
+
+#[derive(Debug)]
+pub enum SubscriberAccessError {
+    Block(UserServiceError),
+    CheckIsBlocked(UserServiceError),
+    HitRateLimit(RateLimitServiceError),
+    ResetViolations(RateLimitServiceError),
+    SendNotice(ExecuteError),
+    SendWarning(ExecuteError),
+}
+
+impl fmt::Display for SubscriberAccessError {
+    fn fmt(&self, out: &mut fmt::Formatter) -> fmt::Result {
+        use self::SubscriberAccessError::*;
+        match self {
+            Block(err) => err.fmt(out),
+            CheckIsBlocked(err) => err.fmt(out),
+            HitRateLimit(err) => err.fmt(out),
+            ResetViolations(err) => err.fmt(out),
+            SendNotice(err) => err.fmt(out),
+            SendWarning(err) => err.fmt(out),
+        }
+    }
+}
+
+impl Error for SubscriberAccessError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        use self::SubscriberAccessError::*;
+        Some(match self {
+            Block(err) => err,
+            CheckIsBlocked(err) => err,
+            HitRateLimit(err) => err,
+            ResetViolations(err) => err,
+            SendNotice(err) => err,
+            SendWarning(err) => err,
         })
     }
 }
@@ -0,0 +1,31 @@
+use crate::metrics;
+use carapax::{methods::Method, Api as Client, ApiError, ExecuteError};
+use serde::de::DeserializeOwned;
+use std::{any::type_name, time::Instant};
+
+#[derive(Clone)]
+pub struct Api {
+    client: Client,
+}
+
+impl Api {
+    pub fn new(token: &str) -> Result<Self, ApiError> {
+        Client::new(token).map(|client| Self { client })
+    }
+
+    pub fn client(&self) -> Client {
+        self.client.clone()
+    }
+
+    pub async fn execute<M>(&self, method: M) -> Result<M::Response, ExecuteError>
+    where
+        M: Method,
+        M::Response: DeserializeOwned + Send + 'static,
+    {
+        let name = type_name::<M>().rsplit("::").next().unwrap_or_default();
+        let started_at = Instant::now();
+        let result = self.client.execute(method).await;
+        metrics::observe_api_request(name, started_at.elapsed(), result.is_err());
+        result
+    }
+}
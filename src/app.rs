@@ -1,22 +1,37 @@
 use crate::{
     access::SubscriberAccessPolicy,
+    api::Api,
     config::{Config, ConfigError},
-    handlers, migrations,
-    services::{MessageLinkService, UserService},
+    db::Client,
+    handlers,
+    health::{self, Health},
+    metrics, migrations,
+    scheduler::Scheduler,
+    services::{
+        AdminService, ConversationService, GreetingService, MediaGroupService, MessageLinkService, PendingReplyService,
+        RateLimitService, RatingService, ScheduleService, SettingsService, SnippetService, UserService,
+        VerificationService,
+    },
+    shutdown::{wait_for_signal, Shutdown, Tracker},
 };
 use carapax::{
     access::{AccessExt, AccessRule, InMemoryAccessPolicy},
     longpoll::LongPoll,
-    webhook,
+    methods::{GetUpdates, SetMyCommands},
+    types::{BotCommand, BotCommandError, BotCommandScope},
     webhook::HyperError,
-    Api, ApiError, App, Chain, Context,
+    ApiError, App, Chain, Context, ExecuteError, UpdateHandler,
 };
 use clap::{Parser, Subcommand};
+use futures_util::future::join;
 use refinery::Error as MigrationError;
-use std::{error::Error, fmt, sync::Arc};
-use tokio::spawn;
+use std::{error::Error, fmt, io::Error as IoError, net::SocketAddr, sync::Arc, time::Duration};
+use tokio::{pin, select, spawn, time::timeout};
 use tokio_postgres::{connect as pg_connect, Client as PgClient, Error as PgError, NoTls as PgNoTls};
 
+const DEFAULT_SHUTDOWN_TIMEOUT: u64 = 30;
+const PG_CLOSE_TIMEOUT: Duration = Duration::from_secs(5);
+
 #[derive(Parser)]
 #[clap(about, author, version)]
 pub struct Arguments {
@@ -43,7 +58,7 @@ pub async fn run() -> Result<(), AppError> {
         .await
         .map_err(AppError::PgConnect)?;
 
-    spawn(async move {
+    let pg_connection = spawn(async move {
         if let Err(err) = pg_connection.await {
             log::error!("PostgreSQL connection error: {}", err);
         }
@@ -52,72 +67,246 @@ pub async fn run() -> Result<(), AppError> {
     match args.command {
         Command::Migrate => {
             migrations::run(&mut pg_client).await.map_err(AppError::Migrate)?;
+            drop(pg_client);
         }
         Command::Start => {
             start(config, pg_client).await?;
         }
     }
 
+    // the connection is closed when the last client is dropped
+    if timeout(PG_CLOSE_TIMEOUT, pg_connection).await.is_err() {
+        log::warn!("PostgreSQL connection is still in use, exiting");
+    }
+
     Ok(())
 }
 
 async fn start(config: Config, pg_client: PgClient) -> Result<(), AppError> {
     let api = Api::new(&config.token).map_err(AppError::CreateApi)?;
 
-    let pg_client = Arc::new(pg_client);
+    if let Some(ref menu) = config.menu {
+        // commands without a language are shown to subscribers whose language has no locale file,
+        // Telegram accepts two-letter language codes only, so regional files (e.g. pt-br) are skipped
+        let language_codes = config
+            .language_codes()
+            .into_iter()
+            .filter(|language_code| language_code.len() == 2);
+        for language_code in [None].into_iter().chain(language_codes.map(Some)) {
+            let commands = menu
+                .items()
+                .iter()
+                .map(|item| BotCommand::new(item.command(), config.menu_description(language_code, item)))
+                .collect::<Result<Vec<BotCommand>, BotCommandError>>()
+                .map_err(AppError::BuildCommands)?;
+            let mut method = SetMyCommands::new(commands).scope(BotCommandScope::AllPrivateChats);
+            if let Some(language_code) = language_code {
+                method = method.language_code(language_code);
+            }
+            api.execute(method).await.map_err(AppError::SetCommands)?;
+        }
+    }
+
+    let pg_client = Arc::new(Client::new(pg_client));
+    let admin_service = AdminService::new(pg_client.clone());
+    let conversation_service = ConversationService::new(pg_client.clone());
+    let message_link_service = MessageLinkService::new(pg_client.clone());
+    let pending_reply_service = PendingReplyService::new(pg_client.clone());
+    let schedule_service = ScheduleService::new(pg_client.clone());
     let user_service = UserService::new(pg_client.clone());
 
-    let admin_policy = InMemoryAccessPolicy::from(vec![AccessRule::allow_chat(config.chat_id)]);
-    let subscriber_policy = SubscriberAccessPolicy::new(user_service.clone(), config.chat_id);
+    let admin_policy = InMemoryAccessPolicy::from(
+        config
+            .admin_chat_ids()
+            .into_iter()
+            .map(AccessRule::allow_chat)
+            .collect::<Vec<AccessRule>>(),
+    );
+    let rate_limit_service = match config.rate_limit {
+        Some(ref rate_limit) if rate_limit.is_persistent() => RateLimitService::persistent(pg_client.clone()),
+        _ => RateLimitService::in_memory(),
+    };
+    let subscriber_policy = SubscriberAccessPolicy::new(
+        api.clone(),
+        user_service.clone(),
+        rate_limit_service,
+        config.rate_limit.clone(),
+        config.locales.clone(),
+        config.chat_id,
+        config.admin_chat_ids(),
+    );
+
+    let shutdown = Shutdown::new();
+    let tracker = shutdown.tracker();
 
     let mut context = Context::default();
     context.insert(config.clone());
+    context.insert(admin_service.clone());
     context.insert(api.clone());
-    context.insert(MessageLinkService::new(pg_client.clone()));
-    context.insert(user_service);
+    context.insert(conversation_service.clone());
+    context.insert(GreetingService::new(pg_client.clone()));
+    context.insert(MediaGroupService::new());
+    context.insert(message_link_service.clone());
+    context.insert(pending_reply_service.clone());
+    context.insert(schedule_service.clone());
+    context.insert(RatingService::new(pg_client.clone()));
+    context.insert(SettingsService::new(pg_client.clone()));
+    context.insert(SnippetService::new(pg_client.clone()));
+    context.insert(tracker.clone());
+    context.insert(user_service.clone());
+    context.insert(VerificationService::new(pg_client.clone()));
 
     let chain = Chain::all()
         .add(handlers::middleware::setup())
         .add(handlers::admin::setup().access(admin_policy))
         .add(handlers::subscriber::setup().access(subscriber_policy));
 
-    let app = App::new(context, chain);
+    let app = tracker.wrap(App::new(context, chain));
+
+    let health = Health::new(api.clone(), pg_client.clone());
+    if let Some(address) = config.health_address {
+        let server = health::run_server(address, health.clone(), tracker.stopped());
+        tracker.spawn(async move {
+            if let Err(err) = server.await {
+                log::error!("Could not start server for health checks: {}", err);
+            }
+        });
+    }
+
+    if let Some(ref metrics) = config.metrics {
+        let server = metrics::run_server(
+            metrics.address(),
+            metrics.path().to_string(),
+            user_service.clone(),
+            tracker.stopped(),
+        );
+        tracker.spawn(async move {
+            if let Err(err) = server.await {
+                log::error!("Could not start server for metrics: {}", err);
+            }
+        });
+    }
+
+    tracker.spawn(
+        Scheduler::new(
+            api.clone(),
+            config.clone(),
+            admin_service,
+            conversation_service,
+            message_link_service,
+            pending_reply_service,
+            schedule_service,
+            user_service,
+        )
+        .run(tracker.stopped()),
+    );
 
-    match config.webhook_address {
+    let receiving = receive_updates(
+        api.clone(),
+        config.webhook_address,
+        config.webhook_path.clone(),
+        app,
+        health,
+        tracker,
+    );
+    pin!(receiving);
+    select! {
+        result = &mut receiving => return result.map_err(AppError::StartServer),
+        result = wait_for_signal() => result.map_err(AppError::WaitSignal)?,
+    }
+
+    log::info!("Shutting down");
+    shutdown.stop();
+    let shutdown_timeout = config.shutdown_timeout.unwrap_or(DEFAULT_SHUTDOWN_TIMEOUT);
+    let is_long_poll = config.webhook_address.is_none();
+    let stopping = async move {
+        let result = receiving.await;
+        let last_update_id = shutdown.wait().await;
+        if let Some(update_id) = last_update_id.filter(|_| is_long_poll) {
+            confirm_updates(&api, update_id).await;
+        }
+        result
+    };
+    match timeout(Duration::from_secs(shutdown_timeout), stopping).await {
+        Ok(result) => result.map_err(AppError::StartServer)?,
+        Err(_) => log::warn!(
+            "Could not finish in-flight updates in {} seconds, exiting",
+            shutdown_timeout
+        ),
+    }
+
+    Ok(())
+}
+
+async fn receive_updates<H>(
+    api: Api,
+    webhook_address: Option<SocketAddr>,
+    webhook_path: Option<String>,
+    handler: H,
+    health: Health,
+    tracker: Tracker,
+) -> Result<(), HyperError>
+where
+    H: UpdateHandler + Send + Sync + 'static,
+    H::Future: Send + 'static,
+{
+    match webhook_address {
         Some(address) => {
-            let path = config.webhook_path.unwrap_or_else(|| String::from("/"));
-            webhook::run_server(address, path, app)
-                .await
-                .map_err(AppError::StartServer)?;
+            let path = webhook_path.unwrap_or_else(|| String::from("/"));
+            health::run_webhook_server(address, path, handler, health, tracker.stopped()).await
         }
         None => {
-            LongPoll::new(api, app).run().await;
+            let long_poll = LongPoll::new(api.client(), handler);
+            let handle = long_poll.get_handle();
+            let stopped = tracker.stopped();
+            join(long_poll.run(), async move {
+                stopped.await;
+                handle.shutdown().await;
+            })
+            .await;
+            Ok(())
         }
     }
+}
 
-    Ok(())
+// the last batch is confirmed by the next getUpdates call only,
+// without it the updates would be handled again after restart
+async fn confirm_updates(api: &Api, last_update_id: i64) {
+    let method = GetUpdates::default()
+        .offset(last_update_id + 1)
+        .limit(1)
+        .timeout(Duration::from_secs(0));
+    if let Err(err) = api.execute(method).await {
+        log::error!("Could not confirm received updates: {}", err);
+    }
 }
 
 #[derive(Debug)]
 pub enum AppError {
+    BuildCommands(BotCommandError),
     CreateApi(ApiError),
     Migrate(MigrationError),
     NoConfig,
     PgConnect(PgError),
     ReadConfig(ConfigError),
+    SetCommands(ExecuteError),
     StartServer(HyperError),
+    WaitSignal(IoError),
 }
 
 impl fmt::Display for AppError {
     fn fmt(&self, out: &mut fmt::Formatter) -> fmt::Result {
         use self::AppError::*;
         match self {
+            BuildCommands(err) => write!(out, "Invalid menu command: {}", err),
             CreateApi(err) => write!(out, "Could not create API client: {}", err),
             Migrate(err) => write!(out, "Migration error: {}", err),
             NoConfig => write!(out, "Path to configuration file is not provided"),
             PgConnect(err) => write!(out, "PostgreSQL: {}", err),
             ReadConfig(err) => write!(out, "{}", err),
+            SetCommands(err) => write!(out, "Could not set bot commands: {}", err),
             StartServer(err) => write!(out, "Could not start server for webhooks: {}", err),
+            WaitSignal(err) => write!(out, "Could not listen for shutdown signals: {}", err),
         }
     }
 }
@@ -126,12 +315,15 @@ impl Error for AppError {
     fn source(&self) -> Option<&(dyn Error + 'static)> {
         use self::AppError::*;
         Some(match self {
+            BuildCommands(err) => err,
             CreateApi(err) => err,
             Migrate(err) => err,
             NoConfig => return None,
             PgConnect(err) => err,
             ReadConfig(err) => err,
+            SetCommands(err) => err,
             StartServer(err) => err,
+            WaitSignal(err) => err,
         })
     }
 }
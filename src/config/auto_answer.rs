@@ -0,0 +1,70 @@
+use regex::{Error as RegexError, Regex};
+use serde::Deserialize;
+
+#[derive(Clone, Deserialize)]
+pub struct AutoAnswers {
+    #[serde(default = "default_true")]
+    enabled: bool,
+    rules: Vec<AutoAnswerRule>,
+}
+
+impl AutoAnswers {
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn find(&self, text: &str) -> Option<&AutoAnswerRule> {
+        self.rules.iter().find(|rule| rule.matcher.is_match(text))
+    }
+}
+
+#[derive(Clone, Deserialize)]
+pub struct AutoAnswerRule {
+    #[serde(flatten)]
+    matcher: AutoAnswerMatcher,
+    answer: String,
+    #[serde(default = "default_true")]
+    forward: bool,
+}
+
+impl AutoAnswerRule {
+    pub fn answer(&self) -> &str {
+        &self.answer
+    }
+
+    pub fn forward(&self) -> bool {
+        self.forward
+    }
+}
+
+#[derive(Clone, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum AutoAnswerMatcher {
+    Keyword(String),
+    Regex(Pattern),
+}
+
+impl AutoAnswerMatcher {
+    fn is_match(&self, text: &str) -> bool {
+        match self {
+            AutoAnswerMatcher::Keyword(keyword) => text.to_lowercase().contains(&keyword.to_lowercase()),
+            AutoAnswerMatcher::Regex(pattern) => pattern.0.is_match(text),
+        }
+    }
+}
+
+#[derive(Clone, Deserialize)]
+#[serde(try_from = "String")]
+struct Pattern(Regex);
+
+impl TryFrom<String> for Pattern {
+    type Error = RegexError;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        Regex::new(&value).map(Self)
+    }
+}
+
+fn default_true() -> bool {
+    true
+}
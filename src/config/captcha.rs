@@ -0,0 +1,32 @@
+use serde::Deserialize;
+
+const DEFAULT_TEXT: &str = "Please confirm that you are not a robot";
+
+#[derive(Clone, Deserialize)]
+pub struct Captcha {
+    kind: CaptchaKind,
+    text: Option<String>,
+    #[serde(default)]
+    hold_messages: bool,
+}
+
+impl Captcha {
+    pub fn kind(&self) -> CaptchaKind {
+        self.kind
+    }
+
+    pub fn text(&self) -> &str {
+        self.text.as_deref().unwrap_or(DEFAULT_TEXT)
+    }
+
+    pub fn hold_messages(&self) -> bool {
+        self.hold_messages
+    }
+}
+
+#[derive(Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CaptchaKind {
+    Arithmetic,
+    Button,
+}
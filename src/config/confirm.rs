@@ -0,0 +1,18 @@
+use chrono::Duration;
+use serde::Deserialize;
+
+#[derive(Clone, Deserialize)]
+pub struct ConfirmReplies {
+    #[serde(default = "default_timeout")]
+    timeout: i64,
+}
+
+impl ConfirmReplies {
+    pub fn timeout(&self) -> Duration {
+        Duration::seconds(self.timeout)
+    }
+}
+
+fn default_timeout() -> i64 {
+    600
+}
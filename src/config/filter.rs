@@ -0,0 +1,212 @@
+use carapax::types::{Integer, Message, MessageData, Text, TextEntity};
+use serde::Deserialize;
+use std::fmt;
+
+const DEFAULT_REJECT_MESSAGE: &str = "Your message has been rejected";
+
+#[derive(Clone, Deserialize)]
+pub struct ContentFilter {
+    #[serde(flatten)]
+    rule: FilterRule,
+    #[serde(default)]
+    action: FilterAction,
+    message: Option<String>,
+}
+
+impl ContentFilter {
+    pub fn check(&self, message: &Message) -> Option<FilterViolation<'_>> {
+        self.rule.check(message).map(|reason| FilterViolation {
+            action: self.action,
+            message: self.message.as_deref().unwrap_or(DEFAULT_REJECT_MESSAGE),
+            reason,
+        })
+    }
+}
+
+pub struct FilterViolation<'a> {
+    action: FilterAction,
+    message: &'a str,
+    reason: FilterReason,
+}
+
+impl<'a> FilterViolation<'a> {
+    pub fn action(&self) -> FilterAction {
+        self.action
+    }
+
+    pub fn message(&self) -> &'a str {
+        self.message
+    }
+
+    pub fn reason(&self) -> FilterReason {
+        self.reason
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum FilterAction {
+    #[default]
+    Drop,
+    Reject,
+    Warn,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum FilterReason {
+    BannedWord,
+    FileSize,
+    Link,
+    MessageType,
+}
+
+impl fmt::Display for FilterReason {
+    fn fmt(&self, out: &mut fmt::Formatter) -> fmt::Result {
+        use self::FilterReason::*;
+        match self {
+            BannedWord => write!(out, "banned word"),
+            FileSize => write!(out, "large file"),
+            Link => write!(out, "link"),
+            MessageType => write!(out, "message type"),
+        }
+    }
+}
+
+#[derive(Clone, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum FilterRule {
+    AllowedTypes(Vec<MessageType>),
+    DeniedTypes(Vec<MessageType>),
+    MaxFileSize(Integer),
+    AllowedLinks(Vec<String>),
+    DeniedLinks(Vec<String>),
+    BannedWords(Vec<String>),
+}
+
+impl FilterRule {
+    fn check(&self, message: &Message) -> Option<FilterReason> {
+        let violated = match self {
+            FilterRule::AllowedTypes(types) => MessageType::of(message).any(|x| !types.contains(&x)),
+            FilterRule::DeniedTypes(types) => MessageType::of(message).any(|x| types.contains(&x)),
+            FilterRule::MaxFileSize(size) => get_file_size(message).map(|x| x > *size).unwrap_or(false),
+            FilterRule::AllowedLinks(domains) => get_links(message)
+                .iter()
+                .any(|link| !domains.iter().any(|domain| is_domain_match(link, domain))),
+            FilterRule::DeniedLinks(domains) => get_links(message)
+                .iter()
+                .any(|link| domains.iter().any(|domain| is_domain_match(link, domain))),
+            FilterRule::BannedWords(words) => message
+                .get_text()
+                .map(|text| {
+                    text.data
+                        .to_lowercase()
+                        .split(|c: char| !c.is_alphanumeric())
+                        .any(|x| words.iter().any(|word| word.to_lowercase() == x))
+                })
+                .unwrap_or(false),
+        };
+        if violated {
+            Some(match self {
+                FilterRule::AllowedTypes(_) | FilterRule::DeniedTypes(_) => FilterReason::MessageType,
+                FilterRule::MaxFileSize(_) => FilterReason::FileSize,
+                FilterRule::AllowedLinks(_) | FilterRule::DeniedLinks(_) => FilterReason::Link,
+                FilterRule::BannedWords(_) => FilterReason::BannedWord,
+            })
+        } else {
+            None
+        }
+    }
+}
+
+#[derive(Clone, Copy, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+enum MessageType {
+    Animation,
+    Audio,
+    Contact,
+    Document,
+    Forwarded,
+    Location,
+    Other,
+    Photo,
+    Poll,
+    Sticker,
+    Text,
+    Video,
+    VideoNote,
+    Voice,
+}
+
+impl MessageType {
+    fn of(message: &Message) -> impl Iterator<Item = MessageType> {
+        let kind = match message.data {
+            MessageData::Animation(_) => MessageType::Animation,
+            MessageData::Audio { .. } => MessageType::Audio,
+            MessageData::Contact(_) => MessageType::Contact,
+            MessageData::Document { .. } => MessageType::Document,
+            MessageData::Location(_) | MessageData::Venue(_) => MessageType::Location,
+            MessageData::Photo { .. } => MessageType::Photo,
+            MessageData::Poll(_) => MessageType::Poll,
+            MessageData::Sticker(_) => MessageType::Sticker,
+            MessageData::Text(_) => MessageType::Text,
+            MessageData::Video { .. } => MessageType::Video,
+            MessageData::VideoNote(_) => MessageType::VideoNote,
+            MessageData::Voice { .. } => MessageType::Voice,
+            _ => MessageType::Other,
+        };
+        let forwarded = message.forward.as_ref().map(|_| MessageType::Forwarded);
+        std::iter::once(kind).chain(forwarded)
+    }
+}
+
+fn get_file_size(message: &Message) -> Option<Integer> {
+    match message.data {
+        MessageData::Animation(ref animation) => animation.file_size,
+        MessageData::Audio { ref data, .. } => data.file_size,
+        MessageData::Document { ref data, .. } => data.file_size,
+        MessageData::Photo { ref data, .. } => data.iter().filter_map(|x| x.file_size).max(),
+        MessageData::Sticker(ref sticker) => sticker.file_size,
+        MessageData::Video { ref data, .. } => data.file_size,
+        MessageData::VideoNote(ref video_note) => video_note.file_size,
+        MessageData::Voice { ref data, .. } => data.file_size,
+        _ => None,
+    }
+}
+
+fn get_links(message: &Message) -> Vec<String> {
+    let text = match message.get_text() {
+        Some(text) => text,
+        None => return Vec::new(),
+    };
+    let entities = match text.entities {
+        Some(ref entities) => entities,
+        None => return Vec::new(),
+    };
+    entities
+        .iter()
+        .filter_map(|entity| match entity {
+            TextEntity::Url(position) => Some(get_entity_text(text, position.offset, position.length)),
+            TextEntity::TextLink { url, .. } => Some(url.clone()),
+            _ => None,
+        })
+        .collect()
+}
+
+fn get_entity_text(text: &Text, offset: u32, length: u32) -> String {
+    // entity positions are given in UTF-16 code units
+    let data: Vec<u16> = text
+        .data
+        .encode_utf16()
+        .skip(offset as usize)
+        .take(length as usize)
+        .collect();
+    String::from_utf16_lossy(&data)
+}
+
+fn is_domain_match(link: &str, domain: &str) -> bool {
+    let link = link.to_lowercase();
+    let host = link.split_once("://").map(|(_, rest)| rest).unwrap_or(&link);
+    let host = host.split(&['/', '?', '#', ':'][..]).next().unwrap_or(host);
+    let domain = domain.to_lowercase();
+    host == domain || host.ends_with(&format!(".{}", domain))
+}
@@ -0,0 +1,51 @@
+use carapax::types::User;
+use serde::Deserialize;
+
+const DEFAULT_TEMPLATE: &str = "#id{user_id} {name} {username} {source}";
+
+#[derive(Clone, Deserialize)]
+pub struct Header {
+    #[serde(default)]
+    kind: HeaderKind,
+    template: Option<String>,
+}
+
+impl Header {
+    pub fn kind(&self) -> HeaderKind {
+        self.kind
+    }
+
+    pub fn has_source(&self) -> bool {
+        self.template().contains("{source}")
+    }
+
+    pub fn render(&self, user: &User, conversation_id: i32, source: Option<&str>) -> String {
+        let username = user
+            .username
+            .as_ref()
+            .map(|username| format!("@{}", username))
+            .unwrap_or_default();
+        let source = source.map(|source| format!("via {}", source)).unwrap_or_default();
+        self.template()
+            .replace("{user_id}", &user.id.to_string())
+            .replace("{name}", &user.get_full_name())
+            .replace("{username}", &username)
+            .replace("{language}", user.language_code.as_deref().unwrap_or_default())
+            .replace("{conversation_id}", &conversation_id.to_string())
+            .replace("{source}", &source)
+            .trim()
+            .to_string()
+    }
+
+    fn template(&self) -> &str {
+        self.template.as_deref().unwrap_or(DEFAULT_TEMPLATE)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum HeaderKind {
+    #[default]
+    Text,
+    Caption,
+}
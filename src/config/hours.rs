@@ -0,0 +1,129 @@
+use chrono::{DateTime, Datelike, Duration, NaiveDate, NaiveTime, Utc, Weekday};
+use chrono_tz::Tz;
+use serde::Deserialize;
+use std::{error::Error, fmt};
+
+const DEFAULT_AWAY_INTERVAL: i64 = 3600;
+
+#[derive(Clone, Deserialize)]
+pub struct BusinessHours {
+    timezone: Tz,
+    #[serde(default)]
+    hours: WeeklyHours,
+    #[serde(default)]
+    holidays: Vec<NaiveDate>,
+    away_message: String,
+    #[serde(default = "default_away_interval")]
+    away_interval: i64,
+}
+
+impl BusinessHours {
+    pub fn is_open(&self, now: DateTime<Utc>) -> bool {
+        let now = now.with_timezone(&self.timezone);
+        let date = now.date().naive_local();
+        let time = now.time();
+        let is_open_today =
+            !self.holidays.contains(&date) && self.hours.get(now.weekday()).iter().any(|range| range.contains(time));
+        // a range wrapping midnight started on the previous day
+        let is_open_since_yesterday = !self.holidays.contains(&(date - Duration::days(1)))
+            && self
+                .hours
+                .get(now.weekday().pred())
+                .iter()
+                .any(|range| range.contains_next_day(time));
+        is_open_today || is_open_since_yesterday
+    }
+
+    pub fn away_message(&self) -> &str {
+        &self.away_message
+    }
+
+    pub fn away_interval(&self) -> Duration {
+        Duration::seconds(self.away_interval)
+    }
+}
+
+fn default_away_interval() -> i64 {
+    DEFAULT_AWAY_INTERVAL
+}
+
+#[derive(Clone, Default, Deserialize)]
+struct WeeklyHours {
+    #[serde(default)]
+    mon: Vec<TimeRange>,
+    #[serde(default)]
+    tue: Vec<TimeRange>,
+    #[serde(default)]
+    wed: Vec<TimeRange>,
+    #[serde(default)]
+    thu: Vec<TimeRange>,
+    #[serde(default)]
+    fri: Vec<TimeRange>,
+    #[serde(default)]
+    sat: Vec<TimeRange>,
+    #[serde(default)]
+    sun: Vec<TimeRange>,
+}
+
+impl WeeklyHours {
+    fn get(&self, weekday: Weekday) -> &[TimeRange] {
+        use self::Weekday::*;
+        match weekday {
+            Mon => &self.mon,
+            Tue => &self.tue,
+            Wed => &self.wed,
+            Thu => &self.thu,
+            Fri => &self.fri,
+            Sat => &self.sat,
+            Sun => &self.sun,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Deserialize)]
+#[serde(try_from = "String")]
+struct TimeRange {
+    start: NaiveTime,
+    end: Option<NaiveTime>,
+}
+
+impl TimeRange {
+    fn contains(&self, time: NaiveTime) -> bool {
+        time >= self.start && self.end.map(|end| end < self.start || time < end).unwrap_or(true)
+    }
+
+    fn contains_next_day(&self, time: NaiveTime) -> bool {
+        self.end.map(|end| end < self.start && time < end).unwrap_or(false)
+    }
+}
+
+impl TryFrom<String> for TimeRange {
+    type Error = TimeRangeError;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        let parse = |value: &str| NaiveTime::parse_from_str(value.trim(), "%H:%M");
+        let (start, end) = value.split_once('-').ok_or_else(|| TimeRangeError(value.clone()))?;
+        let start = parse(start).map_err(|_| TimeRangeError(value.clone()))?;
+        // "24:00" means the end of a day
+        let end = match end.trim() {
+            "24:00" => None,
+            end => Some(parse(end).map_err(|_| TimeRangeError(value.clone()))?),
+        };
+        // "22:00-06:00" wraps midnight and ends on the next day
+        if end == Some(start) {
+            return Err(TimeRangeError(value));
+        }
+        Ok(Self { start, end })
+    }
+}
+
+#[derive(Debug)]
+pub struct TimeRangeError(String);
+
+impl fmt::Display for TimeRangeError {
+    fn fmt(&self, out: &mut fmt::Formatter) -> fmt::Result {
+        write!(out, "invalid time range: {}, expected HH:MM-HH:MM", self.0)
+    }
+}
+
+impl Error for TimeRangeError {}
@@ -0,0 +1,67 @@
+use crate::config::ConfigError;
+use serde::Deserialize;
+use std::{
+    collections::HashMap,
+    fs::{read_dir, read_to_string},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+#[derive(Clone, Deserialize)]
+pub struct Locales {
+    path: PathBuf,
+    default: Option<String>,
+    admin: Option<String>,
+    #[serde(skip)]
+    messages: Arc<HashMap<String, HashMap<String, String>>>,
+}
+
+impl Locales {
+    pub(super) fn load(&mut self, base: &Path) -> Result<(), ConfigError> {
+        let mut result = HashMap::new();
+        let path = base.join(&self.path);
+        for entry in read_dir(&path).map_err(|err| ConfigError::ReadLocale(path.clone(), err))? {
+            let path = entry.map_err(|err| ConfigError::ReadLocale(path.clone(), err))?.path();
+            let language_code = match (path.file_stem(), path.extension()) {
+                (Some(stem), Some(extension)) if extension == "yaml" || extension == "yml" => {
+                    stem.to_string_lossy().to_lowercase()
+                }
+                _ => continue,
+            };
+            let data = read_to_string(&path).map_err(|err| ConfigError::ReadLocale(path.clone(), err))?;
+            let messages = serde_yaml::from_str(&data).map_err(|err| ConfigError::ParseLocale(path.clone(), err))?;
+            result.insert(language_code, messages);
+        }
+        self.messages = Arc::new(result);
+        Ok(())
+    }
+
+    pub fn get(&self, language_code: Option<&str>, key: &str) -> Option<&str> {
+        self.find(language_code, key, true)
+    }
+
+    /// Returns a text in the given language only, without falling back to the default language
+    pub fn get_translation(&self, language_code: Option<&str>, key: &str) -> Option<&str> {
+        self.find(language_code, key, false)
+    }
+
+    fn find(&self, language_code: Option<&str>, key: &str, with_default: bool) -> Option<&str> {
+        let language_code = language_code.map(str::to_lowercase);
+        let primary = language_code.as_deref().and_then(|code| code.split(['-', '_']).next());
+        let default = self.default.as_deref().filter(|_| with_default);
+        for code in [language_code.as_deref(), primary, default].into_iter().flatten() {
+            if let Some(text) = self.messages.get(code).and_then(|messages| messages.get(key)) {
+                return Some(text);
+            }
+        }
+        None
+    }
+
+    pub fn language_codes(&self) -> Vec<&str> {
+        self.messages.keys().map(String::as_str).collect()
+    }
+
+    pub fn get_admin(&self, key: &str) -> Option<&str> {
+        self.get(self.admin.as_deref(), key)
+    }
+}
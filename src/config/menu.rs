@@ -0,0 +1,66 @@
+use serde::Deserialize;
+
+#[derive(Clone, Deserialize)]
+pub struct Menu {
+    items: Vec<MenuItem>,
+    buttons: Option<MenuButtons>,
+}
+
+impl Menu {
+    pub fn items(&self) -> &[MenuItem] {
+        &self.items
+    }
+
+    pub fn buttons(&self) -> Option<MenuButtons> {
+        self.buttons
+    }
+
+    pub fn get(&self, index: usize) -> Option<&MenuItem> {
+        self.items.get(index)
+    }
+
+    pub fn find_command(&self, command: &str) -> Option<&MenuItem> {
+        self.items.iter().find(|item| item.command == command)
+    }
+}
+
+#[derive(Clone, Copy, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum MenuButtons {
+    Inline,
+    Reply,
+}
+
+#[derive(Clone, Deserialize)]
+pub struct MenuItem {
+    command: String,
+    description: String,
+    text: Option<String>,
+    media: Option<MenuMedia>,
+}
+
+impl MenuItem {
+    pub fn command(&self) -> &str {
+        &self.command
+    }
+
+    pub fn description(&self) -> &str {
+        &self.description
+    }
+
+    pub fn text(&self) -> Option<&str> {
+        self.text.as_deref()
+    }
+
+    pub fn media(&self) -> Option<&MenuMedia> {
+        self.media.as_ref()
+    }
+}
+
+#[derive(Clone, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MenuMedia {
+    Document(String),
+    Photo(String),
+    Video(String),
+}
@@ -0,0 +1,20 @@
+use serde::Deserialize;
+use std::net::SocketAddr;
+
+const DEFAULT_PATH: &str = "/metrics";
+
+#[derive(Clone, Deserialize)]
+pub struct Metrics {
+    address: SocketAddr,
+    path: Option<String>,
+}
+
+impl Metrics {
+    pub fn address(&self) -> SocketAddr {
+        self.address
+    }
+
+    pub fn path(&self) -> &str {
+        self.path.as_deref().unwrap_or(DEFAULT_PATH)
+    }
+}
@@ -0,0 +1,198 @@
+use carapax::types::{Integer, Message, User};
+use serde::Deserialize;
+use serde_yaml::Error as YamlError;
+use std::{
+    collections::HashMap,
+    error::Error,
+    fmt,
+    fs::read_to_string,
+    io::Error as IoError,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+};
+
+mod auto_answer;
+mod captcha;
+mod confirm;
+mod filter;
+mod header;
+mod hours;
+mod locale;
+mod menu;
+mod metrics;
+mod rate_limit;
+mod rating;
+mod reminders;
+mod role;
+mod routing;
+
+pub use self::{
+    auto_answer::AutoAnswers,
+    captcha::{Captcha, CaptchaKind},
+    confirm::ConfirmReplies,
+    filter::{ContentFilter, FilterAction, FilterReason, FilterViolation},
+    header::{Header, HeaderKind},
+    hours::BusinessHours,
+    locale::Locales,
+    menu::{Menu, MenuButtons, MenuItem, MenuMedia},
+    metrics::Metrics,
+    rate_limit::RateLimit,
+    rating::Ratings,
+    reminders::Reminders,
+    role::Role,
+    routing::Route,
+};
+
+#[derive(Clone, Deserialize)]
+pub struct Config {
+    pub token: String,
+    pub chat_id: Integer,
+    pub database_url: String,
+    pub webhook_address: Option<SocketAddr>,
+    pub webhook_path: Option<String>,
+    pub health_address: Option<SocketAddr>,
+    pub shutdown_timeout: Option<u64>,
+    pub greeting: Option<String>,
+    pub business_hours: Option<BusinessHours>,
+    pub auto_answers: Option<AutoAnswers>,
+    pub rate_limit: Option<RateLimit>,
+    pub captcha: Option<Captcha>,
+    #[serde(default)]
+    pub filters: Vec<ContentFilter>,
+    pub header: Option<Header>,
+    #[serde(default)]
+    pub signatures: bool,
+    pub internal_prefix: Option<String>,
+    pub confirm_replies: Option<ConfirmReplies>,
+    #[serde(default)]
+    pub routing: Vec<Route>,
+    #[serde(default)]
+    pub roles: HashMap<Integer, Role>,
+    pub default_role: Option<Role>,
+    #[serde(default)]
+    pub strict_assignment: bool,
+    pub reminders: Option<Reminders>,
+    pub ratings: Option<Ratings>,
+    #[serde(default)]
+    pub delivery_receipts: bool,
+    pub menu: Option<Menu>,
+    pub locales: Option<Locales>,
+    pub metrics: Option<Metrics>,
+}
+
+impl Config {
+    pub fn read_from_file<P: AsRef<Path>>(path: P) -> Result<Self, ConfigError> {
+        let path = path.as_ref();
+        let data = read_to_string(path).map_err(ConfigError::Read)?;
+        let mut config: Self = serde_yaml::from_str(&data).map_err(ConfigError::Parse)?;
+        if let Some(ref mut locales) = config.locales {
+            locales.load(path.parent().unwrap_or_else(|| Path::new(".")))?;
+        }
+        Ok(config)
+    }
+
+    pub fn admin_text<'a>(&'a self, key: &str, default: &'a str) -> &'a str {
+        self.locales
+            .as_ref()
+            .and_then(|locales| locales.get_admin(key))
+            .unwrap_or(default)
+    }
+
+    pub fn subscriber_text<'a>(&'a self, language_code: Option<&str>, key: &str, default: &'a str) -> &'a str {
+        self.locales
+            .as_ref()
+            .and_then(|locales| locales.get(language_code, key))
+            .unwrap_or(default)
+    }
+
+    pub fn greeting(&self, language_code: Option<&str>) -> Option<&str> {
+        self.locales
+            .as_ref()
+            .and_then(|locales| locales.get_translation(language_code, "greeting"))
+            .or(self.greeting.as_deref())
+    }
+
+    pub fn menu_description<'a>(&'a self, language_code: Option<&str>, item: &'a MenuItem) -> &'a str {
+        self.subscriber_text(language_code, &format!("menu_{}", item.command()), item.description())
+    }
+
+    /// Returns the answer text or the media caption of a menu item
+    pub fn menu_text<'a>(&'a self, language_code: Option<&str>, item: &'a MenuItem) -> Option<&'a str> {
+        item.text()
+            .map(|text| self.subscriber_text(language_code, &format!("menu_{}_text", item.command()), text))
+    }
+
+    /// Returns languages which have a locale file
+    pub fn language_codes(&self) -> Vec<&str> {
+        self.locales
+            .as_ref()
+            .map(|locales| locales.language_codes())
+            .unwrap_or_default()
+    }
+
+    pub fn admin_chat_ids(&self) -> Vec<Integer> {
+        let mut chat_ids = vec![self.chat_id];
+        for chat_id in self.routing.iter().flat_map(|route| route.chats()) {
+            if !chat_ids.contains(chat_id) {
+                chat_ids.push(*chat_id);
+            }
+        }
+        chat_ids
+    }
+
+    /// Returns the role of an admin, `assigned_role` is a role assigned with /role
+    ///
+    /// A role from the config takes precedence over an assigned one.
+    /// Without any roles in the config every admin is an agent, as it was before roles were added.
+    pub fn get_role(&self, user_id: Integer, assigned_role: Option<Role>) -> Role {
+        self.roles
+            .get(&user_id)
+            .copied()
+            .or(assigned_role)
+            .or(self.default_role)
+            .unwrap_or(if self.roles.is_empty() {
+                Role::Agent
+            } else {
+                Role::Viewer
+            })
+    }
+
+    pub fn route(&self, subscriber: &User, message: Option<&Message>, conversation_id: i32) -> Integer {
+        self.routing
+            .iter()
+            .find_map(|route| route.select(subscriber, message, conversation_id))
+            .unwrap_or(self.chat_id)
+    }
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Parse(YamlError),
+    ParseLocale(PathBuf, YamlError),
+    Read(IoError),
+    ReadLocale(PathBuf, IoError),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, out: &mut fmt::Formatter) -> fmt::Result {
+        use self::ConfigError::*;
+        match self {
+            Parse(err) => write!(out, "failed to parse config: {}", err),
+            ParseLocale(path, err) => write!(out, "failed to parse locale {}: {}", path.display(), err),
+            Read(err) => write!(out, "failed to read config: {}", err),
+            ReadLocale(path, err) => write!(out, "failed to read locale {}: {}", path.display(), err),
+        }
+    }
+}
+
+impl Error for ConfigError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        use self::ConfigError::*;
+        Some(match self {
+            Parse(err) => err,
+            ParseLocale(_, err) => err,
+            Read(err) => err,
+            ReadLocale(_, err) => err,
+        })
+    }
+}
@@ -0,0 +1,50 @@
+use chrono::Duration;
+use serde::Deserialize;
+
+#[derive(Clone, Deserialize)]
+pub struct RateLimit {
+    messages: i32,
+    window: i64,
+    warning: Option<String>,
+    block: Option<RateLimitBlock>,
+    #[serde(default)]
+    persistent: bool,
+}
+
+impl RateLimit {
+    pub fn messages(&self) -> i32 {
+        self.messages
+    }
+
+    pub fn window(&self) -> Duration {
+        Duration::seconds(self.window)
+    }
+
+    pub fn warning(&self) -> Option<&str> {
+        self.warning.as_deref()
+    }
+
+    pub fn block(&self) -> Option<&RateLimitBlock> {
+        self.block.as_ref()
+    }
+
+    pub fn is_persistent(&self) -> bool {
+        self.persistent
+    }
+}
+
+#[derive(Clone, Deserialize)]
+pub struct RateLimitBlock {
+    violations: i32,
+    duration: i64,
+}
+
+impl RateLimitBlock {
+    pub fn violations(&self) -> i32 {
+        self.violations
+    }
+
+    pub fn duration(&self) -> Duration {
+        Duration::seconds(self.duration)
+    }
+}
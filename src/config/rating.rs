@@ -0,0 +1,20 @@
+use serde::Deserialize;
+
+const DEFAULT_TEXT: &str = "How would you rate our support?";
+
+#[derive(Clone, Deserialize)]
+pub struct Ratings {
+    text: Option<String>,
+    #[serde(default)]
+    comment: bool,
+}
+
+impl Ratings {
+    pub fn text(&self) -> &str {
+        self.text.as_deref().unwrap_or(DEFAULT_TEXT)
+    }
+
+    pub fn request_comment(&self) -> bool {
+        self.comment
+    }
+}
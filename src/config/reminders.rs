@@ -0,0 +1,24 @@
+use carapax::types::Integer;
+use chrono::Duration;
+use serde::Deserialize;
+
+#[derive(Clone, Deserialize)]
+pub struct Reminders {
+    remind_after: i64,
+    escalate_after: Option<i64>,
+    escalation_chat_id: Option<Integer>,
+}
+
+impl Reminders {
+    pub fn remind_after(&self) -> Duration {
+        Duration::seconds(self.remind_after)
+    }
+
+    pub fn escalate_after(&self) -> Option<Duration> {
+        self.escalate_after.map(Duration::seconds)
+    }
+
+    pub fn escalation_chat_id(&self) -> Option<Integer> {
+        self.escalation_chat_id
+    }
+}
@@ -0,0 +1,48 @@
+use carapax::types::{Integer, Message, User};
+use serde::Deserialize;
+
+#[derive(Clone, Deserialize)]
+pub struct Route {
+    language: Option<Vec<String>>,
+    tag: Option<String>,
+    keyword: Option<String>,
+    chats: Vec<Integer>,
+}
+
+impl Route {
+    pub fn chats(&self) -> &[Integer] {
+        &self.chats
+    }
+
+    pub fn select(&self, subscriber: &User, message: Option<&Message>, conversation_id: i32) -> Option<Integer> {
+        if !self.is_match(subscriber, message) || self.chats.is_empty() {
+            return None;
+        }
+        // round-robin between chats, a conversation always stays in the same chat
+        let idx = conversation_id.unsigned_abs() as usize % self.chats.len();
+        Some(self.chats[idx])
+    }
+
+    fn is_match(&self, subscriber: &User, message: Option<&Message>) -> bool {
+        let language_match = match (&self.language, &subscriber.language_code) {
+            (Some(languages), Some(code)) => languages.iter().any(|x| x.eq_ignore_ascii_case(code)),
+            (Some(_), None) => false,
+            (None, _) => true,
+        };
+        let text = message.and_then(|x| x.get_text()).map(|x| x.data.to_lowercase());
+        let tag_match = match (&self.tag, &text) {
+            (Some(tag), Some(text)) => {
+                let tag = format!("#{}", tag.trim_start_matches('#').to_lowercase());
+                text.split(|c: char| c.is_whitespace() || c == ',').any(|x| x == tag)
+            }
+            (Some(_), None) => false,
+            (None, _) => true,
+        };
+        let keyword_match = match (&self.keyword, &text) {
+            (Some(keyword), Some(text)) => text.contains(&keyword.to_lowercase()),
+            (Some(_), None) => false,
+            (None, _) => true,
+        };
+        language_match && tag_match && keyword_match
+    }
+}
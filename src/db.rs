@@ -0,0 +1,48 @@
+use crate::metrics;
+use std::{future::Future, time::Instant};
+use tokio_postgres::{types::ToSql, Client as PgClient, Error, Row, ToStatement};
+
+pub struct Client {
+    client: PgClient,
+}
+
+impl Client {
+    pub fn new(client: PgClient) -> Self {
+        Self { client }
+    }
+
+    pub async fn execute<T>(&self, statement: &T, params: &[&(dyn ToSql + Sync)]) -> Result<u64, Error>
+    where
+        T: ?Sized + ToStatement,
+    {
+        observe(self.client.execute(statement, params)).await
+    }
+
+    pub async fn query<T>(&self, statement: &T, params: &[&(dyn ToSql + Sync)]) -> Result<Vec<Row>, Error>
+    where
+        T: ?Sized + ToStatement,
+    {
+        observe(self.client.query(statement, params)).await
+    }
+
+    pub async fn query_one<T>(&self, statement: &T, params: &[&(dyn ToSql + Sync)]) -> Result<Row, Error>
+    where
+        T: ?Sized + ToStatement,
+    {
+        observe(self.client.query_one(statement, params)).await
+    }
+
+    pub async fn query_opt<T>(&self, statement: &T, params: &[&(dyn ToSql + Sync)]) -> Result<Option<Row>, Error>
+    where
+        T: ?Sized + ToStatement,
+    {
+        observe(self.client.query_opt(statement, params)).await
+    }
+}
+
+async fn observe<T>(query: impl Future<Output = Result<T, Error>>) -> Result<T, Error> {
+    let started_at = Instant::now();
+    let result = query.await;
+    metrics::observe_db_query(started_at.elapsed(), result.is_err());
+    result
+}
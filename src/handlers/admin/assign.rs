@@ -0,0 +1,240 @@
+use crate::{
+    api::Api,
+    config::{Config, Role},
+    handlers::admin::{
+        message::{find_link, MessageError},
+        role::{Sender, SenderError},
+    },
+    metrics::ErrorVariant,
+    services::{
+        AdminService, AdminServiceError, ConversationService, ConversationServiceError, MessageLinkService,
+        UserService, UserServiceError,
+    },
+};
+use carapax::{
+    methods::{GetChatMember, SendMessage},
+    types::{Command, Integer, Message, TextEntity, User},
+    ExecuteError, Ref,
+};
+use std::{error::Error, fmt};
+
+const MESSAGE_ASSIGNED_TO: &str = "Assigned to {name}";
+const MESSAGE_NO_LINK: &str = "Reply to a subscriber message to assign the conversation";
+const MESSAGE_UNASSIGNED: &str = "Conversation is unassigned";
+const MESSAGE_UNKNOWN_ADMIN: &str =
+    "Unknown admin, they should be a member of the admin chat and send a message there first";
+
+#[allow(clippy::too_many_arguments)]
+pub async fn handle_assign(
+    api: Ref<Api>,
+    config: Ref<Config>,
+    admin_service: Ref<AdminService>,
+    conversation_service: Ref<ConversationService>,
+    message_link_service: Ref<MessageLinkService>,
+    user_service: Ref<UserService>,
+    sender: Sender,
+    command: Command,
+) -> Result<(), AssignError> {
+    let message = command.get_message();
+    if !sender
+        .require(Role::Supervisor, "assign conversations", message)
+        .await
+        .map_err(AssignError::CheckRole)?
+    {
+        return Ok(());
+    }
+    let assignee = match command.get_args().first() {
+        Some(arg) => match find_admin(&user_service, message, arg).await? {
+            Some(user) if is_admin(&api, &config, &admin_service, user.id).await? => Some(user),
+            _ => {
+                return send_reply(
+                    &api,
+                    message,
+                    config.admin_text("assign_unknown_admin", MESSAGE_UNKNOWN_ADMIN),
+                )
+                .await
+            }
+        },
+        None => None,
+    };
+    let text = assign(
+        &config,
+        &conversation_service,
+        &message_link_service,
+        message,
+        assignee.as_ref(),
+    )
+    .await?;
+    send_reply(&api, message, &text).await
+}
+
+pub async fn handle_take(
+    api: Ref<Api>,
+    config: Ref<Config>,
+    conversation_service: Ref<ConversationService>,
+    message_link_service: Ref<MessageLinkService>,
+    sender: Sender,
+    command: Command,
+) -> Result<(), AssignError> {
+    let message = command.get_message();
+    if !sender
+        .require(Role::Agent, "take conversations", message)
+        .await
+        .map_err(AssignError::CheckRole)?
+    {
+        return Ok(());
+    }
+    let text = assign(
+        &config,
+        &conversation_service,
+        &message_link_service,
+        message,
+        message.get_user(),
+    )
+    .await?;
+    send_reply(&api, message, &text).await
+}
+
+async fn find_admin(user_service: &UserService, message: &Message, arg: &str) -> Result<Option<User>, AssignError> {
+    // admins without username can be mentioned by name
+    let mentioned = message.get_text().and_then(|text| {
+        text.entities.as_ref().and_then(|entities| {
+            entities.iter().find_map(|entity| match entity {
+                TextEntity::TextMention { user, .. } => Some(user.clone()),
+                _ => None,
+            })
+        })
+    });
+    if mentioned.is_some() {
+        return Ok(mentioned);
+    }
+    match arg.strip_prefix('@') {
+        Some(username) => user_service
+            .find_by_username(username)
+            .await
+            .map_err(AssignError::FindAdmin),
+        None => match arg.parse::<Integer>() {
+            Ok(user_id) => user_service.get(user_id).await.map_err(AssignError::FindAdmin),
+            Err(_) => Ok(None),
+        },
+    }
+}
+
+async fn is_admin(
+    api: &Api,
+    config: &Config,
+    admin_service: &AdminService,
+    user_id: Integer,
+) -> Result<bool, AssignError> {
+    if config.roles.contains_key(&user_id)
+        || admin_service
+            .get_role(user_id)
+            .await
+            .map_err(AssignError::GetRole)?
+            .is_some()
+    {
+        return Ok(true);
+    }
+    for chat_id in config.admin_chat_ids() {
+        match api.execute(GetChatMember::new(chat_id, user_id)).await {
+            Ok(member) if member.is_member() => return Ok(true),
+            // the user has never been in the chat
+            Ok(_) | Err(ExecuteError::Response(_)) => {}
+            Err(err) => return Err(AssignError::GetChatMember(err)),
+        }
+    }
+    Ok(false)
+}
+
+async fn assign(
+    config: &Config,
+    conversation_service: &ConversationService,
+    message_link_service: &MessageLinkService,
+    message: &Message,
+    assignee: Option<&User>,
+) -> Result<String, AssignError> {
+    let link = match find_link(message_link_service, message)
+        .await
+        .map_err(AssignError::FindLink)?
+    {
+        Some(link) => link,
+        None => return Ok(String::from(config.admin_text("assign_no_link", MESSAGE_NO_LINK))),
+    };
+    let conversation_id = conversation_service
+        .get_or_create(link.subscriber_user_id())
+        .await
+        .map_err(AssignError::SetAssignee)?;
+    conversation_service
+        .set_assignee(conversation_id, assignee.map(|user| user.id))
+        .await
+        .map_err(AssignError::SetAssignee)?;
+    Ok(match assignee {
+        Some(user) => config
+            .admin_text("assign_assigned", MESSAGE_ASSIGNED_TO)
+            .replace("{name}", &user.get_full_name()),
+        None => String::from(config.admin_text("assign_unassigned", MESSAGE_UNASSIGNED)),
+    })
+}
+
+async fn send_reply(api: &Api, message: &Message, text: &str) -> Result<(), AssignError> {
+    api.execute(SendMessage::new(message.get_chat_id(), text).reply_to_message_id(message.id))
+        .await
+        .map_err(AssignError::SendMessage)?;
+    Ok(())
+}
+
+#[derive(Debug)]
+pub enum AssignError {
+    CheckRole(SenderError),
+    FindAdmin(UserServiceError),
+    FindLink(MessageError),
+    GetChatMember(ExecuteError),
+    GetRole(AdminServiceError),
+    SendMessage(ExecuteError),
+    SetAssignee(ConversationServiceError),
+}
+
+impl fmt::Display for AssignError {
+    fn fmt(&self, out: &mut fmt::Formatter) -> fmt::Result {
+        use self::AssignError::*;
+        match self {
+            CheckRole(err) => err.fmt(out),
+            FindAdmin(err) => err.fmt(out),
+            FindLink(err) => err.fmt(out),
+            GetChatMember(err) => err.fmt(out),
+            GetRole(err) => err.fmt(out),
+            SendMessage(err) => err.fmt(out),
+            SetAssignee(err) => err.fmt(out),
+        }
+    }
+}
+
+impl Error for AssignError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        use self::AssignError::*;
+        Some(match self {
+            CheckRole(err) => err,
+            FindAdmin(err) => err,
+            FindLink(err) => err,
+            GetChatMember(err) => err,
+            GetRole(err) => err,
+            SendMessage(err) => err,
+            SetAssignee(err) => err,
+        })
+    }
+}
+
+impl ErrorVariant for AssignError {
+    fn variant(&self) -> &'static str {
+        use self::AssignError::*;
+        match self {
+            CheckRole(_) => "CheckRole",
+            FindAdmin(_) => "FindAdmin",
+            FindLink(_) => "FindLink",
+            GetChatMember(_) => "GetChatMember",
+            GetRole(_) => "GetRole",
+            SendMessage(_) => "SendMessage",
+            SetAssignee(_) => "SetAssignee",
+        }
+    }
+}
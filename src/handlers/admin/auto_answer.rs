@@ -0,0 +1,113 @@
+use crate::{
+    api::Api,
+    config::{Config, Role},
+    handlers::admin::role::{Sender, SenderError},
+    metrics::ErrorVariant,
+    services::{SettingsService, SettingsServiceError, SETTING_AUTO_ANSWERS_ENABLED},
+};
+use carapax::{
+    methods::SendMessage,
+    types::{ChatId, Command},
+    ExecuteError, Ref,
+};
+use std::{error::Error, fmt};
+
+const MESSAGE_NOT_CONFIGURED: &str = "Auto-answers are not configured";
+const MESSAGE_ENABLED: &str = "Auto-answers are enabled";
+const MESSAGE_DISABLED: &str = "Auto-answers are disabled";
+const MESSAGE_USAGE: &str = "Usage: /autoanswer [on|off]";
+
+pub async fn handle(
+    api: Ref<Api>,
+    config: Ref<Config>,
+    settings_service: Ref<SettingsService>,
+    sender: Sender,
+    chat_id: ChatId,
+    command: Command,
+) -> Result<(), AutoAnswerError> {
+    if !sender
+        .require(Role::Supervisor, "toggle auto-answers", command.get_message())
+        .await
+        .map_err(AutoAnswerError::CheckRole)?
+    {
+        return Ok(());
+    }
+    let message_id = command.get_message().id;
+    let text = match config.auto_answers {
+        Some(ref auto_answers) => match command.get_args().first().map(String::as_str) {
+            Some(value @ ("on" | "off")) => {
+                let is_enabled = value == "on";
+                settings_service
+                    .set(SETTING_AUTO_ANSWERS_ENABLED, is_enabled)
+                    .await
+                    .map_err(AutoAnswerError::SetSettings)?;
+                get_status_message(&config, is_enabled)
+            }
+            Some(_) => config.admin_text("auto_answer_usage", MESSAGE_USAGE),
+            None => get_status_message(
+                &config,
+                settings_service
+                    .get_bool(SETTING_AUTO_ANSWERS_ENABLED, auto_answers.is_enabled())
+                    .await
+                    .map_err(AutoAnswerError::GetSettings)?,
+            ),
+        },
+        None => config.admin_text("auto_answer_not_configured", MESSAGE_NOT_CONFIGURED),
+    };
+    api.execute(SendMessage::new(chat_id, text).reply_to_message_id(message_id))
+        .await
+        .map_err(AutoAnswerError::SendMessage)?;
+    Ok(())
+}
+
+fn get_status_message(config: &Config, is_enabled: bool) -> &str {
+    if is_enabled {
+        config.admin_text("auto_answer_enabled", MESSAGE_ENABLED)
+    } else {
+        config.admin_text("auto_answer_disabled", MESSAGE_DISABLED)
+    }
+}
+
+#[derive(Debug)]
+pub enum AutoAnswerError {
+    CheckRole(SenderError),
+    GetSettings(SettingsServiceError),
+    SendMessage(ExecuteError),
+    SetSettings(SettingsServiceError),
+}
+
+impl fmt::Display for AutoAnswerError {
+    fn fmt(&self, out: &mut fmt::Formatter) -> fmt::Result {
+        use self::AutoAnswerError::*;
+        match self {
+            CheckRole(err) => err.fmt(out),
+            GetSettings(err) => err.fmt(out),
+            SendMessage(err) => err.fmt(out),
+            SetSettings(err) => err.fmt(out),
+        }
+    }
+}
+
+impl Error for AutoAnswerError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        use self::AutoAnswerError::*;
+        Some(match self {
+            CheckRole(err) => err,
+            GetSettings(err) => err,
+            SendMessage(err) => err,
+            SetSettings(err) => err,
+        })
+    }
+}
+
+impl ErrorVariant for AutoAnswerError {
+    fn variant(&self) -> &'static str {
+        use self::AutoAnswerError::*;
+        match self {
+            CheckRole(_) => "CheckRole",
+            GetSettings(_) => "GetSettings",
+            SendMessage(_) => "SendMessage",
+            SetSettings(_) => "SetSettings",
+        }
+    }
+}
@@ -1,24 +1,37 @@
-use crate::services::{
-    MessageLinkDirection, MessageLinkService, MessageLinkServiceError, UserService, UserServiceError,
+use crate::{
+    api::Api,
+    config::{Config, Role},
+    handlers::admin::{
+        role::{Sender, SenderError},
+        MESSAGE_NOT_FOUND, MESSAGE_OK,
+    },
+    metrics::ErrorVariant,
+    services::{MessageLinkDirection, MessageLinkService, MessageLinkServiceError, UserService, UserServiceError},
 };
 use carapax::{
     methods::SendMessage,
     types::{ChatId, Message},
-    Api, ExecuteError, Ref,
+    ExecuteError, Ref,
 };
 use futures_util::future::OptionFuture;
 use std::{error::Error, fmt};
 
-const MESSAGE_OK: &str = "OK";
-const MESSAGE_NOT_FOUND: &str = "Not found";
-
 pub async fn handle(
     api: Ref<Api>,
+    config: Ref<Config>,
     message_link_service: Ref<MessageLinkService>,
     user_service: Ref<UserService>,
+    sender: Sender,
     chat_id: ChatId,
     message: Message,
 ) -> Result<(), BlockError> {
+    if !sender
+        .require(Role::Supervisor, "block users", &message)
+        .await
+        .map_err(BlockError::CheckRole)?
+    {
+        return Ok(());
+    }
     let link =
         OptionFuture::from(message.reply_to.map(|reply_to| {
             message_link_service.find(reply_to.get_chat_id(), reply_to.id, MessageLinkDirection::Admin)
@@ -34,12 +47,12 @@ pub async fn handle(
                 .await
                 .map_err(BlockError::SetBlock)?
             {
-                MESSAGE_OK
+                config.admin_text("ok", MESSAGE_OK)
             } else {
-                MESSAGE_NOT_FOUND
+                config.admin_text("not_found", MESSAGE_NOT_FOUND)
             }
         }
-        None => MESSAGE_NOT_FOUND,
+        None => config.admin_text("not_found", MESSAGE_NOT_FOUND),
     };
     api.execute(SendMessage::new(chat_id, text).reply_to_message_id(message.id))
         .await
@@ -49,6 +62,7 @@ pub async fn handle(
 
 #[derive(Debug)]
 pub enum BlockError {
+    CheckRole(SenderError),
     GetLink(MessageLinkServiceError),
     SendMessage(ExecuteError),
     SetBlock(UserServiceError),
@@ -58,6 +72,7 @@ impl fmt::Display for BlockError {
     fn fmt(&self, out: &mut fmt::Formatter) -> fmt::Result {
         use self::BlockError::*;
         match self {
+            CheckRole(err) => err.fmt(out),
             GetLink(err) => err.fmt(out),
             SendMessage(err) => err.fmt(out),
             SetBlock(err) => err.fmt(out),
@@ -69,9 +84,22 @@ impl Error for BlockError {
     fn source(&self) -> Option<&(dyn Error + 'static)> {
         use self::BlockError::*;
         Some(match self {
+            CheckRole(err) => err,
             GetLink(err) => err,
             SendMessage(err) => err,
             SetBlock(err) => err,
         })
     }
 }
+
+impl ErrorVariant for BlockError {
+    fn variant(&self) -> &'static str {
+        use self::BlockError::*;
+        match self {
+            CheckRole(_) => "CheckRole",
+            GetLink(_) => "GetLink",
+            SendMessage(_) => "SendMessage",
+            SetBlock(_) => "SetBlock",
+        }
+    }
+}
@@ -0,0 +1,159 @@
+use crate::{
+    api::Api,
+    config::{Config, Role},
+    handlers::{
+        admin::{
+            message::{check_assignee, find_link, MessageError, MESSAGE_ASSIGNED},
+            role::{Sender, SenderError},
+        },
+        subscriber::request_rating,
+    },
+    metrics::ErrorVariant,
+    services::{ConversationService, ConversationServiceError, MessageLinkService, Rating, RatingService, UserService},
+};
+use carapax::{
+    methods::SendMessage,
+    types::{ChatId, Command},
+    ExecuteError, Ref,
+};
+use std::{error::Error, fmt};
+
+const MESSAGE_CLOSED: &str = "Conversation closed";
+const MESSAGE_CLOSED_RATING: &str = "Conversation closed, rating requested";
+const MESSAGE_CLOSED_RATING_FAILED: &str = "Conversation closed, could not request rating";
+const MESSAGE_NOT_OPEN: &str = "There is no open conversation with this subscriber";
+const MESSAGE_USAGE: &str = "Reply to a subscriber message with /close to close the conversation";
+
+#[allow(clippy::too_many_arguments)]
+pub async fn handle(
+    api: Ref<Api>,
+    config: Ref<Config>,
+    conversation_service: Ref<ConversationService>,
+    message_link_service: Ref<MessageLinkService>,
+    rating_service: Ref<RatingService>,
+    user_service: Ref<UserService>,
+    sender: Sender,
+    chat_id: ChatId,
+    command: Command,
+) -> Result<(), CloseError> {
+    let message = command.get_message();
+    if !sender
+        .require(Role::Agent, "close conversations", message)
+        .await
+        .map_err(CloseError::CheckRole)?
+    {
+        return Ok(());
+    }
+    let link = find_link(&message_link_service, message)
+        .await
+        .map_err(CloseError::FindLink)?;
+    let text = match link {
+        Some(link) => {
+            if !check_assignee(&config, &conversation_service, &sender, link.subscriber_user_id())
+                .await
+                .map_err(CloseError::CheckAssignee)?
+            {
+                config.admin_text("assigned", MESSAGE_ASSIGNED)
+            } else {
+                match conversation_service
+                    .close(link.subscriber_user_id(), sender.user_id())
+                    .await
+                    .map_err(CloseError::Close)?
+                {
+                    Some(conversation) => match config.ratings {
+                        Some(ref ratings) => {
+                            let rating = Rating::new(
+                                conversation.id(),
+                                link.subscriber_user_id(),
+                                conversation
+                                    .assignee_user_id()
+                                    .or_else(|| conversation.answered_by_user_id()),
+                                conversation.admin_chat_id().unwrap_or(link.admin_chat_id()),
+                            );
+                            // the conversation is closed already, a missing prompt must not hide it
+                            let subscriber = match user_service.get(link.subscriber_user_id()).await {
+                                Ok(subscriber) => subscriber,
+                                Err(err) => {
+                                    log::error!("Could not get subscriber to request rating: {}", err);
+                                    None
+                                }
+                            };
+                            match request_rating(
+                                &api,
+                                &config,
+                                &rating_service,
+                                ratings,
+                                subscriber.as_ref(),
+                                link.subscriber_chat_id(),
+                                rating,
+                            )
+                            .await
+                            {
+                                Ok(()) => config.admin_text("close_closed_rating", MESSAGE_CLOSED_RATING),
+                                Err(err) => {
+                                    log::error!("Could not request rating: {}", err);
+                                    config.admin_text("close_closed_rating_failed", MESSAGE_CLOSED_RATING_FAILED)
+                                }
+                            }
+                        }
+                        None => config.admin_text("close_closed", MESSAGE_CLOSED),
+                    },
+                    None => config.admin_text("close_not_open", MESSAGE_NOT_OPEN),
+                }
+            }
+        }
+        None => config.admin_text("close_usage", MESSAGE_USAGE),
+    };
+    api.execute(SendMessage::new(chat_id, text).reply_to_message_id(message.id))
+        .await
+        .map_err(CloseError::SendMessage)?;
+    Ok(())
+}
+
+#[derive(Debug)]
+pub enum CloseError {
+    CheckAssignee(MessageError),
+    CheckRole(SenderError),
+    Close(ConversationServiceError),
+    FindLink(MessageError),
+    SendMessage(ExecuteError),
+}
+
+impl fmt::Display for CloseError {
+    fn fmt(&self, out: &mut fmt::Formatter) -> fmt::Result {
+        use self::CloseError::*;
+        match self {
+            CheckAssignee(err) => err.fmt(out),
+            CheckRole(err) => err.fmt(out),
+            Close(err) => err.fmt(out),
+            FindLink(err) => err.fmt(out),
+            SendMessage(err) => err.fmt(out),
+        }
+    }
+}
+
+impl Error for CloseError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        use self::CloseError::*;
+        Some(match self {
+            CheckAssignee(err) => err,
+            CheckRole(err) => err,
+            Close(err) => err,
+            FindLink(err) => err,
+            SendMessage(err) => err,
+        })
+    }
+}
+
+impl ErrorVariant for CloseError {
+    fn variant(&self) -> &'static str {
+        use self::CloseError::*;
+        match self {
+            CheckAssignee(_) => "CheckAssignee",
+            CheckRole(_) => "CheckRole",
+            Close(_) => "Close",
+            FindLink(_) => "FindLink",
+            SendMessage(_) => "SendMessage",
+        }
+    }
+}
@@ -0,0 +1,403 @@
+use crate::{
+    api::Api,
+    config::{Config, Role},
+    handlers::{
+        admin::{
+            message::{check_assignee, send_reply, MessageError, Reply, ReplyTarget, MESSAGE_ASSIGNED},
+            role::{Sender, SenderError, MESSAGE_PERMISSION_DENIED},
+            MESSAGE_REPLY_EXPIRED,
+        },
+        receipt::{report_delivery, ReceiptError},
+    },
+    metrics::ErrorVariant,
+    services::{
+        AdminService, ConversationService, ConversationServiceError, MessageLink, MessageLinkDirection,
+        MessageLinkService, MessageLinkServiceError, PendingReply, PendingReplyService, PendingReplyServiceError,
+    },
+};
+use carapax::{
+    methods::{AnswerCallbackQuery, EditMessageText},
+    types::{CallbackQuery, CallbackQueryError, InlineKeyboardButton, InlineKeyboardError, Integer, Message},
+    ExecuteError, HandlerInput, Ref, TryFromInput,
+};
+use futures_util::future::BoxFuture;
+use serde::{Deserialize, Serialize};
+use std::{error::Error, fmt};
+
+pub const MESSAGE_PREVIEW: &str = "Send this reply to the subscriber?";
+const MESSAGE_CANCELLED: &str = "Reply cancelled";
+const MESSAGE_NOT_FOUND: &str = "Reply has already been handled";
+const MESSAGE_NO_LINK: &str = "Could not find the subscriber message to reply to";
+const MESSAGE_SENT: &str = "Reply sent";
+const BUTTON_SEND: &str = "✅ Send";
+const BUTTON_CANCEL: &str = "❌ Cancel";
+
+pub fn build_keyboard(config: &Config, reply: i32) -> Result<Vec<Vec<InlineKeyboardButton>>, InlineKeyboardError> {
+    Ok(vec![vec![
+        InlineKeyboardButton::with_callback_data_struct(
+            config.admin_text("confirm_button_send", BUTTON_SEND),
+            &ConfirmAnswer { reply, send: true },
+        )?,
+        InlineKeyboardButton::with_callback_data_struct(
+            config.admin_text("confirm_button_cancel", BUTTON_CANCEL),
+            &ConfirmAnswer { reply, send: false },
+        )?,
+    ]])
+}
+
+#[allow(clippy::too_many_arguments)]
+pub async fn handle(
+    api: Ref<Api>,
+    config: Ref<Config>,
+    admin_service: Ref<AdminService>,
+    conversation_service: Ref<ConversationService>,
+    message_link_service: Ref<MessageLinkService>,
+    pending_reply_service: Ref<PendingReplyService>,
+    sender: Sender,
+    query: ConfirmQuery,
+) -> Result<(), ConfirmError> {
+    if !sender
+        .is_allowed(Role::Agent, "confirm replies")
+        .await
+        .map_err(ConfirmError::CheckRole)?
+    {
+        api.execute(
+            AnswerCallbackQuery::new(query.id).text(config.admin_text("permission_denied", MESSAGE_PERMISSION_DENIED)),
+        )
+        .await
+        .map_err(ConfirmError::AnswerCallbackQuery)?;
+        return Ok(());
+    }
+    let pending_reply = match pending_reply_service
+        .take(query.reply)
+        .await
+        .map_err(ConfirmError::TakePendingReply)?
+    {
+        Some(pending_reply) => pending_reply,
+        None => {
+            api.execute(
+                AnswerCallbackQuery::new(query.id).text(config.admin_text("confirm_not_found", MESSAGE_NOT_FOUND)),
+            )
+            .await
+            .map_err(ConfirmError::AnswerCallbackQuery)?;
+            return Ok(());
+        }
+    };
+    // only the author of a reply and supervisors can handle it
+    let is_author = pending_reply.admin_user_id() == Some(sender.user_id());
+    if !is_author
+        && !sender
+            .is_allowed(Role::Supervisor, "confirm replies of other admins")
+            .await
+            .map_err(ConfirmError::CheckRole)?
+    {
+        return reject(
+            &api,
+            &pending_reply_service,
+            pending_reply,
+            query.id,
+            config.admin_text("permission_denied", MESSAGE_PERMISSION_DENIED),
+        )
+        .await;
+    }
+    let link = if query.send && !pending_reply.is_expired() {
+        message_link_service
+            .find(
+                pending_reply.chat_id(),
+                pending_reply.reply_to_message_id(),
+                MessageLinkDirection::Admin,
+            )
+            .await
+            .map_err(ConfirmError::FindLink)?
+    } else {
+        None
+    };
+    // the conversation could have been assigned to another admin since the reply was written
+    if let Some(ref link) = link {
+        if !check_assignee(&config, &conversation_service, &sender, link.subscriber_user_id())
+            .await
+            .map_err(ConfirmError::CheckAssignee)?
+        {
+            return reject(
+                &api,
+                &pending_reply_service,
+                pending_reply,
+                query.id,
+                config.admin_text("assigned", MESSAGE_ASSIGNED),
+            )
+            .await;
+        }
+    }
+    api.execute(AnswerCallbackQuery::new(query.id))
+        .await
+        .map_err(ConfirmError::AnswerCallbackQuery)?;
+    let (chat_id, message_id) = (pending_reply.chat_id(), pending_reply.message_id());
+    let text = if pending_reply.is_expired() {
+        config.admin_text("confirm_expired", MESSAGE_REPLY_EXPIRED)
+    } else if !query.send {
+        config.admin_text("confirm_cancelled", MESSAGE_CANCELLED)
+    } else {
+        match link {
+            Some(link) => {
+                let result = send_pending_reply(
+                    &api,
+                    &config,
+                    &admin_service,
+                    &message_link_service,
+                    &link,
+                    &pending_reply,
+                    query.original.as_ref(),
+                )
+                .await;
+                match result {
+                    // the reply has been sent, only the link is missing
+                    Ok(()) | Err(MessageError::CreateLink(_)) => {}
+                    Err(_) => pending_reply_service
+                        .restore(pending_reply)
+                        .await
+                        .map_err(ConfirmError::RestorePendingReply)?,
+                }
+                report_delivery(
+                    &api,
+                    &config,
+                    &conversation_service,
+                    &message_link_service,
+                    &link,
+                    (chat_id, message_id),
+                    &result,
+                )
+                .await
+                .map_err(ConfirmError::Receipt)?;
+                result.map_err(ConfirmError::Reply)?;
+                let author = query
+                    .original
+                    .as_ref()
+                    .filter(|original| original.id == message_id)
+                    .and_then(Message::get_user_id);
+                conversation_service
+                    .mark_answered(link.subscriber_user_id(), author)
+                    .await
+                    .map_err(ConfirmError::MarkAnswered)?;
+                config.admin_text("confirm_sent", MESSAGE_SENT)
+            }
+            None => config.admin_text("confirm_no_link", MESSAGE_NO_LINK),
+        }
+    };
+    api.execute(EditMessageText::new(query.chat_id, query.message_id, text))
+        .await
+        .map_err(ConfirmError::EditMessage)?;
+    Ok(())
+}
+
+/// Puts a reply back, so that it can be handled by another admin
+async fn reject(
+    api: &Api,
+    pending_reply_service: &PendingReplyService,
+    pending_reply: PendingReply,
+    query_id: String,
+    text: &str,
+) -> Result<(), ConfirmError> {
+    pending_reply_service
+        .restore(pending_reply)
+        .await
+        .map_err(ConfirmError::RestorePendingReply)?;
+    api.execute(AnswerCallbackQuery::new(query_id).text(text))
+        .await
+        .map_err(ConfirmError::AnswerCallbackQuery)?;
+    Ok(())
+}
+
+async fn send_pending_reply(
+    api: &Api,
+    config: &Config,
+    admin_service: &AdminService,
+    message_link_service: &MessageLinkService,
+    link: &MessageLink,
+    pending_reply: &PendingReply,
+    original: Option<&Message>,
+) -> Result<(), MessageError> {
+    // the preview is a reply to the admin message, it is missing when the message has been deleted
+    let original = original.filter(|original| original.id == pending_reply.message_id());
+    let (chat_id, message_id) = (pending_reply.chat_id(), pending_reply.message_id());
+    let reply = match (
+        pending_reply.parts(),
+        pending_reply.part_message_ids(),
+        pending_reply.source(),
+    ) {
+        (Some(parts), _, _) => Reply::MediaGroup(parts),
+        (None, Some(message_ids), _) => Reply::StoredMediaGroup { chat_id, message_ids },
+        (None, None, Some((source_chat_id, source_message_id))) => Reply::Stored {
+            chat_id: source_chat_id,
+            message_id: source_message_id,
+            admin_chat_id: chat_id,
+            admin_message_id: message_id,
+        },
+        (None, None, None) => match original {
+            Some(original) => Reply::Message(original),
+            None => Reply::Stored {
+                chat_id,
+                message_id,
+                admin_chat_id: chat_id,
+                admin_message_id: message_id,
+            },
+        },
+    };
+    send_reply(
+        api,
+        config,
+        admin_service,
+        message_link_service,
+        &ReplyTarget::from(link),
+        reply,
+        original.and_then(Message::get_user_id),
+    )
+    .await
+}
+
+#[derive(Serialize, Deserialize)]
+struct ConfirmAnswer {
+    reply: i32,
+    send: bool,
+}
+
+pub struct ConfirmQuery {
+    id: String,
+    chat_id: Integer,
+    message_id: Integer,
+    original: Option<Message>,
+    reply: i32,
+    send: bool,
+}
+
+impl TryFrom<CallbackQuery> for ConfirmQuery {
+    type Error = ConfirmQueryError;
+
+    fn try_from(query: CallbackQuery) -> Result<Self, Self::Error> {
+        let ConfirmAnswer { reply, send } = query
+            .parse_data()
+            .map_err(ConfirmQueryError::ParseData)
+            .and_then(|data: Option<ConfirmAnswer>| data.ok_or(ConfirmQueryError::NoData))?;
+        let message = query.message.ok_or(ConfirmQueryError::NoMessage)?;
+        Ok(Self {
+            id: query.id,
+            chat_id: message.get_chat_id(),
+            message_id: message.id,
+            original: message.reply_to.map(|x| *x),
+            reply,
+            send,
+        })
+    }
+}
+
+#[derive(Debug)]
+pub enum ConfirmQueryError {
+    NoData,
+    NoMessage,
+    ParseData(CallbackQueryError),
+}
+
+impl fmt::Display for ConfirmQueryError {
+    fn fmt(&self, out: &mut fmt::Formatter) -> fmt::Result {
+        use self::ConfirmQueryError::*;
+        match self {
+            NoData => write!(out, "callback query has no data"),
+            NoMessage => write!(out, "callback query has no message"),
+            ParseData(err) => write!(out, "could not parse query data: {}", err),
+        }
+    }
+}
+
+impl Error for ConfirmQueryError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        use self::ConfirmQueryError::*;
+        match self {
+            NoData => None,
+            NoMessage => None,
+            ParseData(err) => Some(err),
+        }
+    }
+}
+
+impl TryFromInput for ConfirmQuery {
+    type Error = ConfirmQueryError;
+
+    type Future = BoxFuture<'static, Result<Option<Self>, Self::Error>>;
+
+    fn try_from_input(input: HandlerInput) -> Self::Future {
+        Box::pin(async move {
+            Ok(CallbackQuery::try_from_input(input)
+                .await
+                .ok()
+                .flatten()
+                .and_then(|query| ConfirmQuery::try_from(query).ok()))
+        })
+    }
+}
+
+#[derive(Debug)]
+pub enum ConfirmError {
+    AnswerCallbackQuery(ExecuteError),
+    CheckAssignee(MessageError),
+    CheckRole(SenderError),
+    EditMessage(ExecuteError),
+    FindLink(MessageLinkServiceError),
+    MarkAnswered(ConversationServiceError),
+    Receipt(ReceiptError),
+    Reply(MessageError),
+    RestorePendingReply(PendingReplyServiceError),
+    TakePendingReply(PendingReplyServiceError),
+}
+
+impl fmt::Display for ConfirmError {
+    fn fmt(&self, out: &mut fmt::Formatter) -> fmt::Result {
+        use self::ConfirmError::*;
+        match self {
+            AnswerCallbackQuery(err) => err.fmt(out),
+            CheckAssignee(err) => err.fmt(out),
+            CheckRole(err) => err.fmt(out),
+            EditMessage(err) => err.fmt(out),
+            FindLink(err) => err.fmt(out),
+            MarkAnswered(err) => err.fmt(out),
+            Receipt(err) => err.fmt(out),
+            Reply(err) => err.fmt(out),
+            RestorePendingReply(err) => err.fmt(out),
+            TakePendingReply(err) => err.fmt(out),
+        }
+    }
+}
+
+impl Error for ConfirmError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        use self::ConfirmError::*;
+        Some(match self {
+            AnswerCallbackQuery(err) => err,
+            CheckAssignee(err) => err,
+            CheckRole(err) => err,
+            EditMessage(err) => err,
+            FindLink(err) => err,
+            MarkAnswered(err) => err,
+            Receipt(err) => err,
+            Reply(err) => err,
+            RestorePendingReply(err) => err,
+            TakePendingReply(err) => err,
+        })
+    }
+}
+
+impl ErrorVariant for ConfirmError {
+    fn variant(&self) -> &'static str {
+        use self::ConfirmError::*;
+        match self {
+            AnswerCallbackQuery(_) => "AnswerCallbackQuery",
+            CheckAssignee(_) => "CheckAssignee",
+            CheckRole(_) => "CheckRole",
+            EditMessage(_) => "EditMessage",
+            FindLink(_) => "FindLink",
+            MarkAnswered(_) => "MarkAnswered",
+            Receipt(_) => "Receipt",
+            Reply(_) => "Reply",
+            RestorePendingReply(_) => "RestorePendingReply",
+            TakePendingReply(_) => "TakePendingReply",
+        }
+    }
+}
@@ -0,0 +1,153 @@
+use crate::{
+    api::Api,
+    config::{Config, Role},
+    handlers::{
+        admin::{
+            role::{Sender, SenderError},
+            MESSAGE_OK,
+        },
+        subscriber::{send_greeting, GreetingError},
+    },
+    metrics::ErrorVariant,
+    services::{is_valid_source, Greeting, GreetingService, GreetingServiceError, DEFAULT_GREETING},
+};
+use carapax::{methods::SendMessage, types::Command, ExecuteError, Ref};
+use std::{error::Error, fmt};
+
+const MESSAGE_EMPTY: &str = "Greeting is not set";
+const MESSAGE_NOT_SET: &str = "Greeting is not set in the database, the config value is used";
+const MESSAGE_RESET: &str = "Greeting has been reset to the config value";
+const MESSAGE_USAGE: &str =
+    "Reply to a message with /setgreeting [source] to use it as the greeting, or /setgreeting reset [source]";
+
+pub async fn handle_preview(
+    api: Ref<Api>,
+    config: Ref<Config>,
+    greeting_service: Ref<GreetingService>,
+    command: Command,
+) -> Result<(), GreetingCommandError> {
+    let message = command.get_message();
+    let source = command.get_args().first().map(String::as_str);
+    // preview the greeting in the language of the admin, as a subscriber with the same language gets it
+    let language_code = message.get_user().and_then(|user| user.language_code.as_deref());
+    if !send_greeting(
+        &api,
+        &config,
+        &greeting_service,
+        message.get_chat_id(),
+        language_code,
+        source,
+        false,
+    )
+    .await
+    .map_err(GreetingCommandError::SendGreeting)?
+    {
+        send_reply(&api, &command, config.admin_text("greeting_empty", MESSAGE_EMPTY)).await?;
+    }
+    Ok(())
+}
+
+pub async fn handle_set(
+    api: Ref<Api>,
+    config: Ref<Config>,
+    greeting_service: Ref<GreetingService>,
+    sender: Sender,
+    command: Command,
+) -> Result<(), GreetingCommandError> {
+    let message = command.get_message();
+    if !sender
+        .require(Role::Supervisor, "change the greeting", message)
+        .await
+        .map_err(GreetingCommandError::CheckRole)?
+    {
+        return Ok(());
+    }
+    let args = command.get_args();
+    let text = match (
+        args.first().map(String::as_str),
+        args.get(1).map(String::as_str),
+        message.reply_to.as_ref(),
+    ) {
+        (Some("reset"), source, _) if source.map(is_valid_source).unwrap_or(true) => {
+            if greeting_service
+                .delete(source.unwrap_or(DEFAULT_GREETING))
+                .await
+                .map_err(GreetingCommandError::Delete)?
+            {
+                config.admin_text("greeting_reset", MESSAGE_RESET)
+            } else {
+                config.admin_text("greeting_not_set", MESSAGE_NOT_SET)
+            }
+        }
+        (source, None, Some(reply_to)) if source.map(is_valid_source).unwrap_or(true) => {
+            greeting_service
+                .save(Greeting::new(
+                    source.unwrap_or(DEFAULT_GREETING),
+                    reply_to.get_chat_id(),
+                    reply_to.id,
+                    reply_to.reply_markup.clone(),
+                ))
+                .await
+                .map_err(GreetingCommandError::Save)?;
+            config.admin_text("ok", MESSAGE_OK)
+        }
+        _ => config.admin_text("greeting_usage", MESSAGE_USAGE),
+    };
+    send_reply(&api, &command, text).await
+}
+
+async fn send_reply(api: &Api, command: &Command, text: &str) -> Result<(), GreetingCommandError> {
+    let message = command.get_message();
+    api.execute(SendMessage::new(message.get_chat_id(), text).reply_to_message_id(message.id))
+        .await
+        .map_err(GreetingCommandError::SendMessage)?;
+    Ok(())
+}
+
+#[derive(Debug)]
+pub enum GreetingCommandError {
+    CheckRole(SenderError),
+    Delete(GreetingServiceError),
+    Save(GreetingServiceError),
+    SendGreeting(GreetingError),
+    SendMessage(ExecuteError),
+}
+
+impl fmt::Display for GreetingCommandError {
+    fn fmt(&self, out: &mut fmt::Formatter) -> fmt::Result {
+        use self::GreetingCommandError::*;
+        match self {
+            CheckRole(err) => err.fmt(out),
+            Delete(err) => err.fmt(out),
+            Save(err) => err.fmt(out),
+            SendGreeting(err) => err.fmt(out),
+            SendMessage(err) => err.fmt(out),
+        }
+    }
+}
+
+impl Error for GreetingCommandError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        use self::GreetingCommandError::*;
+        Some(match self {
+            CheckRole(err) => err,
+            Delete(err) => err,
+            Save(err) => err,
+            SendGreeting(err) => err,
+            SendMessage(err) => err,
+        })
+    }
+}
+
+impl ErrorVariant for GreetingCommandError {
+    fn variant(&self) -> &'static str {
+        use self::GreetingCommandError::*;
+        match self {
+            CheckRole(_) => "CheckRole",
+            Delete(_) => "Delete",
+            Save(_) => "Save",
+            SendGreeting(_) => "SendGreeting",
+            SendMessage(_) => "SendMessage",
+        }
+    }
+}
@@ -0,0 +1,104 @@
+use crate::{
+    api::Api,
+    config::{Config, Role},
+    handlers::admin::{
+        message::{find_link, MessageError},
+        role::{Sender, SenderError},
+        MESSAGE_OK,
+    },
+    metrics::ErrorVariant,
+    services::{ConversationService, ConversationServiceError, MessageLinkService},
+};
+use carapax::{
+    methods::SendMessage,
+    types::{ChatId, Command},
+    ExecuteError, Ref,
+};
+use std::{error::Error, fmt};
+
+const MESSAGE_USAGE: &str = "Reply to a subscriber message with /internal <text> to add an internal note";
+
+pub async fn handle(
+    api: Ref<Api>,
+    config: Ref<Config>,
+    conversation_service: Ref<ConversationService>,
+    message_link_service: Ref<MessageLinkService>,
+    sender: Sender,
+    chat_id: ChatId,
+    command: Command,
+) -> Result<(), InternalError> {
+    let message = command.get_message();
+    if !sender
+        .require(Role::Agent, "add notes", message)
+        .await
+        .map_err(InternalError::CheckRole)?
+    {
+        return Ok(());
+    }
+    let text = command.get_args().join(" ");
+    let link = find_link(&message_link_service, message)
+        .await
+        .map_err(InternalError::FindLink)?;
+    let reply = match link {
+        Some(link) if !text.is_empty() => {
+            let conversation_id = conversation_service
+                .get_or_create(link.subscriber_user_id())
+                .await
+                .map_err(InternalError::AddNote)?;
+            conversation_service
+                .add_note(conversation_id, message.get_user_id(), &text)
+                .await
+                .map_err(InternalError::AddNote)?;
+            config.admin_text("ok", MESSAGE_OK)
+        }
+        _ => config.admin_text("internal_usage", MESSAGE_USAGE),
+    };
+    api.execute(SendMessage::new(chat_id, reply).reply_to_message_id(message.id))
+        .await
+        .map_err(InternalError::SendMessage)?;
+    Ok(())
+}
+
+#[derive(Debug)]
+pub enum InternalError {
+    CheckRole(SenderError),
+    AddNote(ConversationServiceError),
+    FindLink(MessageError),
+    SendMessage(ExecuteError),
+}
+
+impl fmt::Display for InternalError {
+    fn fmt(&self, out: &mut fmt::Formatter) -> fmt::Result {
+        use self::InternalError::*;
+        match self {
+            CheckRole(err) => err.fmt(out),
+            AddNote(err) => err.fmt(out),
+            FindLink(err) => err.fmt(out),
+            SendMessage(err) => err.fmt(out),
+        }
+    }
+}
+
+impl Error for InternalError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        use self::InternalError::*;
+        Some(match self {
+            CheckRole(err) => err,
+            AddNote(err) => err,
+            FindLink(err) => err,
+            SendMessage(err) => err,
+        })
+    }
+}
+
+impl ErrorVariant for InternalError {
+    fn variant(&self) -> &'static str {
+        use self::InternalError::*;
+        match self {
+            CheckRole(_) => "CheckRole",
+            AddNote(_) => "AddNote",
+            FindLink(_) => "FindLink",
+            SendMessage(_) => "SendMessage",
+        }
+    }
+}
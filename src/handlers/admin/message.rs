@@ -1,40 +1,355 @@
-use crate::services::{MessageLink, MessageLinkDirection, MessageLinkService, MessageLinkServiceError};
-use carapax::{methods::CopyMessage, types::Message, Api, ExecuteError, Ref};
+use crate::{
+    api::Api,
+    config::{Config, Role},
+    handlers::{
+        admin::{
+            confirm::{build_keyboard, MESSAGE_PREVIEW},
+            role::{Sender, SenderError},
+        },
+        media_group::{self, MediaGroupError, MEDIA_GROUP_DELAY},
+        receipt::{report_delivery, ReceiptError},
+    },
+    metrics::{self, ErrorVariant},
+    services::{
+        AdminService, AdminServiceError, ConversationService, ConversationServiceError, MediaGroupService, MessageLink,
+        MessageLinkDirection, MessageLinkService, MessageLinkServiceError, PendingReply, PendingReplyService,
+        PendingReplyServiceError,
+    },
+    shutdown::Tracker,
+};
+use carapax::{
+    methods::{CopyMessage, DeleteMessage, ForwardMessage, SendMessage},
+    types::{InlineKeyboardError, Integer, Message, MessageData, Text},
+    ExecuteError, Ref,
+};
+use chrono::Utc;
 use futures_util::future::OptionFuture;
 use std::{error::Error, fmt};
+use tokio::time::sleep;
 
+pub const MESSAGE_ASSIGNED: &str = "This conversation is assigned to another admin";
+const MESSAGE_MEDIA_GROUP_FAILED: &str = "Could not send media group";
+
+#[allow(clippy::too_many_arguments)]
 pub async fn handle(
     api: Ref<Api>,
+    config: Ref<Config>,
+    admin_service: Ref<AdminService>,
+    conversation_service: Ref<ConversationService>,
+    media_group_service: Ref<MediaGroupService>,
     message_link_service: Ref<MessageLinkService>,
+    pending_reply_service: Ref<PendingReplyService>,
+    tracker: Ref<Tracker>,
+    sender: Sender,
     message: Message,
 ) -> Result<(), MessageError> {
-    let link =
-        OptionFuture::from(message.reply_to.map(|reply_to| {
+    let internal_text = get_internal_text(&config, &message);
+    if let Some(ref media_group_id) = message.media_group_id {
+        let is_first_part = match internal_text {
+            Some(_) => media_group_service.add(media_group_id, message.clone()),
+            None if message.reply_to.is_some()
+                && !sender
+                    .is_allowed(Role::Agent, "reply to subscribers")
+                    .await
+                    .map_err(MessageError::CheckRole)? =>
+            {
+                return Ok(());
+            }
+            None => media_group_service.add(media_group_id, message.clone()),
+        };
+        if is_first_part {
+            let (api, config, admin_service) = (api.clone(), config.clone(), admin_service.clone());
+            let conversation_service = conversation_service.clone();
+            let (media_group_service, message_link_service) =
+                (media_group_service.clone(), message_link_service.clone());
+            let pending_reply_service = pending_reply_service.clone();
+            let (sender, media_group_id) = (sender.clone(), media_group_id.clone());
+            tracker.spawn(async move {
+                sleep(MEDIA_GROUP_DELAY).await;
+                let parts = media_group_service.take(&media_group_id);
+                // only one part of a group has a caption, so the whole group is internal because of it
+                if parts.iter().any(|part| get_internal_text(&config, part).is_some()) {
+                    return;
+                }
+                if let Err(err) = reply_media_group(
+                    &api,
+                    &config,
+                    &admin_service,
+                    &conversation_service,
+                    &message_link_service,
+                    &pending_reply_service,
+                    &sender,
+                    &parts,
+                )
+                .await
+                {
+                    metrics::record_error(&err);
+                    log::error!("Failed to reply with media group: {}", err);
+                    let text = format!(
+                        "{}: {}",
+                        config.admin_text("media_group_failed", MESSAGE_MEDIA_GROUP_FAILED),
+                        err
+                    );
+                    media_group::report_error(&api, &parts, &text).await;
+                }
+            });
+        }
+        if let Some(text) = internal_text {
+            add_note(&conversation_service, &message_link_service, &sender, &message, text).await?;
+        }
+        return Ok(());
+    }
+    if let Some(text) = internal_text {
+        return add_note(&conversation_service, &message_link_service, &sender, &message, text).await;
+    }
+    if let Some(link) = find_link(&message_link_service, &message).await? {
+        if !sender
+            .require(Role::Agent, "reply to subscribers", &message)
+            .await
+            .map_err(MessageError::CheckRole)?
+        {
+            return Ok(());
+        }
+        if !check_assignee(&config, &conversation_service, &sender, link.subscriber_user_id()).await? {
+            api.execute(
+                SendMessage::new(message.get_chat_id(), config.admin_text("assigned", MESSAGE_ASSIGNED))
+                    .reply_to_message_id(message.id),
+            )
+            .await
+            .map_err(MessageError::SendMessage)?;
+            return Ok(());
+        }
+        match config.confirm_replies {
+            Some(ref confirm_replies) => {
+                let pending_reply = PendingReply::new(
+                    message.get_chat_id(),
+                    message.id,
+                    sender.user_id(),
+                    link.admin_message_id(),
+                    Utc::now().naive_utc() + confirm_replies.timeout(),
+                );
+                request_confirmation(&api, &config, &pending_reply_service, pending_reply).await?
+            }
+            None => {
+                let result = send_reply(
+                    &api,
+                    &config,
+                    &admin_service,
+                    &message_link_service,
+                    &ReplyTarget::from(&link),
+                    Reply::Message(&message),
+                    message.get_user_id(),
+                )
+                .await;
+                report_delivery(
+                    &api,
+                    &config,
+                    &conversation_service,
+                    &message_link_service,
+                    &link,
+                    (message.get_chat_id(), message.id),
+                    &result,
+                )
+                .await
+                .map_err(MessageError::Receipt)?;
+                result?;
+                conversation_service
+                    .mark_answered(link.subscriber_user_id(), message.get_user_id())
+                    .await
+                    .map_err(MessageError::MarkAnswered)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+async fn add_note(
+    conversation_service: &ConversationService,
+    message_link_service: &MessageLinkService,
+    sender: &Sender,
+    message: &Message,
+    text: &str,
+) -> Result<(), MessageError> {
+    let link = match find_link(message_link_service, message).await? {
+        Some(link) => link,
+        None => return Ok(()),
+    };
+    if !sender
+        .require(Role::Agent, "add notes", message)
+        .await
+        .map_err(MessageError::CheckRole)?
+    {
+        return Ok(());
+    }
+    let conversation_id = conversation_service
+        .get_or_create(link.subscriber_user_id())
+        .await
+        .map_err(MessageError::AddNote)?;
+    conversation_service
+        .add_note(conversation_id, message.get_user_id(), text)
+        .await
+        .map_err(MessageError::AddNote)
+}
+
+fn get_internal_text<'a>(config: &Config, message: &'a Message) -> Option<&'a str> {
+    let prefix = config.internal_prefix.as_deref()?;
+    message
+        .get_text()
+        .and_then(|text| text.data.strip_prefix(prefix))
+        .map(str::trim)
+}
+
+pub async fn find_link(
+    message_link_service: &MessageLinkService,
+    message: &Message,
+) -> Result<Option<MessageLink>, MessageError> {
+    Ok(OptionFuture::from(
+        message.reply_to.as_ref().map(|reply_to| {
             message_link_service.find(reply_to.get_chat_id(), reply_to.id, MessageLinkDirection::Admin)
-        }))
+        }),
+    )
+    .await
+    .transpose()
+    .map_err(MessageError::FindLink)?
+    .flatten())
+}
+
+pub async fn request_confirmation(
+    api: &Api,
+    config: &Config,
+    pending_reply_service: &PendingReplyService,
+    pending_reply: PendingReply,
+) -> Result<(), MessageError> {
+    let (chat_id, message_id) = (pending_reply.chat_id(), pending_reply.message_id());
+    let id = pending_reply_service
+        .create(pending_reply)
         .await
-        .transpose()
-        .map_err(MessageError::FindLink)?
-        .flatten();
-    if let Some(link) = link {
-        let subscriber_user_id = link.subscriber_user_id();
-        let subscriber_chat_id = link.subscriber_chat_id();
-        let admin_chat_id = link.admin_chat_id();
-        let subscriber_message_id = api
-            .execute(
-                CopyMessage::new(subscriber_chat_id, admin_chat_id, message.id)
-                    .reply_to_message_id(link.subscriber_message_id()),
-            )
+        .map_err(MessageError::CreatePendingReply)?;
+    let keyboard = build_keyboard(config, id).map_err(MessageError::BuildKeyboard)?;
+    // the preview is a reply to the admin message, so the author of the reply is known on confirmation
+    let preview_message_id = api
+        .execute(
+            SendMessage::new(chat_id, config.admin_text("confirm_preview", MESSAGE_PREVIEW))
+                .reply_to_message_id(message_id)
+                .reply_markup(keyboard),
+        )
+        .await
+        .map_err(MessageError::SendPreview)?
+        .id;
+    pending_reply_service
+        .set_preview(id, preview_message_id)
+        .await
+        .map_err(MessageError::CreatePendingReply)
+}
+
+/// A subscriber to reply to
+pub struct ReplyTarget {
+    user_id: Integer,
+    chat_id: Integer,
+    reply_to_message_id: Option<Integer>,
+}
+
+impl ReplyTarget {
+    pub fn new(user_id: Integer, reply_to_message_id: Option<Integer>) -> Self {
+        // subscribers write to the bot in private chats
+        Self {
+            user_id,
+            chat_id: user_id,
+            reply_to_message_id,
+        }
+    }
+}
+
+impl From<&MessageLink> for ReplyTarget {
+    fn from(link: &MessageLink) -> Self {
+        Self {
+            user_id: link.subscriber_user_id(),
+            chat_id: link.subscriber_chat_id(),
+            reply_to_message_id: Some(link.subscriber_message_id()),
+        }
+    }
+}
+
+/// Content of a reply to a subscriber
+pub enum Reply<'a> {
+    /// A message sent by an admin
+    Message(&'a Message),
+    /// Parts of a media group sent by an admin
+    MediaGroup(&'a [Message]),
+    /// Parts of a media group known only by their IDs
+    StoredMediaGroup {
+        chat_id: Integer,
+        message_ids: &'a [Integer],
+    },
+    /// A message known only by its ID, e.g. a snippet or a scheduled reply,
+    /// the sent message is linked to the admin message which triggered the reply
+    Stored {
+        chat_id: Integer,
+        message_id: Integer,
+        admin_chat_id: Integer,
+        admin_message_id: Integer,
+    },
+}
+
+/// Sends a reply to a subscriber with the signature of the admin and links the sent messages
+pub async fn send_reply(
+    api: &Api,
+    config: &Config,
+    admin_service: &AdminService,
+    message_link_service: &MessageLinkService,
+    target: &ReplyTarget,
+    reply: Reply<'_>,
+    admin_user_id: Option<Integer>,
+) -> Result<(), MessageError> {
+    let signature = match admin_user_id {
+        Some(user_id) if config.signatures => admin_service
+            .get_signature(user_id)
             .await
-            .map_err(MessageError::CopyMessage)?
-            .message_id;
+            .map_err(MessageError::GetSignature)?
+            .map(|signature| format!("\n\n— {}", signature)),
+        _ => None,
+    };
+    let links = match reply {
+        Reply::Message(message) => {
+            let subscriber_message_id = match signature {
+                Some(ref signature) => send_signed(api, target, message, signature).await?,
+                None => copy_message(api, target, message.get_chat_id(), message.id, None).await?,
+            };
+            vec![(subscriber_message_id, message.get_chat_id(), message.id)]
+        }
+        Reply::MediaGroup(parts) => send_media_group(api, target, parts.to_vec(), signature.as_deref()).await?,
+        Reply::StoredMediaGroup { chat_id, message_ids } => {
+            let mut parts = Vec::with_capacity(message_ids.len());
+            for message_id in message_ids {
+                parts.push(fetch_message(api, chat_id, *message_id).await?);
+            }
+            send_media_group(api, target, parts, signature.as_deref()).await?
+        }
+        Reply::Stored {
+            chat_id,
+            message_id,
+            admin_chat_id,
+            admin_message_id,
+        } => {
+            let subscriber_message_id = match signature {
+                Some(ref signature) => {
+                    let message = fetch_message(api, chat_id, message_id).await?;
+                    send_signed(api, target, &message, signature).await?
+                }
+                None => copy_message(api, target, chat_id, message_id, None).await?,
+            };
+            vec![(subscriber_message_id, admin_chat_id, admin_message_id)]
+        }
+    };
+    metrics::record_forwarded_to_subscriber(links.len());
+    for (subscriber_message_id, admin_chat_id, admin_message_id) in links {
         message_link_service
             .create(MessageLink::new(
-                subscriber_user_id,
-                subscriber_chat_id,
+                target.user_id,
+                target.chat_id,
                 subscriber_message_id,
                 admin_chat_id,
-                message.id,
+                admin_message_id,
             ))
             .await
             .map_err(MessageError::CreateLink)?;
@@ -42,20 +357,257 @@ pub async fn handle(
     Ok(())
 }
 
+async fn send_media_group(
+    api: &Api,
+    target: &ReplyTarget,
+    mut parts: Vec<Message>,
+    signature: Option<&str>,
+) -> Result<Vec<(Integer, Integer, Integer)>, MessageError> {
+    if let (Some(signature), false) = (signature, parts.is_empty()) {
+        // Telegram shows a caption of an album only when a single part has it
+        let index = parts
+            .iter()
+            .position(|part| media_group::get_caption(&part.data).is_some())
+            .unwrap_or(0);
+        sign(&mut parts[index], signature);
+    }
+    Ok(
+        media_group::send(api, target.chat_id, &parts, target.reply_to_message_id)
+            .await
+            .map_err(MessageError::SendMediaGroup)?
+            .into_iter()
+            .zip(&parts)
+            .map(|(subscriber_message_id, part)| (subscriber_message_id, part.get_chat_id(), part.id))
+            .collect(),
+    )
+}
+
+// Telegram has no method to get a message by its ID,
+// so the message is forwarded to the same chat and the forward is deleted right away
+async fn fetch_message(api: &Api, chat_id: Integer, message_id: Integer) -> Result<Message, MessageError> {
+    let mut message = api
+        .execute(ForwardMessage::new(chat_id, chat_id, message_id).disable_notification(true))
+        .await
+        .map_err(MessageError::FetchMessage)?;
+    if let Err(err) = api.execute(DeleteMessage::new(chat_id, message.id)).await {
+        log::warn!("Could not delete a forwarded message: {}", err);
+    }
+    // the forward is gone, the content is sent and linked as the original message
+    message.id = message_id;
+    Ok(message)
+}
+
+// The signature is appended, so offsets of the existing entities stay valid
+fn sign(message: &mut Message, signature: &str) {
+    let caption = match message.data {
+        MessageData::Text(ref mut text) => {
+            text.data.push_str(signature);
+            return;
+        }
+        MessageData::Audio { ref mut caption, .. }
+        | MessageData::Document { ref mut caption, .. }
+        | MessageData::Photo { ref mut caption, .. }
+        | MessageData::Video { ref mut caption, .. }
+        | MessageData::Voice { ref mut caption, .. } => caption,
+        _ => return,
+    };
+    match caption {
+        Some(caption) => caption.data.push_str(signature),
+        None => {
+            *caption = Some(Text {
+                data: String::from(signature.trim_start()),
+                entities: None,
+            })
+        }
+    }
+}
+
+async fn send_signed(
+    api: &Api,
+    target: &ReplyTarget,
+    message: &Message,
+    signature: &str,
+) -> Result<Integer, MessageError> {
+    let mut message = message.clone();
+    sign(&mut message, signature);
+    match message.data {
+        MessageData::Text(text) => {
+            let mut method = SendMessage::new(target.chat_id, text.data);
+            if let Some(entities) = text.entities {
+                method = method.entities(entities.to_vec());
+            }
+            if let Some(reply_to_message_id) = target.reply_to_message_id {
+                method = method
+                    .reply_to_message_id(reply_to_message_id)
+                    .allow_sending_without_reply(true);
+            }
+            Ok(api.execute(method).await.map_err(MessageError::SendMessage)?.id)
+        }
+        ref data => {
+            copy_message(
+                api,
+                target,
+                message.get_chat_id(),
+                message.id,
+                media_group::get_caption(data),
+            )
+            .await
+        }
+    }
+}
+
+async fn copy_message(
+    api: &Api,
+    target: &ReplyTarget,
+    from_chat_id: Integer,
+    message_id: Integer,
+    caption: Option<&Text>,
+) -> Result<Integer, MessageError> {
+    let mut method = CopyMessage::new(target.chat_id, from_chat_id, message_id);
+    if let Some(caption) = caption {
+        method = method.caption(caption.data.clone());
+        if let Some(ref entities) = caption.entities {
+            method = method.caption_entities(entities.to_vec());
+        }
+    }
+    // the subscriber could have deleted the message by the time a reply is sent
+    if let Some(reply_to_message_id) = target.reply_to_message_id {
+        method = method
+            .reply_to_message_id(reply_to_message_id)
+            .allow_sending_without_reply(true);
+    }
+    Ok(api.execute(method).await.map_err(MessageError::CopyMessage)?.message_id)
+}
+
+pub async fn check_assignee(
+    config: &Config,
+    conversation_service: &ConversationService,
+    sender: &Sender,
+    subscriber_user_id: Integer,
+) -> Result<bool, MessageError> {
+    if !config.strict_assignment {
+        return Ok(true);
+    }
+    let conversation_id = conversation_service
+        .get_or_create(subscriber_user_id)
+        .await
+        .map_err(MessageError::GetAssignee)?;
+    match conversation_service
+        .get_assignee(conversation_id)
+        .await
+        .map_err(MessageError::GetAssignee)?
+    {
+        Some(user_id) if user_id != sender.user_id() => sender
+            .is_allowed(Role::Supervisor, "reply in conversations assigned to other admins")
+            .await
+            .map_err(MessageError::CheckRole),
+        _ => Ok(true),
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn reply_media_group(
+    api: &Api,
+    config: &Config,
+    admin_service: &AdminService,
+    conversation_service: &ConversationService,
+    message_link_service: &MessageLinkService,
+    pending_reply_service: &PendingReplyService,
+    sender: &Sender,
+    parts: &[Message],
+) -> Result<(), MessageError> {
+    let mut link = None;
+    for part in parts {
+        link = find_link(message_link_service, part).await?;
+        if link.is_some() {
+            break;
+        }
+    }
+    let link = match link {
+        Some(link) => link,
+        None => return Ok(()),
+    };
+    if !check_assignee(config, conversation_service, sender, link.subscriber_user_id()).await? {
+        return Ok(());
+    }
+    if let Some(ref confirm_replies) = config.confirm_replies {
+        let pending_reply = PendingReply::new_media_group(
+            link.admin_chat_id(),
+            parts.to_vec(),
+            sender.user_id(),
+            link.admin_message_id(),
+            Utc::now().naive_utc() + confirm_replies.timeout(),
+        );
+        return request_confirmation(api, config, pending_reply_service, pending_reply).await;
+    }
+    let result = send_reply(
+        api,
+        config,
+        admin_service,
+        message_link_service,
+        &ReplyTarget::from(&link),
+        Reply::MediaGroup(parts),
+        Some(sender.user_id()),
+    )
+    .await;
+    if let Some(first_part) = parts.first() {
+        report_delivery(
+            api,
+            config,
+            conversation_service,
+            message_link_service,
+            &link,
+            (first_part.get_chat_id(), first_part.id),
+            &result,
+        )
+        .await
+        .map_err(MessageError::Receipt)?;
+    }
+    result?;
+    conversation_service
+        .mark_answered(link.subscriber_user_id(), Some(sender.user_id()))
+        .await
+        .map_err(MessageError::MarkAnswered)
+}
+
 #[derive(Debug)]
 pub enum MessageError {
+    AddNote(ConversationServiceError),
+    BuildKeyboard(InlineKeyboardError),
+    CheckRole(SenderError),
     CopyMessage(ExecuteError),
+    CreatePendingReply(PendingReplyServiceError),
     CreateLink(MessageLinkServiceError),
+    FetchMessage(ExecuteError),
     FindLink(MessageLinkServiceError),
+    GetAssignee(ConversationServiceError),
+    GetSignature(AdminServiceError),
+    MarkAnswered(ConversationServiceError),
+    Receipt(ReceiptError),
+    SendMediaGroup(MediaGroupError),
+    SendMessage(ExecuteError),
+    SendPreview(ExecuteError),
 }
 
 impl fmt::Display for MessageError {
     fn fmt(&self, out: &mut fmt::Formatter) -> fmt::Result {
         use self::MessageError::*;
         match self {
+            AddNote(err) => err.fmt(out),
+            BuildKeyboard(err) => write!(out, "could not build inline keyboard: {}", err),
+            CheckRole(err) => err.fmt(out),
             CreateLink(err) => err.fmt(out),
+            CreatePendingReply(err) => err.fmt(out),
             CopyMessage(err) => err.fmt(out),
+            FetchMessage(err) => write!(out, "could not fetch message: {}", err),
             FindLink(err) => err.fmt(out),
+            GetAssignee(err) => err.fmt(out),
+            GetSignature(err) => err.fmt(out),
+            MarkAnswered(err) => err.fmt(out),
+            Receipt(err) => err.fmt(out),
+            SendMediaGroup(err) => err.fmt(out),
+            SendMessage(err) => err.fmt(out),
+            SendPreview(err) => err.fmt(out),
         }
     }
 }
@@ -64,9 +616,44 @@ impl Error for MessageError {
     fn source(&self) -> Option<&(dyn Error + 'static)> {
         use self::MessageError::*;
         Some(match self {
+            AddNote(err) => err,
+            BuildKeyboard(err) => err,
+            CheckRole(err) => err,
             CreateLink(err) => err,
+            CreatePendingReply(err) => err,
             CopyMessage(err) => err,
+            FetchMessage(err) => err,
             FindLink(err) => err,
+            GetAssignee(err) => err,
+            GetSignature(err) => err,
+            MarkAnswered(err) => err,
+            Receipt(err) => err,
+            SendMediaGroup(err) => err,
+            SendMessage(err) => err,
+            SendPreview(err) => err,
         })
     }
 }
+
+impl ErrorVariant for MessageError {
+    fn variant(&self) -> &'static str {
+        use self::MessageError::*;
+        match self {
+            AddNote(_) => "AddNote",
+            BuildKeyboard(_) => "BuildKeyboard",
+            CheckRole(_) => "CheckRole",
+            CopyMessage(_) => "CopyMessage",
+            CreatePendingReply(_) => "CreatePendingReply",
+            CreateLink(_) => "CreateLink",
+            FetchMessage(_) => "FetchMessage",
+            FindLink(_) => "FindLink",
+            GetAssignee(_) => "GetAssignee",
+            GetSignature(_) => "GetSignature",
+            MarkAnswered(_) => "MarkAnswered",
+            Receipt(_) => "Receipt",
+            SendMediaGroup(_) => "SendMediaGroup",
+            SendMessage(_) => "SendMessage",
+            SendPreview(_) => "SendPreview",
+        }
+    }
+}
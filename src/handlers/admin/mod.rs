@@ -1,15 +1,58 @@
-use carapax::{Chain, CommandExt};
+use crate::metrics::TrackErrorsExt;
+use carapax::{Chain, CommandExt, PredicateExt};
 
+mod assign;
+mod auto_answer;
 mod block;
+mod close;
+mod confirm;
+mod greeting;
+mod internal;
 mod message;
+mod notes;
+mod pending;
+mod role;
+mod schedule;
+mod signature;
+mod snippet;
+mod stats;
 mod unblock;
 mod users;
+mod verify;
+
+pub use self::message::{send_reply, MessageError, Reply, ReplyTarget};
+
+// fallbacks of texts shared by several handlers, see locales/en.yaml
+const MESSAGE_INVALID_USER_ID: &str = "Invalid User ID";
+const MESSAGE_MORE: &str = "…and {count} more";
+const MESSAGE_NOT_FOUND: &str = "Not found";
+const MESSAGE_OK: &str = "OK";
+pub const MESSAGE_REPLY_EXPIRED: &str = "Reply expired";
+const MESSAGE_USER_ID_REQUIRED: &str = "User ID is required";
 
 pub fn setup() -> Chain {
     Chain::once()
-        .add(users::handle_list.command("/users"))
-        .add(users::handle_page_changed)
-        .add(block::handle.command("/block"))
-        .add(unblock::handle.command("/unblock"))
-        .add(message::handle)
+        .add(users::handle_list.track_errors().command("/users"))
+        .add(confirm::handle.track_errors())
+        .add(users::handle_page_changed.track_errors())
+        .add(assign::handle_assign.track_errors().command("/assign"))
+        .add(assign::handle_take.track_errors().command("/take"))
+        .add(block::handle.track_errors().command("/block"))
+        .add(close::handle.track_errors().command("/close"))
+        .add(auto_answer::handle.track_errors().command("/autoanswer"))
+        .add(greeting::handle_preview.track_errors().command("/greeting"))
+        .add(greeting::handle_set.track_errors().command("/setgreeting"))
+        .add(internal::handle.track_errors().command("/internal"))
+        .add(notes::handle.track_errors().command("/notes"))
+        .add(unblock::handle.track_errors().command("/unblock"))
+        .add(verify::handle.track_errors().command("/verify"))
+        .add(schedule::handle.track_errors().command("/schedule"))
+        .add(pending::handle.track_errors().command("/pending"))
+        .add(role::handle.track_errors().command("/role"))
+        .add(signature::handle.track_errors().command("/signature"))
+        .add(snippet::handle.track_errors().command("/snippet"))
+        .add(stats::handle.track_errors().command("/stats"))
+        .add(snippet::handle_call.track_errors().predicate(snippet::is_call))
+        .add(schedule::handle_reply.track_errors().predicate(schedule::is_reply))
+        .add(message::handle.track_errors())
 }
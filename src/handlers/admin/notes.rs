@@ -0,0 +1,116 @@
+use crate::{
+    api::Api,
+    config::{Config, Role},
+    handlers::admin::{
+        message::{find_link, MessageError},
+        role::{Sender, SenderError},
+        MESSAGE_MORE,
+    },
+    metrics::ErrorVariant,
+    services::{ConversationService, ConversationServiceError, MessageLinkService},
+};
+use carapax::{
+    methods::SendMessage,
+    types::{ChatId, Command},
+    ExecuteError, Ref,
+};
+use std::{error::Error, fmt};
+
+const MESSAGE_EMPTY: &str = "No notes";
+const MESSAGE_USAGE: &str = "Reply to a subscriber message with /notes to view the notes";
+
+const MAX_ITEMS: usize = 50;
+
+pub async fn handle(
+    api: Ref<Api>,
+    config: Ref<Config>,
+    conversation_service: Ref<ConversationService>,
+    message_link_service: Ref<MessageLinkService>,
+    sender: Sender,
+    chat_id: ChatId,
+    command: Command,
+) -> Result<(), NotesError> {
+    let message = command.get_message();
+    if !sender
+        .require(Role::Agent, "view notes", message)
+        .await
+        .map_err(NotesError::CheckRole)?
+    {
+        return Ok(());
+    }
+    let link = find_link(&message_link_service, message)
+        .await
+        .map_err(NotesError::FindLink)?;
+    let text = match link {
+        Some(link) => {
+            let notes = conversation_service
+                .get_notes(link.subscriber_user_id())
+                .await
+                .map_err(NotesError::GetNotes)?;
+            if notes.is_empty() {
+                String::from(config.admin_text("notes_empty", MESSAGE_EMPTY))
+            } else {
+                // the newest notes go first
+                let mut lines: Vec<String> = notes.iter().take(MAX_ITEMS).map(ToString::to_string).collect();
+                if notes.len() > MAX_ITEMS {
+                    lines.push(
+                        config
+                            .admin_text("more", MESSAGE_MORE)
+                            .replace("{count}", &(notes.len() - MAX_ITEMS).to_string()),
+                    );
+                }
+                lines.join("\n")
+            }
+        }
+        None => String::from(config.admin_text("notes_usage", MESSAGE_USAGE)),
+    };
+    // notes are sent as plain text, they are written by admins as is
+    api.execute(SendMessage::new(chat_id, text).reply_to_message_id(message.id))
+        .await
+        .map_err(NotesError::SendMessage)?;
+    Ok(())
+}
+
+#[derive(Debug)]
+pub enum NotesError {
+    CheckRole(SenderError),
+    FindLink(MessageError),
+    GetNotes(ConversationServiceError),
+    SendMessage(ExecuteError),
+}
+
+impl fmt::Display for NotesError {
+    fn fmt(&self, out: &mut fmt::Formatter) -> fmt::Result {
+        use self::NotesError::*;
+        match self {
+            CheckRole(err) => err.fmt(out),
+            FindLink(err) => err.fmt(out),
+            GetNotes(err) => err.fmt(out),
+            SendMessage(err) => err.fmt(out),
+        }
+    }
+}
+
+impl Error for NotesError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        use self::NotesError::*;
+        Some(match self {
+            CheckRole(err) => err,
+            FindLink(err) => err,
+            GetNotes(err) => err,
+            SendMessage(err) => err,
+        })
+    }
+}
+
+impl ErrorVariant for NotesError {
+    fn variant(&self) -> &'static str {
+        use self::NotesError::*;
+        match self {
+            CheckRole(_) => "CheckRole",
+            FindLink(_) => "FindLink",
+            GetNotes(_) => "GetNotes",
+            SendMessage(_) => "SendMessage",
+        }
+    }
+}
@@ -0,0 +1,106 @@
+use crate::{
+    api::Api,
+    config::{Config, Role},
+    handlers::admin::{
+        role::{Sender, SenderError},
+        MESSAGE_MORE,
+    },
+    metrics::ErrorVariant,
+    services::{ConversationService, ConversationServiceError},
+};
+use carapax::{
+    methods::SendMessage,
+    types::{ChatId, Command, ParseMode},
+    ExecuteError, Ref,
+};
+use std::{error::Error, fmt};
+
+const MESSAGE_EMPTY: &str = "No pending messages";
+
+const MAX_ITEMS: usize = 50;
+
+pub async fn handle(
+    api: Ref<Api>,
+    config: Ref<Config>,
+    conversation_service: Ref<ConversationService>,
+    sender: Sender,
+    chat_id: ChatId,
+    command: Command,
+) -> Result<(), PendingError> {
+    if !sender
+        .require(Role::Agent, "list pending messages", command.get_message())
+        .await
+        .map_err(PendingError::CheckRole)?
+    {
+        return Ok(());
+    }
+    let conversations = conversation_service
+        .get_awaiting()
+        .await
+        .map_err(PendingError::GetAwaiting)?;
+    let text = if conversations.is_empty() {
+        String::from(config.admin_text("pending_empty", MESSAGE_EMPTY))
+    } else {
+        let mut lines: Vec<String> = conversations
+            .iter()
+            .take(MAX_ITEMS)
+            .map(|conversation| format!("#c{} {}", conversation.id(), conversation))
+            .collect();
+        if conversations.len() > MAX_ITEMS {
+            lines.push(
+                config
+                    .admin_text("more", MESSAGE_MORE)
+                    .replace("{count}", &(conversations.len() - MAX_ITEMS).to_string()),
+            );
+        }
+        lines.join("\n")
+    };
+    api.execute(
+        SendMessage::new(chat_id, text)
+            .parse_mode(ParseMode::Html)
+            .reply_to_message_id(command.get_message().id),
+    )
+    .await
+    .map_err(PendingError::SendMessage)?;
+    Ok(())
+}
+
+#[derive(Debug)]
+pub enum PendingError {
+    CheckRole(SenderError),
+    GetAwaiting(ConversationServiceError),
+    SendMessage(ExecuteError),
+}
+
+impl fmt::Display for PendingError {
+    fn fmt(&self, out: &mut fmt::Formatter) -> fmt::Result {
+        use self::PendingError::*;
+        match self {
+            CheckRole(err) => err.fmt(out),
+            GetAwaiting(err) => err.fmt(out),
+            SendMessage(err) => err.fmt(out),
+        }
+    }
+}
+
+impl Error for PendingError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        use self::PendingError::*;
+        Some(match self {
+            CheckRole(err) => err,
+            GetAwaiting(err) => err,
+            SendMessage(err) => err,
+        })
+    }
+}
+
+impl ErrorVariant for PendingError {
+    fn variant(&self) -> &'static str {
+        use self::PendingError::*;
+        match self {
+            CheckRole(_) => "CheckRole",
+            GetAwaiting(_) => "GetAwaiting",
+            SendMessage(_) => "SendMessage",
+        }
+    }
+}
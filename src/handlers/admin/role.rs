@@ -0,0 +1,223 @@
+use crate::{
+    api::Api,
+    config::{Config, Role},
+    handlers::admin::{MESSAGE_INVALID_USER_ID, MESSAGE_OK},
+    metrics::ErrorVariant,
+    services::{AdminService, AdminServiceError},
+};
+use carapax::{
+    methods::SendMessage,
+    types::{ChatId, Command, Integer, Message},
+    ExecuteError, HandlerInput, Ref, TryFromInput,
+};
+use futures_util::future::{ready, Ready};
+use std::{error::Error, fmt};
+
+pub const MESSAGE_PERMISSION_DENIED: &str = "Permission denied";
+const MESSAGE_CURRENT_ROLE: &str = "Your role: {role}";
+const MESSAGE_UNKNOWN_ROLE: &str = "Unknown role, expected viewer, agent or supervisor";
+const MESSAGE_USAGE: &str = "Usage: /role [user_id viewer|agent|supervisor|reset]";
+
+pub async fn handle(
+    api: Ref<Api>,
+    config: Ref<Config>,
+    admin_service: Ref<AdminService>,
+    sender: Sender,
+    chat_id: ChatId,
+    command: Command,
+) -> Result<(), RoleError> {
+    let message = command.get_message();
+    let args = command.get_args();
+    let text = match (args.first(), args.get(1)) {
+        (None, _) => config.admin_text("role_current", MESSAGE_CURRENT_ROLE).replace(
+            "{role}",
+            &sender.get_role().await.map_err(RoleError::CheckRole)?.to_string(),
+        ),
+        (Some(user_id), Some(role)) => {
+            if !sender
+                .require(Role::Supervisor, "assign roles", message)
+                .await
+                .map_err(RoleError::CheckRole)?
+            {
+                return Ok(());
+            }
+            let role = match role.as_str() {
+                "reset" => Ok(None),
+                role => role.parse::<Role>().map(Some),
+            };
+            match (user_id.parse::<Integer>(), role) {
+                (Err(_), _) => String::from(config.admin_text("invalid_user_id", MESSAGE_INVALID_USER_ID)),
+                (_, Err(_)) => String::from(config.admin_text("role_unknown_role", MESSAGE_UNKNOWN_ROLE)),
+                (Ok(user_id), Ok(role)) => {
+                    admin_service
+                        .set_role(user_id, role)
+                        .await
+                        .map_err(RoleError::SetRole)?;
+                    String::from(config.admin_text("ok", MESSAGE_OK))
+                }
+            }
+        }
+        _ => String::from(config.admin_text("role_usage", MESSAGE_USAGE)),
+    };
+    api.execute(SendMessage::new(chat_id, text).reply_to_message_id(message.id))
+        .await
+        .map_err(RoleError::SendMessage)?;
+    Ok(())
+}
+
+#[derive(Clone)]
+pub struct Sender {
+    api: Api,
+    admin_service: AdminService,
+    config: Config,
+    user_id: Integer,
+}
+
+impl Sender {
+    pub fn user_id(&self) -> Integer {
+        self.user_id
+    }
+
+    pub async fn get_role(&self) -> Result<Role, SenderError> {
+        let assigned_role = if self.config.roles.contains_key(&self.user_id) {
+            // overridden by the config anyway
+            None
+        } else {
+            self.admin_service
+                .get_role(self.user_id)
+                .await
+                .map_err(SenderError::GetRole)?
+        };
+        Ok(self.config.get_role(self.user_id, assigned_role))
+    }
+
+    pub async fn is_allowed(&self, required: Role, action: &str) -> Result<bool, SenderError> {
+        let role = self.get_role().await?;
+        if role >= required {
+            return Ok(true);
+        }
+        log::warn!(
+            "Admin {} with role {} is not allowed to {} (requires {})",
+            self.user_id,
+            role,
+            action,
+            required
+        );
+        Ok(false)
+    }
+
+    pub async fn require(&self, required: Role, action: &str, message: &Message) -> Result<bool, SenderError> {
+        if self.is_allowed(required, action).await? {
+            return Ok(true);
+        }
+        self.api
+            .execute(
+                SendMessage::new(
+                    message.get_chat_id(),
+                    self.config.admin_text("permission_denied", MESSAGE_PERMISSION_DENIED),
+                )
+                .reply_to_message_id(message.id),
+            )
+            .await
+            .map_err(SenderError::SendMessage)?;
+        Ok(false)
+    }
+}
+
+impl TryFromInput for Sender {
+    type Error = SenderError;
+
+    type Future = Ready<Result<Option<Self>, Self::Error>>;
+
+    fn try_from_input(input: HandlerInput) -> Self::Future {
+        let user_id = match input.update.get_user_id() {
+            Some(user_id) => user_id,
+            None => return ready(Ok(None)),
+        };
+        let context = &input.context;
+        ready(
+            match (
+                context.get::<Api>(),
+                context.get::<Config>(),
+                context.get::<AdminService>(),
+            ) {
+                (Some(api), Some(config), Some(admin_service)) => Ok(Some(Self {
+                    api: api.clone(),
+                    admin_service: admin_service.clone(),
+                    config: config.clone(),
+                    user_id,
+                })),
+                _ => Err(SenderError::NoContext),
+            },
+        )
+    }
+}
+
+#[derive(Debug)]
+pub enum SenderError {
+    GetRole(AdminServiceError),
+    NoContext,
+    SendMessage(ExecuteError),
+}
+
+impl fmt::Display for SenderError {
+    fn fmt(&self, out: &mut fmt::Formatter) -> fmt::Result {
+        use self::SenderError::*;
+        match self {
+            GetRole(err) => err.fmt(out),
+            NoContext => write!(out, "could not find services in context"),
+            SendMessage(err) => err.fmt(out),
+        }
+    }
+}
+
+impl Error for SenderError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        use self::SenderError::*;
+        match self {
+            GetRole(err) => Some(err),
+            NoContext => None,
+            SendMessage(err) => Some(err),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum RoleError {
+    CheckRole(SenderError),
+    SendMessage(ExecuteError),
+    SetRole(AdminServiceError),
+}
+
+impl fmt::Display for RoleError {
+    fn fmt(&self, out: &mut fmt::Formatter) -> fmt::Result {
+        use self::RoleError::*;
+        match self {
+            CheckRole(err) => err.fmt(out),
+            SendMessage(err) => err.fmt(out),
+            SetRole(err) => err.fmt(out),
+        }
+    }
+}
+
+impl Error for RoleError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        use self::RoleError::*;
+        Some(match self {
+            CheckRole(err) => err,
+            SendMessage(err) => err,
+            SetRole(err) => err,
+        })
+    }
+}
+
+impl ErrorVariant for RoleError {
+    fn variant(&self) -> &'static str {
+        use self::RoleError::*;
+        match self {
+            CheckRole(_) => "CheckRole",
+            SendMessage(_) => "SendMessage",
+            SetRole(_) => "SetRole",
+        }
+    }
+}
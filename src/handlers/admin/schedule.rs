@@ -0,0 +1,349 @@
+use crate::{
+    api::Api,
+    config::{Config, Role},
+    handlers::admin::{
+        message::{check_assignee, MessageError, MESSAGE_ASSIGNED},
+        role::{Sender, SenderError, MESSAGE_PERMISSION_DENIED},
+        MESSAGE_INVALID_USER_ID, MESSAGE_NOT_FOUND, MESSAGE_OK,
+    },
+    metrics::ErrorVariant,
+    services::{
+        ConversationService, MessageLinkDirection, MessageLinkService, MessageLinkServiceError, ScheduleService,
+        ScheduleServiceError, ScheduledMessage,
+    },
+};
+use carapax::{
+    methods::SendMessage,
+    types::{ChatId, Command, Integer, Message, ParseMode},
+    ExecuteError, Ref,
+};
+use chrono::{NaiveDateTime, Utc};
+use std::{error::Error, fmt};
+
+const MESSAGE_EMPTY: &str = "No scheduled messages";
+const MESSAGE_INVALID_DATETIME: &str = "Invalid datetime, expected YYYY-MM-DD HH:MM (UTC) in the future";
+const MESSAGE_INVALID_ID: &str = "Invalid ID";
+const MESSAGE_NO_REPLY: &str = "Reply to a message to schedule it";
+const MESSAGE_NO_TARGET: &str = "Specify a user ID or \"all\", or reply to a subscriber message";
+const MESSAGE_REPLY_PROMPT: &str = "Reply to this message with the reply to schedule";
+const MESSAGE_USAGE: &str =
+    "Usage: /schedule YYYY-MM-DD HH:MM [user_id|all] (as a reply) | /schedule list | /schedule del ID";
+
+const DATETIME_FORMAT: &str = "%Y-%m-%d %H:%M";
+const TARGET_ALL: &str = "all";
+
+#[allow(clippy::too_many_arguments)]
+pub async fn handle(
+    api: Ref<Api>,
+    config: Ref<Config>,
+    conversation_service: Ref<ConversationService>,
+    message_link_service: Ref<MessageLinkService>,
+    schedule_service: Ref<ScheduleService>,
+    sender: Sender,
+    chat_id: ChatId,
+    command: Command,
+) -> Result<(), ScheduleError> {
+    let message = command.get_message();
+    if !sender
+        .require(Role::Agent, "schedule messages", message)
+        .await
+        .map_err(ScheduleError::CheckRole)?
+    {
+        return Ok(());
+    }
+    let args = command.get_args();
+    let text = match args.first().map(String::as_str) {
+        Some("list") => {
+            let messages = schedule_service.get_list().await.map_err(ScheduleError::GetList)?;
+            if messages.is_empty() {
+                String::from(config.admin_text("schedule_empty", MESSAGE_EMPTY))
+            } else {
+                messages
+                    .iter()
+                    .map(ToString::to_string)
+                    .collect::<Vec<String>>()
+                    .join("\n")
+            }
+        }
+        Some("del") => match args.get(1).map(|arg| arg.parse::<i32>()) {
+            Some(Ok(id)) => match schedule_service.find(id).await.map_err(ScheduleError::Find)? {
+                // the same permissions as for scheduling the message are required
+                Some(scheduled) => match scheduled.subscriber_user_id() {
+                    None if !sender
+                        .is_allowed(Role::Supervisor, "delete scheduled broadcasts")
+                        .await
+                        .map_err(ScheduleError::CheckRole)? =>
+                    {
+                        String::from(config.admin_text("permission_denied", MESSAGE_PERMISSION_DENIED))
+                    }
+                    Some(user_id)
+                        if !check_assignee(&config, &conversation_service, &sender, user_id)
+                            .await
+                            .map_err(ScheduleError::CheckAssignee)? =>
+                    {
+                        String::from(config.admin_text("assigned", MESSAGE_ASSIGNED))
+                    }
+                    _ => {
+                        if schedule_service.delete(id).await.map_err(ScheduleError::Delete)? {
+                            String::from(config.admin_text("ok", MESSAGE_OK))
+                        } else {
+                            String::from(config.admin_text("not_found", MESSAGE_NOT_FOUND))
+                        }
+                    }
+                },
+                None => String::from(config.admin_text("not_found", MESSAGE_NOT_FOUND)),
+            },
+            _ => String::from(config.admin_text("invalid_id", MESSAGE_INVALID_ID)),
+        },
+        Some(_) if args.len() >= 2 => match message.reply_to {
+            Some(ref reply_to) => {
+                let send_at = NaiveDateTime::parse_from_str(&format!("{} {}", args[0], args[1]), DATETIME_FORMAT)
+                    .ok()
+                    .filter(|send_at| *send_at > Utc::now().naive_utc());
+                match (send_at, args.get(2).map(String::as_str)) {
+                    (None, _) => String::from(config.admin_text("schedule_invalid_datetime", MESSAGE_INVALID_DATETIME)),
+                    (Some(_), Some(TARGET_ALL))
+                        if !sender
+                            .is_allowed(Role::Supervisor, "schedule broadcasts")
+                            .await
+                            .map_err(ScheduleError::CheckRole)? =>
+                    {
+                        String::from(config.admin_text("permission_denied", MESSAGE_PERMISSION_DENIED))
+                    }
+                    (Some(send_at), Some(TARGET_ALL)) => {
+                        let message = ScheduledMessage::new(reply_to.get_chat_id(), reply_to.id, None, None, send_at);
+                        create(&config, &schedule_service, message).await?
+                    }
+                    (Some(send_at), Some(user_id)) => match user_id.parse::<Integer>() {
+                        Ok(user_id)
+                            if !check_assignee(&config, &conversation_service, &sender, user_id)
+                                .await
+                                .map_err(ScheduleError::CheckAssignee)? =>
+                        {
+                            String::from(config.admin_text("assigned", MESSAGE_ASSIGNED))
+                        }
+                        Ok(user_id) => {
+                            let message = ScheduledMessage::new(
+                                reply_to.get_chat_id(),
+                                reply_to.id,
+                                Some(user_id),
+                                Some(sender.user_id()),
+                                send_at,
+                            );
+                            create(&config, &schedule_service, message).await?
+                        }
+                        Err(_) => String::from(config.admin_text("invalid_user_id", MESSAGE_INVALID_USER_ID)),
+                    },
+                    (Some(send_at), None) => match message_link_service
+                        .find(reply_to.get_chat_id(), reply_to.id, MessageLinkDirection::Admin)
+                        .await
+                        .map_err(ScheduleError::FindLink)?
+                    {
+                        Some(link)
+                            if !check_assignee(&config, &conversation_service, &sender, link.subscriber_user_id())
+                                .await
+                                .map_err(ScheduleError::CheckAssignee)? =>
+                        {
+                            String::from(config.admin_text("assigned", MESSAGE_ASSIGNED))
+                        }
+                        Some(link) => {
+                            // the reply itself is stored when an admin replies to the prompt,
+                            // so it is not delivered immediately by the message handler
+                            let prompt_message_id = api
+                                .execute(
+                                    SendMessage::new(
+                                        chat_id,
+                                        config.admin_text("schedule_reply_prompt", MESSAGE_REPLY_PROMPT),
+                                    )
+                                    .reply_to_message_id(message.id),
+                                )
+                                .await
+                                .map_err(ScheduleError::SendMessage)?
+                                .id;
+                            schedule_service
+                                .create(ScheduledMessage::new_reply(
+                                    reply_to.get_chat_id(),
+                                    prompt_message_id,
+                                    link.subscriber_user_id(),
+                                    link.subscriber_message_id(),
+                                    send_at,
+                                ))
+                                .await
+                                .map_err(ScheduleError::Create)?;
+                            return Ok(());
+                        }
+                        None => String::from(config.admin_text("schedule_no_target", MESSAGE_NO_TARGET)),
+                    },
+                }
+            }
+            None => String::from(config.admin_text("schedule_no_reply", MESSAGE_NO_REPLY)),
+        },
+        _ => String::from(config.admin_text("schedule_usage", MESSAGE_USAGE)),
+    };
+    api.execute(
+        SendMessage::new(chat_id, text)
+            .parse_mode(ParseMode::Html)
+            .reply_to_message_id(message.id),
+    )
+    .await
+    .map_err(ScheduleError::SendMessage)?;
+    Ok(())
+}
+
+async fn create(
+    config: &Config,
+    schedule_service: &ScheduleService,
+    message: ScheduledMessage,
+) -> Result<String, ScheduleError> {
+    let id = schedule_service.create(message).await.map_err(ScheduleError::Create)?;
+    Ok(format!(
+        "{} (ID: <code>{}</code>)",
+        config.admin_text("ok", MESSAGE_OK),
+        id
+    ))
+}
+
+pub async fn is_reply(schedule_service: Ref<ScheduleService>, message: Message) -> Result<bool, ScheduleServiceError> {
+    match message.reply_to {
+        Some(ref reply_to) => {
+            schedule_service
+                .is_awaiting_reply(message.get_chat_id(), reply_to.id)
+                .await
+        }
+        None => Ok(false),
+    }
+}
+
+pub async fn handle_reply(
+    api: Ref<Api>,
+    config: Ref<Config>,
+    conversation_service: Ref<ConversationService>,
+    schedule_service: Ref<ScheduleService>,
+    sender: Sender,
+    chat_id: ChatId,
+    message: Message,
+) -> Result<(), ScheduleError> {
+    if !sender
+        .require(Role::Agent, "schedule messages", &message)
+        .await
+        .map_err(ScheduleError::CheckRole)?
+    {
+        return Ok(());
+    }
+    let prompt_message_id = match message.reply_to {
+        Some(ref reply_to) => reply_to.id,
+        None => return Ok(()),
+    };
+    // the conversation could have been assigned to another admin since the prompt was sent
+    let subscriber_user_id = schedule_service
+        .find_prompt(message.get_chat_id(), prompt_message_id)
+        .await
+        .map_err(ScheduleError::FindPrompt)?
+        .and_then(|prompt| prompt.subscriber_user_id());
+    if let Some(subscriber_user_id) = subscriber_user_id {
+        if !check_assignee(&config, &conversation_service, &sender, subscriber_user_id)
+            .await
+            .map_err(ScheduleError::CheckAssignee)?
+        {
+            api.execute(
+                SendMessage::new(chat_id, config.admin_text("assigned", MESSAGE_ASSIGNED))
+                    .reply_to_message_id(message.id),
+            )
+            .await
+            .map_err(ScheduleError::SendMessage)?;
+            return Ok(());
+        }
+    }
+    let id = match schedule_service
+        .set_reply(
+            message.get_chat_id(),
+            prompt_message_id,
+            message.id,
+            message.get_user_id(),
+        )
+        .await
+        .map_err(ScheduleError::SetReply)?
+    {
+        Some(id) => id,
+        None => return Ok(()),
+    };
+    api.execute(
+        SendMessage::new(
+            chat_id,
+            format!("{} (ID: <code>{}</code>)", config.admin_text("ok", MESSAGE_OK), id),
+        )
+        .parse_mode(ParseMode::Html)
+        .reply_to_message_id(message.id),
+    )
+    .await
+    .map_err(ScheduleError::SendMessage)?;
+    Ok(())
+}
+
+#[derive(Debug)]
+pub enum ScheduleError {
+    CheckAssignee(MessageError),
+    CheckRole(SenderError),
+    Create(ScheduleServiceError),
+    Delete(ScheduleServiceError),
+    Find(ScheduleServiceError),
+    FindLink(MessageLinkServiceError),
+    FindPrompt(ScheduleServiceError),
+    GetList(ScheduleServiceError),
+    SendMessage(ExecuteError),
+    SetReply(ScheduleServiceError),
+}
+
+impl fmt::Display for ScheduleError {
+    fn fmt(&self, out: &mut fmt::Formatter) -> fmt::Result {
+        use self::ScheduleError::*;
+        match self {
+            CheckAssignee(err) => err.fmt(out),
+            CheckRole(err) => err.fmt(out),
+            Create(err) => err.fmt(out),
+            Delete(err) => err.fmt(out),
+            Find(err) => err.fmt(out),
+            FindLink(err) => err.fmt(out),
+            FindPrompt(err) => err.fmt(out),
+            GetList(err) => err.fmt(out),
+            SendMessage(err) => err.fmt(out),
+            SetReply(err) => err.fmt(out),
+        }
+    }
+}
+
+impl Error for ScheduleError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        use self::ScheduleError::*;
+        Some(match self {
+            CheckAssignee(err) => err,
+            CheckRole(err) => err,
+            Create(err) => err,
+            Delete(err) => err,
+            Find(err) => err,
+            FindLink(err) => err,
+            FindPrompt(err) => err,
+            GetList(err) => err,
+            SendMessage(err) => err,
+            SetReply(err) => err,
+        })
+    }
+}
+
+impl ErrorVariant for ScheduleError {
+    fn variant(&self) -> &'static str {
+        use self::ScheduleError::*;
+        match self {
+            CheckAssignee(_) => "CheckAssignee",
+            CheckRole(_) => "CheckRole",
+            Create(_) => "Create",
+            Delete(_) => "Delete",
+            Find(_) => "Find",
+            FindLink(_) => "FindLink",
+            FindPrompt(_) => "FindPrompt",
+            GetList(_) => "GetList",
+            SendMessage(_) => "SendMessage",
+            SetReply(_) => "SetReply",
+        }
+    }
+}
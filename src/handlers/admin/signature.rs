@@ -0,0 +1,103 @@
+use crate::{
+    api::Api,
+    config::{Config, Role},
+    handlers::admin::{
+        role::{Sender, SenderError},
+        MESSAGE_OK,
+    },
+    metrics::ErrorVariant,
+    services::{AdminService, AdminServiceError},
+};
+use carapax::{
+    methods::SendMessage,
+    types::{ChatId, Command},
+    ExecuteError, Ref,
+};
+use std::{error::Error, fmt};
+
+const MESSAGE_REMOVED: &str = "Signature removed";
+const MESSAGE_NO_USER: &str = "Could not identify the admin, anonymous admins can not have a signature";
+const MESSAGE_TOO_LONG: &str = "Signature is too long";
+
+const MAX_LENGTH: usize = 255;
+
+pub async fn handle(
+    api: Ref<Api>,
+    config: Ref<Config>,
+    admin_service: Ref<AdminService>,
+    sender: Sender,
+    chat_id: ChatId,
+    command: Command,
+) -> Result<(), SignatureError> {
+    let message = command.get_message();
+    if !sender
+        .require(Role::Agent, "set signatures", message)
+        .await
+        .map_err(SignatureError::CheckRole)?
+    {
+        return Ok(());
+    }
+    let signature = command.get_args().join(" ");
+    let text = match message.get_user_id() {
+        Some(_) if signature.chars().count() > MAX_LENGTH => config.admin_text("signature_too_long", MESSAGE_TOO_LONG),
+        Some(user_id) if signature.is_empty() => {
+            admin_service
+                .set_signature(user_id, None)
+                .await
+                .map_err(SignatureError::SetSignature)?;
+            config.admin_text("signature_removed", MESSAGE_REMOVED)
+        }
+        Some(user_id) => {
+            admin_service
+                .set_signature(user_id, Some(&signature))
+                .await
+                .map_err(SignatureError::SetSignature)?;
+            config.admin_text("ok", MESSAGE_OK)
+        }
+        None => config.admin_text("signature_no_user", MESSAGE_NO_USER),
+    };
+    api.execute(SendMessage::new(chat_id, text).reply_to_message_id(message.id))
+        .await
+        .map_err(SignatureError::SendMessage)?;
+    Ok(())
+}
+
+#[derive(Debug)]
+pub enum SignatureError {
+    CheckRole(SenderError),
+    SendMessage(ExecuteError),
+    SetSignature(AdminServiceError),
+}
+
+impl fmt::Display for SignatureError {
+    fn fmt(&self, out: &mut fmt::Formatter) -> fmt::Result {
+        use self::SignatureError::*;
+        match self {
+            CheckRole(err) => err.fmt(out),
+            SendMessage(err) => err.fmt(out),
+            SetSignature(err) => err.fmt(out),
+        }
+    }
+}
+
+impl Error for SignatureError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        use self::SignatureError::*;
+        Some(match self {
+            CheckRole(err) => err,
+            SendMessage(err) => err,
+            SetSignature(err) => err,
+        })
+    }
+}
+
+impl ErrorVariant for SignatureError {
+    fn variant(&self) -> &'static str {
+        use self::SignatureError::*;
+        match self {
+            CheckRole(_) => "CheckRole",
+            SendMessage(_) => "SendMessage",
+            SetSignature(_) => "SetSignature",
+        }
+    }
+}
@@ -0,0 +1,289 @@
+use crate::{
+    api::Api,
+    config::{Config, Role},
+    handlers::{
+        admin::{
+            message::{
+                check_assignee, request_confirmation, send_reply, MessageError, Reply, ReplyTarget, MESSAGE_ASSIGNED,
+            },
+            role::{Sender, SenderError},
+            MESSAGE_NOT_FOUND, MESSAGE_OK,
+        },
+        receipt::{report_delivery, ReceiptError},
+    },
+    metrics::ErrorVariant,
+    services::{
+        AdminService, ConversationService, ConversationServiceError, MessageLinkDirection, MessageLinkService,
+        MessageLinkServiceError, PendingReply, PendingReplyService, Snippet, SnippetService, SnippetServiceError,
+    },
+};
+use carapax::{
+    methods::SendMessage,
+    types::{ChatId, Command, Message},
+    ExecuteError, Ref,
+};
+use chrono::Utc;
+use futures_util::future::OptionFuture;
+use std::{error::Error, fmt};
+
+const MESSAGE_EMPTY: &str = "No snippets";
+const MESSAGE_INVALID_NAME: &str = "Invalid snippet name";
+const MESSAGE_NO_REPLY: &str = "Reply to a message to save it as a snippet";
+const MESSAGE_USAGE: &str = "Usage: /snippet add <name> | /snippet list | /snippet del <name>";
+
+const CALL_PREFIX: char = '!';
+// the length of the name column
+const MAX_NAME_LENGTH: usize = 255;
+
+pub async fn handle(
+    api: Ref<Api>,
+    config: Ref<Config>,
+    snippet_service: Ref<SnippetService>,
+    sender: Sender,
+    chat_id: ChatId,
+    command: Command,
+) -> Result<(), SnippetError> {
+    let message = command.get_message();
+    if !sender
+        .require(Role::Agent, "manage snippets", message)
+        .await
+        .map_err(SnippetError::CheckRole)?
+    {
+        return Ok(());
+    }
+    let args = command.get_args();
+    let text = match (args.first().map(String::as_str), args.get(1)) {
+        (Some("add"), Some(name)) => {
+            if !is_valid_name(name) {
+                String::from(config.admin_text("snippet_invalid_name", MESSAGE_INVALID_NAME))
+            } else if let Some(ref reply_to) = message.reply_to {
+                snippet_service
+                    .save(Snippet::new(name, reply_to.get_chat_id(), reply_to.id))
+                    .await
+                    .map_err(SnippetError::Save)?;
+                String::from(config.admin_text("ok", MESSAGE_OK))
+            } else {
+                String::from(config.admin_text("snippet_no_reply", MESSAGE_NO_REPLY))
+            }
+        }
+        (Some("del"), Some(name)) => {
+            if snippet_service.delete(name).await.map_err(SnippetError::Delete)? {
+                String::from(config.admin_text("ok", MESSAGE_OK))
+            } else {
+                String::from(config.admin_text("not_found", MESSAGE_NOT_FOUND))
+            }
+        }
+        (Some("list"), None) => {
+            let snippets = snippet_service.get_list().await.map_err(SnippetError::GetList)?;
+            if snippets.is_empty() {
+                String::from(config.admin_text("snippet_empty", MESSAGE_EMPTY))
+            } else {
+                snippets
+                    .iter()
+                    .map(|snippet| format!("{}{}", CALL_PREFIX, snippet.name()))
+                    .collect::<Vec<String>>()
+                    .join("\n")
+            }
+        }
+        _ => String::from(config.admin_text("snippet_usage", MESSAGE_USAGE)),
+    };
+    api.execute(SendMessage::new(chat_id, text).reply_to_message_id(message.id))
+        .await
+        .map_err(SnippetError::SendMessage)?;
+    Ok(())
+}
+
+// a reply which does not name an existing snippet is sent to the subscriber as is
+pub async fn is_call(snippet_service: Ref<SnippetService>, message: Message) -> Result<bool, SnippetServiceError> {
+    Ok(match (message.reply_to.as_ref(), get_call_name(&message)) {
+        (Some(_), Some(name)) => snippet_service.find(name).await?.is_some(),
+        _ => false,
+    })
+}
+
+#[allow(clippy::too_many_arguments)]
+pub async fn handle_call(
+    api: Ref<Api>,
+    config: Ref<Config>,
+    admin_service: Ref<AdminService>,
+    conversation_service: Ref<ConversationService>,
+    message_link_service: Ref<MessageLinkService>,
+    pending_reply_service: Ref<PendingReplyService>,
+    snippet_service: Ref<SnippetService>,
+    sender: Sender,
+    chat_id: ChatId,
+    message: Message,
+) -> Result<(), SnippetError> {
+    if !sender
+        .require(Role::Agent, "reply with snippets", &message)
+        .await
+        .map_err(SnippetError::CheckRole)?
+    {
+        return Ok(());
+    }
+    let snippet = match get_call_name(&message) {
+        Some(name) => snippet_service.find(name).await.map_err(SnippetError::Find)?,
+        None => None,
+    };
+    let link =
+        OptionFuture::from(message.reply_to.as_ref().map(|reply_to| {
+            message_link_service.find(reply_to.get_chat_id(), reply_to.id, MessageLinkDirection::Admin)
+        }))
+        .await
+        .transpose()
+        .map_err(SnippetError::FindLink)?
+        .flatten();
+    match (snippet, link) {
+        (Some(_), Some(link))
+            if !check_assignee(&config, &conversation_service, &sender, link.subscriber_user_id())
+                .await
+                .map_err(SnippetError::Reply)? =>
+        {
+            api.execute(
+                SendMessage::new(chat_id, config.admin_text("assigned", MESSAGE_ASSIGNED))
+                    .reply_to_message_id(message.id),
+            )
+            .await
+            .map_err(SnippetError::SendMessage)?;
+        }
+        (Some(snippet), Some(link)) => match config.confirm_replies {
+            Some(ref confirm_replies) => {
+                let pending_reply = PendingReply::new_snippet(
+                    message.get_chat_id(),
+                    message.id,
+                    sender.user_id(),
+                    link.admin_message_id(),
+                    snippet.chat_id(),
+                    snippet.message_id(),
+                    Utc::now().naive_utc() + confirm_replies.timeout(),
+                );
+                request_confirmation(&api, &config, &pending_reply_service, pending_reply)
+                    .await
+                    .map_err(SnippetError::Reply)?;
+            }
+            None => {
+                let result = send_reply(
+                    &api,
+                    &config,
+                    &admin_service,
+                    &message_link_service,
+                    &ReplyTarget::from(&link),
+                    Reply::Stored {
+                        chat_id: snippet.chat_id(),
+                        message_id: snippet.message_id(),
+                        admin_chat_id: message.get_chat_id(),
+                        admin_message_id: message.id,
+                    },
+                    message.get_user_id(),
+                )
+                .await;
+                report_delivery(
+                    &api,
+                    &config,
+                    &conversation_service,
+                    &message_link_service,
+                    &link,
+                    (message.get_chat_id(), message.id),
+                    &result,
+                )
+                .await
+                .map_err(SnippetError::Receipt)?;
+                result.map_err(SnippetError::Reply)?;
+                conversation_service
+                    .mark_answered(link.subscriber_user_id(), message.get_user_id())
+                    .await
+                    .map_err(SnippetError::MarkAnswered)?;
+            }
+        },
+        _ => {
+            api.execute(
+                SendMessage::new(chat_id, config.admin_text("not_found", MESSAGE_NOT_FOUND))
+                    .reply_to_message_id(message.id),
+            )
+            .await
+            .map_err(SnippetError::SendMessage)?;
+        }
+    }
+    Ok(())
+}
+
+fn get_call_name(message: &Message) -> Option<&str> {
+    message
+        .get_text()
+        .and_then(|text| text.data.trim().strip_prefix(CALL_PREFIX))
+        .filter(|name| is_valid_name(name))
+}
+
+fn is_valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.chars().count() <= MAX_NAME_LENGTH
+        && name.chars().all(|c| c.is_alphanumeric() || c == '_' || c == '-')
+}
+
+#[derive(Debug)]
+pub enum SnippetError {
+    CheckRole(SenderError),
+    Delete(SnippetServiceError),
+    Find(SnippetServiceError),
+    FindLink(MessageLinkServiceError),
+    GetList(SnippetServiceError),
+    MarkAnswered(ConversationServiceError),
+    Receipt(ReceiptError),
+    Reply(MessageError),
+    Save(SnippetServiceError),
+    SendMessage(ExecuteError),
+}
+
+impl fmt::Display for SnippetError {
+    fn fmt(&self, out: &mut fmt::Formatter) -> fmt::Result {
+        use self::SnippetError::*;
+        match self {
+            CheckRole(err) => err.fmt(out),
+            Delete(err) => err.fmt(out),
+            Find(err) => err.fmt(out),
+            FindLink(err) => err.fmt(out),
+            GetList(err) => err.fmt(out),
+            MarkAnswered(err) => err.fmt(out),
+            Receipt(err) => err.fmt(out),
+            Reply(err) => err.fmt(out),
+            Save(err) => err.fmt(out),
+            SendMessage(err) => err.fmt(out),
+        }
+    }
+}
+
+impl Error for SnippetError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        use self::SnippetError::*;
+        Some(match self {
+            CheckRole(err) => err,
+            Delete(err) => err,
+            Find(err) => err,
+            FindLink(err) => err,
+            GetList(err) => err,
+            MarkAnswered(err) => err,
+            Receipt(err) => err,
+            Reply(err) => err,
+            Save(err) => err,
+            SendMessage(err) => err,
+        })
+    }
+}
+
+impl ErrorVariant for SnippetError {
+    fn variant(&self) -> &'static str {
+        use self::SnippetError::*;
+        match self {
+            CheckRole(_) => "CheckRole",
+            Delete(_) => "Delete",
+            Find(_) => "Find",
+            FindLink(_) => "FindLink",
+            GetList(_) => "GetList",
+            MarkAnswered(_) => "MarkAnswered",
+            Receipt(_) => "Receipt",
+            Reply(_) => "Reply",
+            Save(_) => "Save",
+            SendMessage(_) => "SendMessage",
+        }
+    }
+}
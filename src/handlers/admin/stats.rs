@@ -0,0 +1,173 @@
+use crate::{
+    api::Api,
+    config::{Config, Role},
+    handlers::admin::role::{Sender, SenderError},
+    metrics::ErrorVariant,
+    services::{
+        ConversationService, ConversationServiceError, RatingService, RatingServiceError, UserService, UserServiceError,
+    },
+};
+use carapax::{
+    methods::SendMessage,
+    types::{ChatId, Command},
+    ExecuteError, Ref,
+};
+use chrono::{Duration, Utc};
+use std::{error::Error, fmt};
+
+const MESSAGE_USAGE: &str = "Usage: /stats [days] [source:<source>]";
+const MESSAGE_NO_RATINGS: &str = "No ratings yet";
+const MESSAGE_CLOSED: &str = "Closed conversations: {count}";
+const MESSAGE_NEW_USERS: &str = "New users via {source}: {count}";
+const MESSAGE_RATINGS: &str = "Ratings: {count}, average {average}";
+const MESSAGE_UNKNOWN_ADMIN: &str = "Unknown admin";
+
+const SOURCE_PREFIX: &str = "source:";
+
+#[allow(clippy::too_many_arguments)]
+pub async fn handle(
+    api: Ref<Api>,
+    config: Ref<Config>,
+    conversation_service: Ref<ConversationService>,
+    rating_service: Ref<RatingService>,
+    user_service: Ref<UserService>,
+    sender: Sender,
+    chat_id: ChatId,
+    command: Command,
+) -> Result<(), StatsError> {
+    let message = command.get_message();
+    if !sender
+        .require(Role::Supervisor, "view stats", message)
+        .await
+        .map_err(StatsError::CheckRole)?
+    {
+        return Ok(());
+    }
+    let mut days = None;
+    let mut source = None;
+    for arg in command.get_args() {
+        match arg.strip_prefix(SOURCE_PREFIX) {
+            Some(value) => source = Some(value),
+            None => days = Some(arg.parse::<i64>()),
+        }
+    }
+    let since = match days {
+        None => None,
+        Some(Ok(days)) if days > 0 => Some(Utc::now().naive_utc() - Duration::days(days)),
+        Some(_) => {
+            api.execute(
+                SendMessage::new(chat_id, config.admin_text("stats_usage", MESSAGE_USAGE))
+                    .reply_to_message_id(message.id),
+            )
+            .await
+            .map_err(StatsError::SendMessage)?;
+            return Ok(());
+        }
+    };
+    let closed = conversation_service
+        .count_closed(since, source)
+        .await
+        .map_err(StatsError::CountClosed)?;
+    let stats = rating_service
+        .get_stats(since, source)
+        .await
+        .map_err(StatsError::GetStats)?;
+    let mut lines = Vec::new();
+    if let Some(source) = source {
+        let users = user_service
+            .count_by_source(source, since)
+            .await
+            .map_err(StatsError::CountUsers)?;
+        lines.push(
+            config
+                .admin_text("stats_new_users", MESSAGE_NEW_USERS)
+                .replace("{source}", source)
+                .replace("{count}", &users.to_string()),
+        );
+    }
+    lines.push(
+        config
+            .admin_text("stats_closed", MESSAGE_CLOSED)
+            .replace("{count}", &closed.to_string()),
+    );
+    if stats.is_empty() {
+        lines.push(String::from(config.admin_text("stats_no_ratings", MESSAGE_NO_RATINGS)));
+    } else {
+        let count: i64 = stats.iter().map(|item| item.count()).sum();
+        let total: f64 = stats.iter().map(|item| item.average() * item.count() as f64).sum();
+        lines.push(
+            config
+                .admin_text("stats_ratings", MESSAGE_RATINGS)
+                .replace("{count}", &count.to_string())
+                .replace("{average}", &format!("{:.2}", total / count as f64)),
+        );
+        for item in stats {
+            let admin = match item.admin_user_id() {
+                Some(user_id) => user_service
+                    .get(user_id)
+                    .await
+                    .map_err(StatsError::GetAdmin)?
+                    .map(|user| user.get_full_name())
+                    .unwrap_or_else(|| user_id.to_string()),
+                None => String::from(config.admin_text("stats_unknown_admin", MESSAGE_UNKNOWN_ADMIN)),
+            };
+            lines.push(format!("{}: {:.2} ({})", admin, item.average(), item.count()));
+        }
+    }
+    api.execute(SendMessage::new(chat_id, lines.join("\n")).reply_to_message_id(message.id))
+        .await
+        .map_err(StatsError::SendMessage)?;
+    Ok(())
+}
+
+#[derive(Debug)]
+pub enum StatsError {
+    CheckRole(SenderError),
+    CountClosed(ConversationServiceError),
+    CountUsers(UserServiceError),
+    GetAdmin(UserServiceError),
+    GetStats(RatingServiceError),
+    SendMessage(ExecuteError),
+}
+
+impl fmt::Display for StatsError {
+    fn fmt(&self, out: &mut fmt::Formatter) -> fmt::Result {
+        use self::StatsError::*;
+        match self {
+            CheckRole(err) => err.fmt(out),
+            CountClosed(err) => err.fmt(out),
+            CountUsers(err) => err.fmt(out),
+            GetAdmin(err) => err.fmt(out),
+            GetStats(err) => err.fmt(out),
+            SendMessage(err) => err.fmt(out),
+        }
+    }
+}
+
+impl Error for StatsError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        use self::StatsError::*;
+        Some(match self {
+            CheckRole(err) => err,
+            CountClosed(err) => err,
+            CountUsers(err) => err,
+            GetAdmin(err) => err,
+            GetStats(err) => err,
+            SendMessage(err) => err,
+        })
+    }
+}
+
+impl ErrorVariant for StatsError {
+    fn variant(&self) -> &'static str {
+        use self::StatsError::*;
+        match self {
+            CheckRole(_) => "CheckRole",
+            CountClosed(_) => "CountClosed",
+            CountUsers(_) => "CountUsers",
+            GetAdmin(_) => "GetAdmin",
+            GetStats(_) => "GetStats",
+            SendMessage(_) => "SendMessage",
+        }
+    }
+}
@@ -1,40 +1,61 @@
-use crate::services::{UserService, UserServiceError};
+use crate::{
+    api::Api,
+    config::{Config, Role},
+    handlers::admin::{
+        role::{Sender, SenderError},
+        MESSAGE_INVALID_USER_ID, MESSAGE_NOT_FOUND, MESSAGE_OK, MESSAGE_USER_ID_REQUIRED,
+    },
+    metrics::ErrorVariant,
+    services::{UserService, UserServiceError},
+};
 use carapax::{
     methods::SendMessage,
     types::{ChatId, Command, Integer},
-    Api, ExecuteError, Ref,
+    ExecuteError, Ref,
 };
 use std::{error::Error, fmt};
 
-const MESSAGE_OK: &str = "OK";
-const MESSAGE_NOT_FOUND: &str = "Not found";
-
 pub async fn handle(
     api: Ref<Api>,
+    config: Ref<Config>,
     user_service: Ref<UserService>,
+    sender: Sender,
     chat_id: ChatId,
     command: Command,
 ) -> Result<(), UnblockError> {
+    if !sender
+        .require(Role::Supervisor, "unblock users", command.get_message())
+        .await
+        .map_err(UnblockError::CheckRole)?
+    {
+        return Ok(());
+    }
     let message_id = command.get_message().id;
     let user_id = match command.get_args().first().map(|arg| arg.parse::<Integer>()) {
         Some(Ok(value)) => value,
         Some(Err(_)) => {
-            api.execute(SendMessage::new(chat_id, "Invalid User ID").reply_to_message_id(message_id))
-                .await
-                .map_err(UnblockError::SendMessage)?;
+            api.execute(
+                SendMessage::new(chat_id, config.admin_text("invalid_user_id", MESSAGE_INVALID_USER_ID))
+                    .reply_to_message_id(message_id),
+            )
+            .await
+            .map_err(UnblockError::SendMessage)?;
             return Ok(());
         }
         None => {
-            api.execute(SendMessage::new(chat_id, "User ID is required").reply_to_message_id(message_id))
-                .await
-                .map_err(UnblockError::SendMessage)?;
+            api.execute(
+                SendMessage::new(chat_id, config.admin_text("user_id_required", MESSAGE_USER_ID_REQUIRED))
+                    .reply_to_message_id(message_id),
+            )
+            .await
+            .map_err(UnblockError::SendMessage)?;
             return Ok(());
         }
     };
     let text = if user_service.unblock(user_id).await.map_err(UnblockError::SetBlock)? {
-        MESSAGE_OK
+        config.admin_text("ok", MESSAGE_OK)
     } else {
-        MESSAGE_NOT_FOUND
+        config.admin_text("not_found", MESSAGE_NOT_FOUND)
     };
     api.execute(SendMessage::new(chat_id, text).reply_to_message_id(message_id))
         .await
@@ -44,6 +65,7 @@ pub async fn handle(
 
 #[derive(Debug)]
 pub enum UnblockError {
+    CheckRole(SenderError),
     SendMessage(ExecuteError),
     SetBlock(UserServiceError),
 }
@@ -52,6 +74,7 @@ impl fmt::Display for UnblockError {
     fn fmt(&self, out: &mut fmt::Formatter) -> fmt::Result {
         use self::UnblockError::*;
         match self {
+            CheckRole(err) => err.fmt(out),
             SendMessage(err) => err.fmt(out),
             SetBlock(err) => err.fmt(out),
         }
@@ -62,8 +85,20 @@ impl Error for UnblockError {
     fn source(&self) -> Option<&(dyn Error + 'static)> {
         use self::UnblockError::*;
         Some(match self {
+            CheckRole(err) => err,
             SendMessage(err) => err,
             SetBlock(err) => err,
         })
     }
 }
+
+impl ErrorVariant for UnblockError {
+    fn variant(&self) -> &'static str {
+        use self::UnblockError::*;
+        match self {
+            CheckRole(_) => "CheckRole",
+            SendMessage(_) => "SendMessage",
+            SetBlock(_) => "SetBlock",
+        }
+    }
+}
@@ -1,23 +1,52 @@
-use crate::services::{UserBlockFilter, UserInfoList, UserService, UserServiceError};
+use crate::{
+    api::Api,
+    config::{Config, Role},
+    handlers::admin::role::{Sender, SenderError, MESSAGE_PERMISSION_DENIED},
+    metrics::ErrorVariant,
+    services::{is_valid_source, UserBlockFilter, UserBlockFilterError, UserInfoList, UserService, UserServiceError},
+};
 use carapax::{
     methods::{AnswerCallbackQuery, EditMessageText, SendMessage},
     types::{
         CallbackQuery, CallbackQueryError, ChatId, Command, InlineKeyboardButton, InlineKeyboardError, Integer,
         ParseMode,
     },
-    Api, ExecuteError, HandlerInput, Ref, TryFromInput,
+    ExecuteError, HandlerInput, Ref, TryFromInput,
 };
 use futures_util::future::BoxFuture;
 use serde::{Deserialize, Serialize};
 use std::{error::Error, fmt};
 
+const SOURCE_PREFIX: &str = "source:";
+// the source is a part of callback data of page buttons, which is limited to 64 bytes
+const MAX_FILTER_SOURCE_LENGTH: usize = 32;
+const MESSAGE_INVALID_SOURCE: &str = "Invalid source, use up to {max} characters: A-Z, a-z, 0-9, _ and -";
+
 pub async fn handle_list(
     api: Ref<Api>,
+    config: Ref<Config>,
     user_service: Ref<UserService>,
+    sender: Sender,
     chat_id: ChatId,
     command: Command,
 ) -> Result<(), UsersError> {
-    let block_filter: UserBlockFilter = match command.get_args().first().try_into() {
+    if !sender
+        .require(Role::Viewer, "list users", command.get_message())
+        .await
+        .map_err(UsersError::CheckRole)?
+    {
+        return Ok(());
+    }
+    let mut block_filter = None;
+    let mut source = None;
+    for arg in command.get_args() {
+        match arg.strip_prefix(SOURCE_PREFIX) {
+            Some(value) => source = Some(value.to_string()),
+            None => block_filter = Some(arg),
+        }
+    }
+    let block_filter: Result<UserBlockFilter, UserBlockFilterError> = block_filter.try_into();
+    let block_filter = match block_filter {
         Ok(value) => value,
         Err(err) => {
             api.execute(SendMessage::new(chat_id, err.to_string()))
@@ -26,8 +55,19 @@ pub async fn handle_list(
             return Ok(());
         }
     };
+    if let Some(ref source) = source {
+        if !is_valid_source(source) || source.len() > MAX_FILTER_SOURCE_LENGTH {
+            let text = config
+                .admin_text("users_invalid_source", MESSAGE_INVALID_SOURCE)
+                .replace("{max}", &MAX_FILTER_SOURCE_LENGTH.to_string());
+            api.execute(SendMessage::new(chat_id, text))
+                .await
+                .map_err(UsersError::SendMessage)?;
+            return Ok(());
+        }
+    }
     let users = user_service
-        .get_list(1, block_filter)
+        .get_list(1, block_filter, source)
         .await
         .map_err(UsersError::GetList)?;
     let keyboard = build_keyboard(&users).map_err(UsersError::BuildKeyboard)?;
@@ -43,11 +83,25 @@ pub async fn handle_list(
 
 pub async fn handle_page_changed(
     api: Ref<Api>,
+    config: Ref<Config>,
     user_service: Ref<UserService>,
+    sender: Sender,
     query: PageQuery,
 ) -> Result<(), UsersError> {
+    if !sender
+        .is_allowed(Role::Viewer, "list users")
+        .await
+        .map_err(UsersError::CheckRole)?
+    {
+        api.execute(
+            AnswerCallbackQuery::new(query.id).text(config.admin_text("permission_denied", MESSAGE_PERMISSION_DENIED)),
+        )
+        .await
+        .map_err(UsersError::AnswerCallbackQuery)?;
+        return Ok(());
+    }
     let users = user_service
-        .get_list(query.number, query.block_filter)
+        .get_list(query.number, query.block_filter, query.source)
         .await
         .map_err(UsersError::GetList)?;
     let keyboard = build_keyboard(&users).map_err(UsersError::BuildKeyboard)?;
@@ -70,12 +124,14 @@ fn build_keyboard(list: &UserInfoList) -> Result<Vec<Vec<InlineKeyboardButton>>,
     let total_pages = list.total_pages();
     let total_items = list.total_items();
     let block_filter = list.block_filter();
+    let source = list.source().map(String::from);
     if current_page != 1 {
         row.push(InlineKeyboardButton::with_callback_data_struct(
             "<<",
             &Page {
                 number: 1,
                 block_filter,
+                source: source.clone(),
             },
         )?)
     }
@@ -85,6 +141,7 @@ fn build_keyboard(list: &UserInfoList) -> Result<Vec<Vec<InlineKeyboardButton>>,
             &Page {
                 number: current_page - 1,
                 block_filter,
+                source: source.clone(),
             },
         )?);
     }
@@ -93,6 +150,7 @@ fn build_keyboard(list: &UserInfoList) -> Result<Vec<Vec<InlineKeyboardButton>>,
         &Page {
             number: current_page,
             block_filter,
+            source: source.clone(),
         },
     )?);
     if current_page < total_pages - 1 {
@@ -101,6 +159,7 @@ fn build_keyboard(list: &UserInfoList) -> Result<Vec<Vec<InlineKeyboardButton>>,
             &Page {
                 number: current_page + 1,
                 block_filter,
+                source: source.clone(),
             },
         )?);
     }
@@ -110,16 +169,22 @@ fn build_keyboard(list: &UserInfoList) -> Result<Vec<Vec<InlineKeyboardButton>>,
             &Page {
                 number: total_pages,
                 block_filter,
+                source: source.clone(),
             },
         )?)
     }
     Ok(vec![row])
 }
 
+// short names keep callback data within 64 bytes
 #[derive(Serialize, Deserialize)]
 struct Page {
+    #[serde(rename = "n")]
     number: i64,
+    #[serde(rename = "b")]
     block_filter: UserBlockFilter,
+    #[serde(rename = "s", default, skip_serializing_if = "Option::is_none")]
+    source: Option<String>,
 }
 
 pub struct PageQuery {
@@ -128,13 +193,18 @@ pub struct PageQuery {
     message_id: Integer,
     number: i64,
     block_filter: UserBlockFilter,
+    source: Option<String>,
 }
 
 impl TryFrom<CallbackQuery> for PageQuery {
     type Error = PageQueryError;
 
     fn try_from(query: CallbackQuery) -> Result<Self, Self::Error> {
-        let Page { number, block_filter } = query
+        let Page {
+            number,
+            block_filter,
+            source,
+        } = query
             .parse_data()
             .map_err(PageQueryError::ParseData)
             .and_then(|page: Option<Page>| page.ok_or(PageQueryError::NoData))?;
@@ -145,6 +215,7 @@ impl TryFrom<CallbackQuery> for PageQuery {
             message_id: message.id,
             number,
             block_filter,
+            source,
         })
     }
 }
@@ -199,6 +270,7 @@ impl TryFromInput for PageQuery {
 pub enum UsersError {
     AnswerCallbackQuery(ExecuteError),
     BuildKeyboard(InlineKeyboardError),
+    CheckRole(SenderError),
     GetList(UserServiceError),
     SendMessage(ExecuteError),
 }
@@ -209,6 +281,7 @@ impl fmt::Display for UsersError {
         match self {
             AnswerCallbackQuery(err) => err.fmt(out),
             BuildKeyboard(err) => write!(out, "could not build inline keyboard: {}", err),
+            CheckRole(err) => err.fmt(out),
             GetList(err) => err.fmt(out),
             SendMessage(err) => err.fmt(out),
         }
@@ -221,8 +294,22 @@ impl Error for UsersError {
         Some(match self {
             AnswerCallbackQuery(err) => err,
             BuildKeyboard(err) => err,
+            CheckRole(err) => err,
             GetList(err) => err,
             SendMessage(err) => err,
         })
     }
 }
+
+impl ErrorVariant for UsersError {
+    fn variant(&self) -> &'static str {
+        use self::UsersError::*;
+        match self {
+            AnswerCallbackQuery(_) => "AnswerCallbackQuery",
+            BuildKeyboard(_) => "BuildKeyboard",
+            CheckRole(_) => "CheckRole",
+            GetList(_) => "GetList",
+            SendMessage(_) => "SendMessage",
+        }
+    }
+}
@@ -0,0 +1,127 @@
+use crate::{
+    api::Api,
+    config::{Config, Role},
+    handlers::{
+        admin::{
+            role::{Sender, SenderError},
+            MESSAGE_INVALID_USER_ID, MESSAGE_NOT_FOUND, MESSAGE_OK, MESSAGE_USER_ID_REQUIRED,
+        },
+        subscriber::{forward_held_messages, CaptchaError, Pipeline},
+    },
+    metrics::ErrorVariant,
+    services::{UserService, UserServiceError, VerificationService, VerificationServiceError},
+};
+use carapax::{
+    methods::SendMessage,
+    types::{ChatId, Command, Integer},
+    ExecuteError, Ref,
+};
+use std::{error::Error, fmt};
+
+#[allow(clippy::too_many_arguments)]
+pub async fn handle(
+    api: Ref<Api>,
+    config: Ref<Config>,
+    pipeline: Pipeline,
+    user_service: Ref<UserService>,
+    verification_service: Ref<VerificationService>,
+    sender: Sender,
+    chat_id: ChatId,
+    command: Command,
+) -> Result<(), VerifyError> {
+    if !sender
+        .require(Role::Supervisor, "verify users", command.get_message())
+        .await
+        .map_err(VerifyError::CheckRole)?
+    {
+        return Ok(());
+    }
+    let message_id = command.get_message().id;
+    let user_id = match command.get_args().first().map(|arg| arg.parse::<Integer>()) {
+        Some(Ok(value)) => value,
+        Some(Err(_)) => {
+            api.execute(
+                SendMessage::new(chat_id, config.admin_text("invalid_user_id", MESSAGE_INVALID_USER_ID))
+                    .reply_to_message_id(message_id),
+            )
+            .await
+            .map_err(VerifyError::SendMessage)?;
+            return Ok(());
+        }
+        None => {
+            api.execute(
+                SendMessage::new(chat_id, config.admin_text("user_id_required", MESSAGE_USER_ID_REQUIRED))
+                    .reply_to_message_id(message_id),
+            )
+            .await
+            .map_err(VerifyError::SendMessage)?;
+            return Ok(());
+        }
+    };
+    let text = if verification_service
+        .verify(user_id)
+        .await
+        .map_err(VerifyError::Verify)?
+    {
+        if let Some(user) = user_service.get(user_id).await.map_err(VerifyError::GetUser)? {
+            forward_held_messages(&pipeline, &verification_service, &user)
+                .await
+                .map_err(VerifyError::ForwardHeldMessages)?;
+        }
+        config.admin_text("ok", MESSAGE_OK)
+    } else {
+        config.admin_text("not_found", MESSAGE_NOT_FOUND)
+    };
+    api.execute(SendMessage::new(chat_id, text).reply_to_message_id(message_id))
+        .await
+        .map_err(VerifyError::SendMessage)?;
+    Ok(())
+}
+
+#[derive(Debug)]
+pub enum VerifyError {
+    CheckRole(SenderError),
+    ForwardHeldMessages(CaptchaError),
+    GetUser(UserServiceError),
+    SendMessage(ExecuteError),
+    Verify(VerificationServiceError),
+}
+
+impl fmt::Display for VerifyError {
+    fn fmt(&self, out: &mut fmt::Formatter) -> fmt::Result {
+        use self::VerifyError::*;
+        match self {
+            CheckRole(err) => err.fmt(out),
+            ForwardHeldMessages(err) => err.fmt(out),
+            GetUser(err) => err.fmt(out),
+            SendMessage(err) => err.fmt(out),
+            Verify(err) => err.fmt(out),
+        }
+    }
+}
+
+impl Error for VerifyError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        use self::VerifyError::*;
+        Some(match self {
+            CheckRole(err) => err,
+            ForwardHeldMessages(err) => err,
+            GetUser(err) => err,
+            SendMessage(err) => err,
+            Verify(err) => err,
+        })
+    }
+}
+
+impl ErrorVariant for VerifyError {
+    fn variant(&self) -> &'static str {
+        use self::VerifyError::*;
+        match self {
+            CheckRole(_) => "CheckRole",
+            ForwardHeldMessages(_) => "ForwardHeldMessages",
+            GetUser(_) => "GetUser",
+            SendMessage(_) => "SendMessage",
+            Verify(_) => "Verify",
+        }
+    }
+}
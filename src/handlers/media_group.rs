@@ -0,0 +1,164 @@
+use crate::api::Api;
+use carapax::{
+    methods::{CopyMessage, SendMediaGroup, SendMessage},
+    types::{
+        InputFile, InputMediaAudio, InputMediaDocument, InputMediaPhoto, InputMediaVideo, Integer, MediaGroup,
+        MediaGroupError as InvalidMediaGroupError, Message, MessageData, Text,
+    },
+    ExecuteError,
+};
+use std::{error::Error, fmt, time::Duration};
+
+pub const MEDIA_GROUP_DELAY: Duration = Duration::from_secs(1);
+
+pub async fn send(
+    api: &Api,
+    chat_id: Integer,
+    parts: &[Message],
+    reply_to_message_id: Option<Integer>,
+) -> Result<Vec<Integer>, MediaGroupError> {
+    if let Some(media_group) = build(parts) {
+        match send_group(api, chat_id, media_group, reply_to_message_id).await {
+            Ok(message_ids) => return Ok(message_ids),
+            Err(err) => log::warn!("Could not send media group, sending parts one by one: {}", err),
+        }
+    }
+    // a group of one part, with unsupported parts or rejected by Telegram is sent message by message
+    let mut result = Vec::new();
+    for part in parts {
+        let mut method = CopyMessage::new(chat_id, part.get_chat_id(), part.id);
+        // a caption is set explicitly, it could have been changed, e.g. signed
+        if let Some(caption) = get_caption(&part.data) {
+            method = method.caption(caption.data.clone());
+            if let Some(ref entities) = caption.entities {
+                method = method.caption_entities(entities.to_vec());
+            }
+        }
+        if let Some(reply_to_message_id) = reply_to_message_id {
+            method = method.reply_to_message_id(reply_to_message_id);
+        }
+        result.push(
+            api.execute(method)
+                .await
+                .map_err(MediaGroupError::CopyMessage)?
+                .message_id,
+        );
+    }
+    Ok(result)
+}
+
+async fn send_group(
+    api: &Api,
+    chat_id: Integer,
+    media_group: MediaGroup,
+    reply_to_message_id: Option<Integer>,
+) -> Result<Vec<Integer>, MediaGroupError> {
+    let mut method = SendMediaGroup::new(chat_id, media_group).map_err(MediaGroupError::Build)?;
+    if let Some(reply_to_message_id) = reply_to_message_id {
+        method = method.reply_to_message_id(reply_to_message_id);
+    }
+    Ok(api
+        .execute(method)
+        .await
+        .map_err(MediaGroupError::SendMediaGroup)?
+        .into_iter()
+        .map(|message| message.id)
+        .collect())
+}
+
+pub async fn report_error(api: &Api, parts: &[Message], text: &str) {
+    let first_part = match parts.first() {
+        Some(part) => part,
+        None => return,
+    };
+    if let Err(err) = api
+        .execute(SendMessage::new(first_part.get_chat_id(), text).reply_to_message_id(first_part.id))
+        .await
+    {
+        log::error!("Could not report media group error: {}", err);
+    }
+}
+
+pub fn get_caption(data: &MessageData) -> Option<&Text> {
+    match data {
+        MessageData::Audio { caption, .. }
+        | MessageData::Document { caption, .. }
+        | MessageData::Photo { caption, .. }
+        | MessageData::Video { caption, .. }
+        | MessageData::Voice { caption, .. } => caption.as_ref(),
+        _ => None,
+    }
+}
+
+macro_rules! with_caption {
+    ($info:expr, $caption:expr) => {
+        match $caption {
+            Some(Text { data, entities }) => {
+                let info = $info.caption(data.clone());
+                match entities {
+                    Some(entities) => info.caption_entities(entities.to_vec()),
+                    None => info,
+                }
+            }
+            None => $info,
+        }
+    };
+}
+
+fn build(parts: &[Message]) -> Option<MediaGroup> {
+    if parts.len() < 2 {
+        return None;
+    }
+    let mut media_group = MediaGroup::default();
+    for part in parts {
+        media_group = match part.data {
+            MessageData::Audio { ref caption, ref data } => media_group.add_item(
+                InputFile::file_id(data.file_id.clone()),
+                with_caption!(InputMediaAudio::default(), caption),
+            ),
+            MessageData::Document { ref caption, ref data } => media_group.add_item(
+                InputFile::file_id(data.file_id.clone()),
+                with_caption!(InputMediaDocument::default(), caption),
+            ),
+            MessageData::Photo { ref caption, ref data } => media_group.add_item(
+                InputFile::file_id(data.last()?.file_id.clone()),
+                with_caption!(InputMediaPhoto::default(), caption),
+            ),
+            MessageData::Video { ref caption, ref data } => media_group.add_item(
+                InputFile::file_id(data.file_id.clone()),
+                with_caption!(InputMediaVideo::default(), caption),
+            ),
+            _ => return None,
+        };
+    }
+    Some(media_group)
+}
+
+#[derive(Debug)]
+pub enum MediaGroupError {
+    Build(InvalidMediaGroupError),
+    CopyMessage(ExecuteError),
+    SendMediaGroup(ExecuteError),
+}
+
+impl fmt::Display for MediaGroupError {
+    fn fmt(&self, out: &mut fmt::Formatter) -> fmt::Result {
+        use self::MediaGroupError::*;
+        match self {
+            Build(err) => write!(out, "could not build media group: {}", err),
+            CopyMessage(err) => err.fmt(out),
+            SendMediaGroup(err) => err.fmt(out),
+        }
+    }
+}
+
+impl Error for MediaGroupError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        use self::MediaGroupError::*;
+        Some(match self {
+            Build(err) => err,
+            CopyMessage(err) => err,
+            SendMediaGroup(err) => err,
+        })
+    }
+}
@@ -1,8 +1,18 @@
-use crate::services::{UserService, UserServiceError};
-use carapax::{types::User, Chain, Ref};
+use crate::{
+    metrics::{self, TrackErrorsExt},
+    services::{UserService, UserServiceError},
+};
+use carapax::{
+    types::{Update, User},
+    Chain, Ref,
+};
 
 pub fn setup() -> Chain {
-    Chain::all().add(track_user)
+    Chain::all().add(track_update).add(track_user.track_errors())
+}
+
+async fn track_update(update: Update) {
+    metrics::record_update(&update);
 }
 
 async fn track_user(user_service: Ref<UserService>, user: User) -> Result<(), UserServiceError> {
@@ -1,3 +1,5 @@
 pub mod admin;
+pub mod media_group;
 pub mod middleware;
+pub mod receipt;
 pub mod subscriber;
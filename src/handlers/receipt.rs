@@ -0,0 +1,171 @@
+use crate::{
+    api::Api,
+    config::Config,
+    handlers::{
+        admin::MessageError,
+        subscriber::{build_button, get_subscriber_url},
+    },
+    services::{
+        ConversationService, ConversationServiceError, MessageLink, MessageLinkService, MessageLinkServiceError,
+    },
+};
+use carapax::{
+    methods::EditMessageReplyMarkup,
+    types::{InlineKeyboardButton, Integer},
+    ExecuteError,
+};
+use std::{error::Error, fmt};
+
+const RECEIPT_BLOCKED: &str = "⛔ Not delivered: the subscriber has blocked the bot";
+const RECEIPT_DELIVERED: &str = "✓ Delivered";
+const RECEIPT_FAILED: &str = "✗ Not delivered";
+const RECEIPT_SEEN: &str = "✓✓ Seen";
+
+/// Shows the delivery status of a reply under the subscriber message it answers
+///
+/// The reply is identified by its message in the admin chat,
+/// so that the status can be changed when the subscriber has seen it.
+pub async fn report_delivery(
+    api: &Api,
+    config: &Config,
+    conversation_service: &ConversationService,
+    message_link_service: &MessageLinkService,
+    link: &MessageLink,
+    (chat_id, message_id): (Integer, Integer),
+    result: &Result<(), MessageError>,
+) -> Result<(), ReceiptError> {
+    if !config.delivery_receipts {
+        return Ok(());
+    }
+    let failed = |err: &dyn fmt::Display| format!("{}: {}", config.admin_text("receipt_failed", RECEIPT_FAILED), err);
+    let status = match result {
+        // the reply has been sent, only the link is missing
+        Ok(()) | Err(MessageError::CreateLink(_)) => {
+            String::from(config.admin_text("receipt_delivered", RECEIPT_DELIVERED))
+        }
+        Err(MessageError::CopyMessage(err))
+        | Err(MessageError::FetchMessage(err))
+        | Err(MessageError::SendMessage(err)) => match err {
+            ExecuteError::Response(response) if response.error_code() == Some(403) => {
+                String::from(config.admin_text("receipt_blocked", RECEIPT_BLOCKED))
+            }
+            ExecuteError::Response(response) => failed(&response.description()),
+            err => failed(err),
+        },
+        Err(MessageError::SendMediaGroup(err)) => failed(err),
+        Err(_) => return Ok(()),
+    };
+    let (admin_chat_id, admin_message_id) = (link.admin_chat_id(), link.admin_message_id());
+    if show(
+        api,
+        conversation_service,
+        link.subscriber_user_id(),
+        admin_chat_id,
+        admin_message_id,
+        &status,
+    )
+    .await?
+        && result.is_ok()
+    {
+        message_link_service
+            .set_delivered(chat_id, message_id, admin_message_id)
+            .await
+            .map_err(ReceiptError::SetDelivered)?;
+    }
+    Ok(())
+}
+
+/// Changes the status of delivered replies when the subscriber writes again
+pub async fn mark_seen(
+    api: &Api,
+    config: &Config,
+    conversation_service: &ConversationService,
+    message_link_service: &MessageLinkService,
+    subscriber_user_id: Integer,
+) -> Result<(), ReceiptError> {
+    if !config.delivery_receipts {
+        return Ok(());
+    }
+    let mut receipts = message_link_service
+        .mark_seen(subscriber_user_id)
+        .await
+        .map_err(ReceiptError::MarkSeen)?;
+    // several replies can answer the same message
+    receipts.sort_unstable();
+    receipts.dedup();
+    let status = config.admin_text("receipt_seen", RECEIPT_SEEN);
+    for (admin_chat_id, admin_message_id) in receipts {
+        show(
+            api,
+            conversation_service,
+            subscriber_user_id,
+            admin_chat_id,
+            admin_message_id,
+            status,
+        )
+        .await?;
+    }
+    Ok(())
+}
+
+async fn show(
+    api: &Api,
+    conversation_service: &ConversationService,
+    subscriber_user_id: Integer,
+    admin_chat_id: Integer,
+    admin_message_id: Integer,
+    status: &str,
+) -> Result<bool, ReceiptError> {
+    let (subscriber, assignee) = match conversation_service
+        .get_participants(subscriber_user_id)
+        .await
+        .map_err(ReceiptError::GetParticipants)?
+    {
+        Some(participants) => participants,
+        None => return Ok(false),
+    };
+    let keyboard = vec![
+        vec![build_button(&subscriber, None, assignee.as_ref())],
+        vec![InlineKeyboardButton::with_url(status, get_subscriber_url(&subscriber))],
+    ];
+    // the answered message could be sent by an admin, it can not be edited then
+    match api
+        .execute(EditMessageReplyMarkup::new(admin_chat_id, admin_message_id).reply_markup(keyboard))
+        .await
+    {
+        Ok(_) => Ok(true),
+        Err(err) => {
+            log::warn!("Could not update delivery receipt: {}", err);
+            Ok(false)
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ReceiptError {
+    GetParticipants(ConversationServiceError),
+    MarkSeen(MessageLinkServiceError),
+    SetDelivered(MessageLinkServiceError),
+}
+
+impl fmt::Display for ReceiptError {
+    fn fmt(&self, out: &mut fmt::Formatter) -> fmt::Result {
+        use self::ReceiptError::*;
+        match self {
+            GetParticipants(err) => err.fmt(out),
+            MarkSeen(err) => err.fmt(out),
+            SetDelivered(err) => err.fmt(out),
+        }
+    }
+}
+
+impl Error for ReceiptError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        use self::ReceiptError::*;
+        Some(match self {
+            GetParticipants(err) => err,
+            MarkSeen(err) => err,
+            SetDelivered(err) => err,
+        })
+    }
+}
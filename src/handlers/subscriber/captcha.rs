@@ -0,0 +1,331 @@
+use crate::{
+    api::Api,
+    config::{Captcha, CaptchaKind, Config},
+    handlers::subscriber::message::Pipeline,
+    metrics::ErrorVariant,
+    services::{HeldMessage, VerificationService, VerificationServiceError},
+};
+use carapax::{
+    methods::{AnswerCallbackQuery, EditMessageText, SendMessage},
+    types::{CallbackQuery, CallbackQueryError, InlineKeyboardButton, InlineKeyboardError, Integer, Message, User},
+    ExecuteError, HandlerInput, Ref, TryFromInput,
+};
+use futures_util::future::BoxFuture;
+use rand::{seq::SliceRandom, thread_rng, Rng};
+use serde::{Deserialize, Serialize};
+use std::{error::Error, fmt};
+
+const MESSAGE_VERIFIED: &str = "Thank you! Now you can send your messages.";
+const MESSAGE_WRONG_ANSWER: &str = "Wrong answer, please try again";
+const BUTTON_TEXT: &str = "✅ I'm not a robot";
+const ARITHMETIC_OPTIONS: usize = 4;
+
+pub async fn is_required(
+    config: Ref<Config>,
+    verification_service: Ref<VerificationService>,
+    message: Message,
+) -> Result<bool, VerificationServiceError> {
+    Ok(match (config.captcha.as_ref(), message.get_user_id()) {
+        (Some(_), Some(user_id)) => !verification_service.is_verified(user_id).await?,
+        _ => false,
+    })
+}
+
+pub async fn handle_message(
+    api: Ref<Api>,
+    config: Ref<Config>,
+    verification_service: Ref<VerificationService>,
+    message: Message,
+) -> Result<(), CaptchaError> {
+    let captcha = match config.captcha {
+        Some(ref captcha) => captcha,
+        None => return Ok(()),
+    };
+    let user = message.get_user().ok_or(CaptchaError::NoUser)?;
+    let (user_id, language_code) = (user.id, user.language_code.as_deref());
+    let chat_id = message.get_chat_id();
+    if captcha.hold_messages() {
+        verification_service
+            .hold(HeldMessage::with_message(user_id, message.clone()))
+            .await
+            .map_err(CaptchaError::Hold)?;
+    }
+    let challenge = Challenge::new(&config, captcha, language_code);
+    // the previous challenge can still be answered, a new one would invalidate it
+    if !verification_service
+        .start_challenge(user_id, challenge.answer)
+        .await
+        .map_err(CaptchaError::SetChallenge)?
+    {
+        return Ok(());
+    }
+    let keyboard = challenge.build_keyboard().map_err(CaptchaError::BuildKeyboard)?;
+    api.execute(SendMessage::new(chat_id, challenge.text).reply_markup(keyboard))
+        .await
+        .map_err(CaptchaError::SendChallenge)?;
+    Ok(())
+}
+
+pub async fn handle_answer(
+    api: Ref<Api>,
+    config: Ref<Config>,
+    pipeline: Pipeline,
+    verification_service: Ref<VerificationService>,
+    query: CaptchaQuery,
+) -> Result<(), CaptchaError> {
+    if verification_service
+        .check_answer(query.user.id, query.answer)
+        .await
+        .map_err(CaptchaError::CheckAnswer)?
+    {
+        api.execute(AnswerCallbackQuery::new(query.id))
+            .await
+            .map_err(CaptchaError::AnswerCallbackQuery)?;
+        let language_code = query.user.language_code.as_deref();
+        let text = config.subscriber_text(language_code, "captcha_verified", MESSAGE_VERIFIED);
+        api.execute(EditMessageText::new(query.chat_id, query.message_id, text))
+            .await
+            .map_err(CaptchaError::EditMessage)?;
+        forward_held_messages(&pipeline, &verification_service, &query.user).await?;
+    } else {
+        let language_code = query.user.language_code.as_deref();
+        let text = config.subscriber_text(language_code, "captcha_wrong_answer", MESSAGE_WRONG_ANSWER);
+        api.execute(AnswerCallbackQuery::new(query.id).text(text))
+            .await
+            .map_err(CaptchaError::AnswerCallbackQuery)?;
+        if let Some(ref captcha) = config.captcha {
+            let challenge = Challenge::new(&config, captcha, language_code);
+            verification_service
+                .set_challenge(query.user.id, challenge.answer)
+                .await
+                .map_err(CaptchaError::SetChallenge)?;
+            let keyboard = challenge.build_keyboard().map_err(CaptchaError::BuildKeyboard)?;
+            api.execute(EditMessageText::new(query.chat_id, query.message_id, challenge.text).reply_markup(keyboard))
+                .await
+                .map_err(CaptchaError::EditMessage)?;
+        }
+    }
+    Ok(())
+}
+
+pub async fn forward_held_messages(
+    pipeline: &Pipeline,
+    verification_service: &VerificationService,
+    subscriber: &User,
+) -> Result<(), CaptchaError> {
+    for message in verification_service
+        .take_held(subscriber.id)
+        .await
+        .map_err(CaptchaError::TakeHeld)?
+    {
+        // held messages pass the same filters and auto-answers as any other message
+        let result = match message.message() {
+            Some(original) => pipeline.process(subscriber, original).await,
+            None => {
+                pipeline
+                    .forward_held(subscriber, message.chat_id(), message.message_id())
+                    .await
+            }
+        };
+        if let Err(err) = result {
+            log::error!("Could not forward held message: {}", err);
+        }
+    }
+    Ok(())
+}
+
+struct Challenge {
+    text: String,
+    answer: i32,
+    options: Vec<(String, i32)>,
+}
+
+impl Challenge {
+    fn new(config: &Config, captcha: &Captcha, language_code: Option<&str>) -> Self {
+        let mut rng = thread_rng();
+        let text = config.subscriber_text(language_code, "captcha_text", captcha.text());
+        match captcha.kind() {
+            CaptchaKind::Button => {
+                let answer = rng.gen();
+                Self {
+                    text: text.to_string(),
+                    answer,
+                    options: vec![(
+                        config
+                            .subscriber_text(language_code, "captcha_button", BUTTON_TEXT)
+                            .to_string(),
+                        answer,
+                    )],
+                }
+            }
+            CaptchaKind::Arithmetic => {
+                let (a, b) = (rng.gen_range(1..10), rng.gen_range(1..10));
+                let answer = a + b;
+                let mut options = vec![answer];
+                while options.len() < ARITHMETIC_OPTIONS {
+                    let option = rng.gen_range(2..19);
+                    if !options.contains(&option) {
+                        options.push(option);
+                    }
+                }
+                options.shuffle(&mut rng);
+                Self {
+                    text: format!("{}\n\n{} + {} = ?", text, a, b),
+                    answer,
+                    options: options.into_iter().map(|x| (x.to_string(), x)).collect(),
+                }
+            }
+        }
+    }
+
+    fn build_keyboard(&self) -> Result<Vec<Vec<InlineKeyboardButton>>, InlineKeyboardError> {
+        Ok(vec![self
+            .options
+            .iter()
+            .map(|(text, answer)| {
+                InlineKeyboardButton::with_callback_data_struct(text, &CaptchaAnswer { captcha: *answer })
+            })
+            .collect::<Result<Vec<InlineKeyboardButton>, InlineKeyboardError>>()?])
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct CaptchaAnswer {
+    captcha: i32,
+}
+
+pub struct CaptchaQuery {
+    id: String,
+    user: User,
+    chat_id: Integer,
+    message_id: Integer,
+    answer: i32,
+}
+
+impl TryFrom<CallbackQuery> for CaptchaQuery {
+    type Error = CaptchaQueryError;
+
+    fn try_from(query: CallbackQuery) -> Result<Self, Self::Error> {
+        let CaptchaAnswer { captcha: answer } = query
+            .parse_data()
+            .map_err(CaptchaQueryError::ParseData)
+            .and_then(|data: Option<CaptchaAnswer>| data.ok_or(CaptchaQueryError::NoData))?;
+        let message = query.message.ok_or(CaptchaQueryError::NoMessage)?;
+        Ok(Self {
+            id: query.id,
+            user: query.from,
+            chat_id: message.get_chat_id(),
+            message_id: message.id,
+            answer,
+        })
+    }
+}
+
+#[derive(Debug)]
+pub enum CaptchaQueryError {
+    NoData,
+    NoMessage,
+    ParseData(CallbackQueryError),
+}
+
+impl fmt::Display for CaptchaQueryError {
+    fn fmt(&self, out: &mut fmt::Formatter) -> fmt::Result {
+        use self::CaptchaQueryError::*;
+        match self {
+            NoData => write!(out, "callback query has no data"),
+            NoMessage => write!(out, "callback query has no message"),
+            ParseData(err) => write!(out, "could not parse query data: {}", err),
+        }
+    }
+}
+
+impl Error for CaptchaQueryError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        use self::CaptchaQueryError::*;
+        match self {
+            NoData => None,
+            NoMessage => None,
+            ParseData(err) => Some(err),
+        }
+    }
+}
+
+impl TryFromInput for CaptchaQuery {
+    type Error = CaptchaQueryError;
+
+    type Future = BoxFuture<'static, Result<Option<Self>, Self::Error>>;
+
+    fn try_from_input(input: HandlerInput) -> Self::Future {
+        Box::pin(async move {
+            Ok(CallbackQuery::try_from_input(input)
+                .await
+                .ok()
+                .flatten()
+                .and_then(|query| CaptchaQuery::try_from(query).ok()))
+        })
+    }
+}
+
+#[derive(Debug)]
+pub enum CaptchaError {
+    AnswerCallbackQuery(ExecuteError),
+    BuildKeyboard(InlineKeyboardError),
+    CheckAnswer(VerificationServiceError),
+    EditMessage(ExecuteError),
+    Hold(VerificationServiceError),
+    NoUser,
+    SendChallenge(ExecuteError),
+    SetChallenge(VerificationServiceError),
+    TakeHeld(VerificationServiceError),
+}
+
+impl fmt::Display for CaptchaError {
+    fn fmt(&self, out: &mut fmt::Formatter) -> fmt::Result {
+        use self::CaptchaError::*;
+        match self {
+            AnswerCallbackQuery(err) => err.fmt(out),
+            BuildKeyboard(err) => write!(out, "could not build inline keyboard: {}", err),
+            CheckAnswer(err) => err.fmt(out),
+            EditMessage(err) => err.fmt(out),
+            Hold(err) => err.fmt(out),
+            NoUser => write!(out, "incoming message has no user"),
+            SendChallenge(err) => err.fmt(out),
+            SetChallenge(err) => err.fmt(out),
+            TakeHeld(err) => err.fmt(out),
+        }
+    }
+}
+
+impl Error for CaptchaError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        use self::CaptchaError::*;
+        Some(match self {
+            AnswerCallbackQuery(err) => err,
+            BuildKeyboard(err) => err,
+            CheckAnswer(err) => err,
+            EditMessage(err) => err,
+            Hold(err) => err,
+            NoUser => return None,
+            SendChallenge(err) => err,
+            SetChallenge(err) => err,
+            TakeHeld(err) => err,
+        })
+    }
+}
+
+impl ErrorVariant for CaptchaError {
+    fn variant(&self) -> &'static str {
+        use self::CaptchaError::*;
+        match self {
+            AnswerCallbackQuery(_) => "AnswerCallbackQuery",
+            BuildKeyboard(_) => "BuildKeyboard",
+            CheckAnswer(_) => "CheckAnswer",
+            EditMessage(_) => "EditMessage",
+            Hold(_) => "Hold",
+            NoUser => "NoUser",
+            SendChallenge(_) => "SendChallenge",
+            SetChallenge(_) => "SetChallenge",
+            TakeHeld(_) => "TakeHeld",
+        }
+    }
+}
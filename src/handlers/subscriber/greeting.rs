@@ -0,0 +1,123 @@
+use crate::{
+    api::Api,
+    config::Config,
+    handlers::subscriber::menu::{build_markup, MenuError},
+    services::{GreetingService, GreetingServiceError, DEFAULT_GREETING},
+};
+use carapax::{
+    methods::{CopyMessage, SendMessage},
+    types::{InlineKeyboardMarkup, Integer, ParseMode, ReplyMarkup},
+    ExecuteError,
+};
+use std::{error::Error, fmt};
+
+const MESSAGE_MENU_PROMPT: &str = "Choose a topic from the menu below";
+
+pub async fn send_greeting(
+    api: &Api,
+    config: &Config,
+    greeting_service: &GreetingService,
+    chat_id: Integer,
+    language_code: Option<&str>,
+    source: Option<&str>,
+    with_menu: bool,
+) -> Result<bool, GreetingError> {
+    let menu_markup = match config.menu {
+        Some(ref menu) if with_menu => build_markup(config, menu, language_code).map_err(GreetingError::BuildMenu)?,
+        _ => None,
+    };
+    // a greeting set with /setgreeting takes precedence over the config and locale values
+    let mut greeting = None;
+    if let Some(source) = source {
+        greeting = greeting_service
+            .find(source)
+            .await
+            .map_err(GreetingError::FindGreeting)?;
+    }
+    if greeting.is_none() {
+        greeting = greeting_service
+            .find(DEFAULT_GREETING)
+            .await
+            .map_err(GreetingError::FindGreeting)?;
+    }
+    if let Some(greeting) = greeting {
+        let mut method = CopyMessage::new(chat_id, greeting.chat_id(), greeting.message_id());
+        // a message can have only one keyboard, so a reply keyboard menu is sent separately
+        let mut follow_up = None;
+        match (menu_markup.clone(), greeting.reply_markup()) {
+            (Some(ReplyMarkup::InlineKeyboardMarkup(menu)), Some(own)) => {
+                let mut keyboard = own.clone().into_vec();
+                keyboard.extend(menu.into_vec());
+                method = method.reply_markup(InlineKeyboardMarkup::from_vec(keyboard));
+            }
+            (Some(markup), Some(own)) => {
+                method = method.reply_markup(own.clone());
+                follow_up = Some(markup);
+            }
+            (Some(markup), None) => method = method.reply_markup(markup),
+            (None, Some(own)) => method = method.reply_markup(own.clone()),
+            (None, None) => {}
+        }
+        match api.execute(method).await {
+            Ok(_) => {
+                if let Some(markup) = follow_up {
+                    api.execute(
+                        SendMessage::new(
+                            chat_id,
+                            config.subscriber_text(language_code, "menu_prompt", MESSAGE_MENU_PROMPT),
+                        )
+                        .reply_markup(markup),
+                    )
+                    .await
+                    .map_err(GreetingError::SendMenu)?;
+                }
+                return Ok(true);
+            }
+            // the original message could be deleted from the admin chat
+            Err(err) => log::warn!("Could not copy greeting, falling back to config: {}", err),
+        }
+    }
+    match config.greeting(language_code) {
+        Some(text) => {
+            let mut method = SendMessage::new(chat_id, text).parse_mode(ParseMode::Html);
+            if let Some(markup) = menu_markup {
+                method = method.reply_markup(markup);
+            }
+            api.execute(method).await.map_err(GreetingError::SendMessage)?;
+            Ok(true)
+        }
+        None => Ok(false),
+    }
+}
+
+#[derive(Debug)]
+pub enum GreetingError {
+    BuildMenu(MenuError),
+    FindGreeting(GreetingServiceError),
+    SendMenu(ExecuteError),
+    SendMessage(ExecuteError),
+}
+
+impl fmt::Display for GreetingError {
+    fn fmt(&self, out: &mut fmt::Formatter) -> fmt::Result {
+        use self::GreetingError::*;
+        match self {
+            BuildMenu(err) => err.fmt(out),
+            FindGreeting(err) => err.fmt(out),
+            SendMenu(err) => write!(out, "could not send menu: {}", err),
+            SendMessage(err) => write!(out, "could not send greeting: {}", err),
+        }
+    }
+}
+
+impl Error for GreetingError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        use self::GreetingError::*;
+        Some(match self {
+            BuildMenu(err) => err,
+            FindGreeting(err) => err,
+            SendMenu(err) => err,
+            SendMessage(err) => err,
+        })
+    }
+}
@@ -0,0 +1,123 @@
+use crate::{
+    config::{Config, HeaderKind},
+    services::{ConversationService, ConversationServiceError, UserService, UserServiceError},
+};
+use carapax::types::{Message, MessageData, Text, TextEntity, User};
+use std::{error::Error, fmt};
+
+pub enum ForwardHeader {
+    Text(String),
+    Caption { caption: String, entities: Vec<TextEntity> },
+}
+
+impl ForwardHeader {
+    pub async fn build(
+        config: &Config,
+        conversation_service: &ConversationService,
+        user_service: &UserService,
+        subscriber: &User,
+        message: Option<&Message>,
+    ) -> Result<Option<Self>, HeaderError> {
+        let header = match config.header {
+            Some(ref header) => header,
+            None => return Ok(None),
+        };
+        let conversation_id = conversation_service
+            .get_or_create(subscriber.id)
+            .await
+            .map_err(HeaderError::GetConversation)?;
+        let source = if header.has_source() {
+            user_service
+                .get_source(subscriber.id)
+                .await
+                .map_err(HeaderError::GetSource)?
+        } else {
+            None
+        };
+        let text = header.render(subscriber, conversation_id, source.as_deref());
+        let caption = match header.kind() {
+            HeaderKind::Caption => message.and_then(get_caption),
+            HeaderKind::Text => None,
+        };
+        Ok(Some(match caption {
+            Some(Some(caption)) => {
+                // entity offsets are given in UTF-16 code units
+                let offset = text.encode_utf16().count() as u32 + 1;
+                ForwardHeader::Caption {
+                    caption: format!("{}\n{}", text, caption.data),
+                    entities: caption
+                        .entities
+                        .as_ref()
+                        .map(|entities| entities.iter().map(|entity| shift_entity(entity, offset)).collect())
+                        .unwrap_or_default(),
+                }
+            }
+            Some(None) => ForwardHeader::Caption {
+                caption: text,
+                entities: Vec::new(),
+            },
+            None => ForwardHeader::Text(text),
+        }))
+    }
+}
+
+fn get_caption(message: &Message) -> Option<Option<&Text>> {
+    match message.data {
+        MessageData::Audio { ref caption, .. }
+        | MessageData::Document { ref caption, .. }
+        | MessageData::Photo { ref caption, .. }
+        | MessageData::Video { ref caption, .. }
+        | MessageData::Voice { ref caption, .. } => Some(caption.as_ref()),
+        _ => None,
+    }
+}
+
+fn shift_entity(entity: &TextEntity, offset: u32) -> TextEntity {
+    let mut entity = entity.clone();
+    let position = match entity {
+        TextEntity::Bold(ref mut position)
+        | TextEntity::BotCommand(ref mut position)
+        | TextEntity::Cashtag(ref mut position)
+        | TextEntity::Code(ref mut position)
+        | TextEntity::Email(ref mut position)
+        | TextEntity::Hashtag(ref mut position)
+        | TextEntity::Italic(ref mut position)
+        | TextEntity::Mention(ref mut position)
+        | TextEntity::PhoneNumber(ref mut position)
+        | TextEntity::Pre { ref mut position, .. }
+        | TextEntity::Spoiler(ref mut position)
+        | TextEntity::Strikethrough(ref mut position)
+        | TextEntity::TextLink { ref mut position, .. }
+        | TextEntity::TextMention { ref mut position, .. }
+        | TextEntity::Underline(ref mut position)
+        | TextEntity::Url(ref mut position) => position,
+    };
+    position.offset += offset;
+    entity
+}
+
+#[derive(Debug)]
+pub enum HeaderError {
+    GetConversation(ConversationServiceError),
+    GetSource(UserServiceError),
+}
+
+impl fmt::Display for HeaderError {
+    fn fmt(&self, out: &mut fmt::Formatter) -> fmt::Result {
+        use self::HeaderError::*;
+        match self {
+            GetConversation(err) => err.fmt(out),
+            GetSource(err) => err.fmt(out),
+        }
+    }
+}
+
+impl Error for HeaderError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        use self::HeaderError::*;
+        Some(match self {
+            GetConversation(err) => err,
+            GetSource(err) => err,
+        })
+    }
+}
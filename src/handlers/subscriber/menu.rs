@@ -0,0 +1,262 @@
+use crate::{
+    api::Api,
+    config::{Config, Menu, MenuButtons, MenuItem, MenuMedia},
+    metrics::ErrorVariant,
+};
+use carapax::{
+    methods::{AnswerCallbackQuery, SendDocument, SendMessage, SendPhoto, SendVideo},
+    types::{
+        CallbackQuery, CallbackQueryError, InlineKeyboardButton, InlineKeyboardError, InputFile, Integer,
+        KeyboardButton, Message, ParseMode, ReplyKeyboardMarkup, ReplyMarkup,
+    },
+    ExecuteError, HandlerInput, Ref, TryFromInput,
+};
+use futures_util::future::BoxFuture;
+use serde::{Deserialize, Serialize};
+use std::{error::Error, fmt};
+
+pub async fn is_menu_request(config: Ref<Config>, message: Message) -> bool {
+    match config.menu {
+        Some(ref menu) => find_item(&config, menu, &message).is_some(),
+        None => false,
+    }
+}
+
+pub async fn handle(api: Ref<Api>, config: Ref<Config>, message: Message) -> Result<(), MenuError> {
+    let item = match config.menu.as_ref().and_then(|menu| find_item(&config, menu, &message)) {
+        Some(item) => item,
+        None => return Ok(()),
+    };
+    let language_code = message.get_user().and_then(|user| user.language_code.as_deref());
+    send_item(&api, &config, message.get_chat_id(), language_code, item).await
+}
+
+pub async fn handle_query(api: Ref<Api>, config: Ref<Config>, query: MenuQuery) -> Result<(), MenuError> {
+    api.execute(AnswerCallbackQuery::new(query.id))
+        .await
+        .map_err(MenuError::AnswerCallbackQuery)?;
+    match config.menu.as_ref().and_then(|menu| menu.get(query.index)) {
+        Some(item) => send_item(&api, &config, query.chat_id, query.language_code.as_deref(), item).await,
+        None => Ok(()),
+    }
+}
+
+pub fn build_markup(
+    config: &Config,
+    menu: &Menu,
+    language_code: Option<&str>,
+) -> Result<Option<ReplyMarkup>, MenuError> {
+    Ok(match menu.buttons() {
+        Some(MenuButtons::Inline) => Some(
+            menu.items()
+                .iter()
+                .enumerate()
+                .map(|(index, item)| {
+                    InlineKeyboardButton::with_callback_data_struct(
+                        config.menu_description(language_code, item),
+                        &MenuAnswer { menu: index },
+                    )
+                    .map(|button| vec![button])
+                })
+                .collect::<Result<Vec<Vec<InlineKeyboardButton>>, InlineKeyboardError>>()
+                .map_err(MenuError::BuildKeyboard)?
+                .into(),
+        ),
+        Some(MenuButtons::Reply) => Some(
+            ReplyKeyboardMarkup::from_vec(
+                menu.items()
+                    .iter()
+                    .map(|item| vec![KeyboardButton::new(config.menu_description(language_code, item))])
+                    .collect(),
+            )
+            .resize_keyboard(true)
+            .into(),
+        ),
+        None => None,
+    })
+}
+
+fn find_item<'a>(config: &Config, menu: &'a Menu, message: &Message) -> Option<&'a MenuItem> {
+    let text = message.get_text()?.data.trim();
+    match text.strip_prefix('/') {
+        Some(command) => {
+            let command = command.split_whitespace().next()?;
+            let command = command.split('@').next()?;
+            menu.find_command(command)
+        }
+        // reply buttons send their text, which is translated to the language of the subscriber
+        None if menu.buttons() == Some(MenuButtons::Reply) => {
+            let language_code = message.get_user().and_then(|user| user.language_code.as_deref());
+            menu.items()
+                .iter()
+                .find(|item| config.menu_description(language_code, item) == text)
+        }
+        None => None,
+    }
+}
+
+async fn send_item(
+    api: &Api,
+    config: &Config,
+    chat_id: Integer,
+    language_code: Option<&str>,
+    item: &MenuItem,
+) -> Result<(), MenuError> {
+    let caption = config.menu_text(language_code, item).unwrap_or_default();
+    match item.media() {
+        Some(MenuMedia::Document(file)) => {
+            api.execute(
+                SendDocument::new(chat_id, get_input_file(file))
+                    .caption(caption)
+                    .parse_mode(ParseMode::Html),
+            )
+            .await
+        }
+        Some(MenuMedia::Photo(file)) => {
+            api.execute(
+                SendPhoto::new(chat_id, get_input_file(file))
+                    .caption(caption)
+                    .parse_mode(ParseMode::Html),
+            )
+            .await
+        }
+        Some(MenuMedia::Video(file)) => {
+            api.execute(
+                SendVideo::new(chat_id, get_input_file(file))
+                    .caption(caption)
+                    .parse_mode(ParseMode::Html),
+            )
+            .await
+        }
+        None if caption.is_empty() => return Ok(()),
+        None => {
+            api.execute(SendMessage::new(chat_id, caption).parse_mode(ParseMode::Html))
+                .await
+        }
+    }
+    .map_err(MenuError::SendItem)?;
+    Ok(())
+}
+
+fn get_input_file(file: &str) -> InputFile {
+    if file.starts_with("http://") || file.starts_with("https://") {
+        InputFile::url(file)
+    } else {
+        InputFile::file_id(file)
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct MenuAnswer {
+    menu: usize,
+}
+
+pub struct MenuQuery {
+    id: String,
+    chat_id: Integer,
+    language_code: Option<String>,
+    index: usize,
+}
+
+impl TryFrom<CallbackQuery> for MenuQuery {
+    type Error = MenuQueryError;
+
+    fn try_from(query: CallbackQuery) -> Result<Self, Self::Error> {
+        let MenuAnswer { menu: index } = query
+            .parse_data()
+            .map_err(MenuQueryError::ParseData)
+            .and_then(|data: Option<MenuAnswer>| data.ok_or(MenuQueryError::NoData))?;
+        let message = query.message.ok_or(MenuQueryError::NoMessage)?;
+        Ok(Self {
+            id: query.id,
+            chat_id: message.get_chat_id(),
+            language_code: query.from.language_code,
+            index,
+        })
+    }
+}
+
+#[derive(Debug)]
+pub enum MenuQueryError {
+    NoData,
+    NoMessage,
+    ParseData(CallbackQueryError),
+}
+
+impl fmt::Display for MenuQueryError {
+    fn fmt(&self, out: &mut fmt::Formatter) -> fmt::Result {
+        use self::MenuQueryError::*;
+        match self {
+            NoData => write!(out, "callback query has no data"),
+            NoMessage => write!(out, "callback query has no message"),
+            ParseData(err) => write!(out, "could not parse query data: {}", err),
+        }
+    }
+}
+
+impl Error for MenuQueryError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        use self::MenuQueryError::*;
+        match self {
+            NoData => None,
+            NoMessage => None,
+            ParseData(err) => Some(err),
+        }
+    }
+}
+
+impl TryFromInput for MenuQuery {
+    type Error = MenuQueryError;
+
+    type Future = BoxFuture<'static, Result<Option<Self>, Self::Error>>;
+
+    fn try_from_input(input: HandlerInput) -> Self::Future {
+        Box::pin(async move {
+            Ok(CallbackQuery::try_from_input(input)
+                .await
+                .ok()
+                .flatten()
+                .and_then(|query| MenuQuery::try_from(query).ok()))
+        })
+    }
+}
+
+#[derive(Debug)]
+pub enum MenuError {
+    AnswerCallbackQuery(ExecuteError),
+    BuildKeyboard(InlineKeyboardError),
+    SendItem(ExecuteError),
+}
+
+impl fmt::Display for MenuError {
+    fn fmt(&self, out: &mut fmt::Formatter) -> fmt::Result {
+        use self::MenuError::*;
+        match self {
+            AnswerCallbackQuery(err) => err.fmt(out),
+            BuildKeyboard(err) => write!(out, "could not build inline keyboard: {}", err),
+            SendItem(err) => write!(out, "could not send menu item: {}", err),
+        }
+    }
+}
+
+impl Error for MenuError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        use self::MenuError::*;
+        Some(match self {
+            AnswerCallbackQuery(err) => err,
+            BuildKeyboard(err) => err,
+            SendItem(err) => err,
+        })
+    }
+}
+
+impl ErrorVariant for MenuError {
+    fn variant(&self) -> &'static str {
+        use self::MenuError::*;
+        match self {
+            AnswerCallbackQuery(_) => "AnswerCallbackQuery",
+            BuildKeyboard(_) => "BuildKeyboard",
+            SendItem(_) => "SendItem",
+        }
+    }
+}
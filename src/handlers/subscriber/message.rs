@@ -0,0 +1,671 @@
+use crate::{
+    api::Api,
+    config::{Config, FilterAction, FilterReason, FilterViolation},
+    handlers::{
+        media_group::{self, MediaGroupError, MEDIA_GROUP_DELAY},
+        receipt::{self, ReceiptError},
+        subscriber::{
+            greeting::{send_greeting, GreetingError},
+            header::{ForwardHeader, HeaderError},
+            route::get_admin_chat_id,
+        },
+    },
+    metrics::{self, ErrorVariant},
+    services::{
+        is_valid_source, ConversationService, ConversationServiceError, GreetingService, MediaGroupService,
+        MessageLink, MessageLinkDirection, MessageLinkService, MessageLinkServiceError, SettingsService,
+        SettingsServiceError, UserService, UserServiceError, SETTING_AUTO_ANSWERS_ENABLED,
+    },
+    shutdown::Tracker,
+};
+use carapax::{
+    methods::{CopyMessage, SendMessage},
+    types::{Command, InlineKeyboardButton, Integer, Message, ParseMode, User},
+    ExecuteError, HandlerInput, Ref, TryFromInput,
+};
+use chrono::Utc;
+use futures_util::future::{ready, OptionFuture, Ready};
+use std::{error::Error, fmt};
+use tokio::time::sleep;
+
+const MESSAGE_AUTO_ANSWER_SENT: &str = "Auto-answer has been sent";
+const MESSAGE_MEDIA_GROUP_NOT_DELIVERED: &str = "Could not deliver your media group, please try again";
+
+pub async fn handle_start(
+    api: Ref<Api>,
+    config: Ref<Config>,
+    greeting_service: Ref<GreetingService>,
+    user_service: Ref<UserService>,
+    command: Command,
+) -> Result<(), SubscriberError> {
+    let message = command.get_message();
+    let user = message.get_user();
+    let source = command
+        .get_args()
+        .first()
+        .map(String::as_str)
+        .filter(|source| is_valid_source(source));
+    if let (Some(user), Some(source)) = (user, source) {
+        user_service
+            .set_source(user.id, source)
+            .await
+            .map_err(SubscriberError::SetSource)?;
+    }
+    let language_code = user.and_then(|user| user.language_code.as_deref());
+    send_greeting(
+        &api,
+        &config,
+        &greeting_service,
+        message.get_chat_id(),
+        language_code,
+        source,
+        true,
+    )
+    .await
+    .map_err(SubscriberError::Greet)?;
+    Ok(())
+}
+
+pub async fn handle_message(pipeline: Pipeline, subscriber_message: Message) -> Result<(), SubscriberError> {
+    let subscriber = subscriber_message.get_user().ok_or(SubscriberError::NoUser)?;
+    pipeline.process(subscriber, &subscriber_message).await
+}
+
+#[derive(Clone)]
+pub struct Pipeline {
+    api: Api,
+    config: Config,
+    conversation_service: ConversationService,
+    media_group_service: MediaGroupService,
+    message_link_service: MessageLinkService,
+    settings_service: SettingsService,
+    user_service: UserService,
+    tracker: Tracker,
+}
+
+impl Pipeline {
+    pub async fn process(&self, subscriber: &User, subscriber_message: &Message) -> Result<(), SubscriberError> {
+        let Self {
+            api,
+            config,
+            conversation_service,
+            media_group_service,
+            message_link_service,
+            settings_service,
+            user_service,
+            tracker,
+        } = self;
+        let subscriber_user_id = subscriber.id;
+        let subscriber_chat_id = subscriber_message.get_chat_id();
+
+        // any message means the subscriber has seen the replies, even if it is not forwarded
+        receipt::mark_seen(
+            api,
+            config,
+            conversation_service,
+            message_link_service,
+            subscriber_user_id,
+        )
+        .await
+        .map_err(SubscriberError::MarkSeen)?;
+
+        let violation = check_filters(config, subscriber_message);
+        let warning = match violation {
+            Some(ref violation) => match violation.action() {
+                FilterAction::Drop => return Ok(()),
+                FilterAction::Reject => {
+                    api.execute(
+                        SendMessage::new(subscriber_chat_id, violation.message())
+                            .parse_mode(ParseMode::Html)
+                            .reply_to_message_id(subscriber_message.id),
+                    )
+                    .await
+                    .map_err(SubscriberError::Reject)?;
+                    return Ok(());
+                }
+                FilterAction::Warn => Some(violation.reason()),
+            },
+            None => None,
+        };
+
+        let auto_answer = match config.auto_answers {
+            Some(ref auto_answers)
+                if settings_service
+                    .get_bool(SETTING_AUTO_ANSWERS_ENABLED, auto_answers.is_enabled())
+                    .await
+                    .map_err(SubscriberError::GetSettings)? =>
+            {
+                subscriber_message
+                    .get_text()
+                    .and_then(|text| auto_answers.find(&text.data))
+            }
+            _ => None,
+        };
+        if let Some(rule) = auto_answer {
+            api.execute(
+                SendMessage::new(subscriber_chat_id, rule.answer())
+                    .parse_mode(ParseMode::Html)
+                    .reply_to_message_id(subscriber_message.id),
+            )
+            .await
+            .map_err(SubscriberError::SendAutoAnswer)?;
+            if !rule.forward() {
+                return Ok(());
+            }
+        }
+
+        if let Some(ref media_group_id) = subscriber_message.media_group_id {
+            if media_group_service.add(media_group_id, subscriber_message.clone()) {
+                let (api, config) = (api.clone(), config.clone());
+                let (conversation_service, media_group_service, message_link_service, user_service) = (
+                    conversation_service.clone(),
+                    media_group_service.clone(),
+                    message_link_service.clone(),
+                    user_service.clone(),
+                );
+                let (subscriber, media_group_id) = (subscriber.clone(), media_group_id.clone());
+                tracker.spawn(async move {
+                    sleep(MEDIA_GROUP_DELAY).await;
+                    let parts = media_group_service.take(&media_group_id);
+                    if let Err(err) = forward_media_group(
+                        &api,
+                        &config,
+                        &conversation_service,
+                        &message_link_service,
+                        &user_service,
+                        &subscriber,
+                        &parts,
+                    )
+                    .await
+                    {
+                        metrics::record_error(&err);
+                        log::error!("Failed to forward media group: {}", err);
+                        let text = config.subscriber_text(
+                            subscriber.language_code.as_deref(),
+                            "media_group_not_delivered",
+                            MESSAGE_MEDIA_GROUP_NOT_DELIVERED,
+                        );
+                        media_group::report_error(&api, &parts, text).await;
+                    }
+                });
+            }
+        } else {
+            let admin_chat_id = get_admin_chat_id(config, conversation_service, subscriber, Some(subscriber_message))
+                .await
+                .map_err(SubscriberError::GetConversation)?;
+            let reply_to_message_id =
+                find_reply_to_message_id(message_link_service, admin_chat_id, subscriber_message).await?;
+            let header = ForwardHeader::build(
+                config,
+                conversation_service,
+                user_service,
+                subscriber,
+                Some(subscriber_message),
+            )
+            .await
+            .map_err(SubscriberError::BuildHeader)?;
+            let assignee = get_assignee(conversation_service, user_service, subscriber_user_id).await?;
+            let admin_message_id = forward(
+                api,
+                message_link_service,
+                admin_chat_id,
+                subscriber,
+                subscriber_chat_id,
+                subscriber_message.id,
+                reply_to_message_id,
+                build_button(subscriber, warning, assignee.as_ref()),
+                header,
+            )
+            .await?;
+            mark_awaiting(
+                conversation_service,
+                subscriber_user_id,
+                admin_chat_id,
+                admin_message_id,
+            )
+            .await?;
+            if auto_answer.is_some() {
+                api.execute(
+                    SendMessage::new(
+                        admin_chat_id,
+                        config.admin_text("auto_answer_sent", MESSAGE_AUTO_ANSWER_SENT),
+                    )
+                    .reply_to_message_id(admin_message_id),
+                )
+                .await
+                .map_err(SubscriberError::SendAutoAnswer)?;
+            }
+        }
+
+        if let Some(ref business_hours) = config.business_hours {
+            if !business_hours.is_open(Utc::now())
+                && user_service
+                    .mark_away_message_sent(subscriber_user_id, business_hours.away_interval())
+                    .await
+                    .map_err(SubscriberError::MarkAwayMessageSent)?
+            {
+                api.execute(
+                    SendMessage::new(
+                        subscriber_chat_id,
+                        config.subscriber_text(
+                            subscriber.language_code.as_deref(),
+                            "away_message",
+                            business_hours.away_message(),
+                        ),
+                    )
+                    .parse_mode(ParseMode::Html),
+                )
+                .await
+                .map_err(SubscriberError::SendAwayMessage)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    pub async fn forward_held(
+        &self,
+        subscriber: &User,
+        chat_id: Integer,
+        message_id: Integer,
+    ) -> Result<(), SubscriberError> {
+        // the original message is unknown, so it is forwarded as is
+        let admin_chat_id = get_admin_chat_id(&self.config, &self.conversation_service, subscriber, None)
+            .await
+            .map_err(SubscriberError::GetConversation)?;
+        let header = ForwardHeader::build(
+            &self.config,
+            &self.conversation_service,
+            &self.user_service,
+            subscriber,
+            None,
+        )
+        .await
+        .map_err(SubscriberError::BuildHeader)?;
+        let admin_message_id = forward(
+            &self.api,
+            &self.message_link_service,
+            admin_chat_id,
+            subscriber,
+            chat_id,
+            message_id,
+            None,
+            build_button(subscriber, None, None),
+            header,
+        )
+        .await?;
+        mark_awaiting(
+            &self.conversation_service,
+            subscriber.id,
+            admin_chat_id,
+            admin_message_id,
+        )
+        .await
+    }
+}
+
+impl TryFromInput for Pipeline {
+    type Error = SubscriberError;
+
+    type Future = Ready<Result<Option<Self>, Self::Error>>;
+
+    fn try_from_input(input: HandlerInput) -> Self::Future {
+        let context = &input.context;
+        ready(
+            match (
+                context.get::<Api>(),
+                context.get::<Config>(),
+                context.get::<ConversationService>(),
+                context.get::<MediaGroupService>(),
+                context.get::<MessageLinkService>(),
+                context.get::<SettingsService>(),
+                context.get::<UserService>(),
+                context.get::<Tracker>(),
+            ) {
+                (
+                    Some(api),
+                    Some(config),
+                    Some(conversation_service),
+                    Some(media_group_service),
+                    Some(message_link_service),
+                    Some(settings_service),
+                    Some(user_service),
+                    Some(tracker),
+                ) => Ok(Some(Self {
+                    api: api.clone(),
+                    config: config.clone(),
+                    conversation_service: conversation_service.clone(),
+                    media_group_service: media_group_service.clone(),
+                    message_link_service: message_link_service.clone(),
+                    settings_service: settings_service.clone(),
+                    user_service: user_service.clone(),
+                    tracker: tracker.clone(),
+                })),
+                _ => Err(SubscriberError::NoContext),
+            },
+        )
+    }
+}
+
+fn check_filters<'a>(config: &'a Config, message: &Message) -> Option<FilterViolation<'a>> {
+    config.filters.iter().find_map(|filter| filter.check(message))
+}
+
+async fn find_reply_to_message_id(
+    message_link_service: &MessageLinkService,
+    admin_chat_id: Integer,
+    subscriber_message: &Message,
+) -> Result<Option<Integer>, SubscriberError> {
+    Ok(OptionFuture::from(subscriber_message.reply_to.as_ref().map(|reply_to| {
+        message_link_service.find(reply_to.get_chat_id(), reply_to.id, MessageLinkDirection::Subscriber)
+    }))
+    .await
+    .transpose()
+    .map_err(SubscriberError::FindLink)?
+    .flatten()
+    .filter(|link| link.admin_chat_id() == admin_chat_id)
+    .map(|link| link.admin_message_id()))
+}
+
+async fn forward_media_group(
+    api: &Api,
+    config: &Config,
+    conversation_service: &ConversationService,
+    message_link_service: &MessageLinkService,
+    user_service: &UserService,
+    subscriber: &User,
+    parts: &[Message],
+) -> Result<(), SubscriberError> {
+    let first_part = match parts.first() {
+        Some(part) => part,
+        None => return Ok(()),
+    };
+    let admin_chat_id = get_admin_chat_id(config, conversation_service, subscriber, Some(first_part))
+        .await
+        .map_err(SubscriberError::GetConversation)?;
+    let reply_to_message_id = find_reply_to_message_id(message_link_service, admin_chat_id, first_part).await?;
+    let admin_message_ids = media_group::send(api, admin_chat_id, parts, reply_to_message_id)
+        .await
+        .map_err(SubscriberError::SendMediaGroup)?;
+    metrics::record_forwarded_to_admins(admin_message_ids.len());
+    if let Some(admin_message_id) = admin_message_ids.first() {
+        mark_awaiting(conversation_service, subscriber.id, admin_chat_id, *admin_message_id).await?;
+    }
+    for (part, admin_message_id) in parts.iter().zip(admin_message_ids.iter()) {
+        message_link_service
+            .create(MessageLink::new(
+                subscriber.id,
+                part.get_chat_id(),
+                part.id,
+                admin_chat_id,
+                *admin_message_id,
+            ))
+            .await
+            .map_err(SubscriberError::CreateLink)?;
+    }
+    // media groups can not have a reply markup, so the subscriber button is sent separately
+    let warning = parts
+        .iter()
+        .filter_map(|part| check_filters(config, part))
+        .find(|violation| violation.action() == FilterAction::Warn)
+        .map(|violation| violation.reason());
+    let text = match ForwardHeader::build(config, conversation_service, user_service, subscriber, None)
+        .await
+        .map_err(SubscriberError::BuildHeader)?
+    {
+        Some(ForwardHeader::Text(text)) | Some(ForwardHeader::Caption { caption: text, .. }) => text,
+        None => subscriber.get_full_name(),
+    };
+    let assignee = get_assignee(conversation_service, user_service, subscriber.id).await?;
+    let button = build_button(subscriber, warning, assignee.as_ref());
+    let mut method = SendMessage::new(admin_chat_id, text).reply_markup(vec![vec![button]]);
+    if let Some(admin_message_id) = admin_message_ids.first() {
+        method = method.reply_to_message_id(*admin_message_id);
+    }
+    let admin_message_id = api
+        .execute(method)
+        .await
+        .map_err(SubscriberError::SendSubscriberButton)?
+        .id;
+    message_link_service
+        .create(MessageLink::new(
+            subscriber.id,
+            first_part.get_chat_id(),
+            first_part.id,
+            admin_chat_id,
+            admin_message_id,
+        ))
+        .await
+        .map_err(SubscriberError::CreateLink)?;
+    Ok(())
+}
+
+pub async fn mark_awaiting(
+    conversation_service: &ConversationService,
+    subscriber_user_id: Integer,
+    admin_chat_id: Integer,
+    admin_message_id: Integer,
+) -> Result<(), SubscriberError> {
+    let conversation_id = conversation_service
+        .get_or_create(subscriber_user_id)
+        .await
+        .map_err(SubscriberError::MarkAwaiting)?;
+    conversation_service
+        .mark_awaiting(conversation_id, admin_chat_id, admin_message_id)
+        .await
+        .map_err(SubscriberError::MarkAwaiting)
+}
+
+async fn get_assignee(
+    conversation_service: &ConversationService,
+    user_service: &UserService,
+    subscriber_user_id: Integer,
+) -> Result<Option<User>, SubscriberError> {
+    let conversation_id = conversation_service
+        .get_or_create(subscriber_user_id)
+        .await
+        .map_err(SubscriberError::GetConversation)?;
+    match conversation_service
+        .get_assignee(conversation_id)
+        .await
+        .map_err(SubscriberError::GetConversation)?
+    {
+        Some(user_id) => user_service.get(user_id).await.map_err(SubscriberError::GetAssignee),
+        None => Ok(None),
+    }
+}
+
+pub fn build_button(subscriber: &User, warning: Option<FilterReason>, assignee: Option<&User>) -> InlineKeyboardButton {
+    let mut name = match warning {
+        Some(reason) => format!("⚠️ {} ({})", subscriber.get_full_name(), reason),
+        None => subscriber.get_full_name(),
+    };
+    if let Some(assignee) = assignee {
+        match assignee.username {
+            Some(ref username) => name.push_str(&format!(" → @{}", username)),
+            None => name.push_str(&format!(" → {}", assignee.get_full_name())),
+        }
+    }
+    InlineKeyboardButton::with_url(name, get_subscriber_url(subscriber))
+}
+
+pub fn get_subscriber_url(subscriber: &User) -> String {
+    match subscriber.username {
+        Some(ref username) => format!("t.me/{}", username),
+        None => subscriber.get_link(),
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+pub async fn forward(
+    api: &Api,
+    message_link_service: &MessageLinkService,
+    admin_chat_id: Integer,
+    subscriber: &User,
+    subscriber_chat_id: Integer,
+    subscriber_message_id: Integer,
+    mut reply_to_message_id: Option<Integer>,
+    button: InlineKeyboardButton,
+    header: Option<ForwardHeader>,
+) -> Result<Integer, SubscriberError> {
+    let create_link = |admin_message_id| {
+        message_link_service.create(MessageLink::new(
+            subscriber.id,
+            subscriber_chat_id,
+            subscriber_message_id,
+            admin_chat_id,
+            admin_message_id,
+        ))
+    };
+
+    let mut method =
+        CopyMessage::new(admin_chat_id, subscriber_chat_id, subscriber_message_id).reply_markup(vec![vec![button]]);
+    match header {
+        Some(ForwardHeader::Text(text)) => {
+            let mut header_method = SendMessage::new(admin_chat_id, text);
+            if let Some(reply_to_message_id) = reply_to_message_id {
+                header_method = header_method.reply_to_message_id(reply_to_message_id);
+            }
+            let header_message_id = api
+                .execute(header_method)
+                .await
+                .map_err(SubscriberError::SendHeader)?
+                .id;
+            create_link(header_message_id)
+                .await
+                .map_err(SubscriberError::CreateLink)?;
+            reply_to_message_id = Some(header_message_id);
+        }
+        Some(ForwardHeader::Caption { caption, entities }) => {
+            method = method.caption(caption);
+            if !entities.is_empty() {
+                method = method.caption_entities(entities);
+            }
+        }
+        None => {}
+    }
+    if let Some(reply_to_message_id) = reply_to_message_id {
+        method = method.reply_to_message_id(reply_to_message_id);
+    }
+
+    let admin_message_id = api
+        .execute(method)
+        .await
+        .map_err(SubscriberError::CopyMessage)?
+        .message_id;
+    metrics::record_forwarded_to_admins(1);
+
+    create_link(admin_message_id)
+        .await
+        .map_err(SubscriberError::CreateLink)?;
+
+    Ok(admin_message_id)
+}
+
+#[derive(Debug)]
+pub enum SubscriberError {
+    BuildHeader(HeaderError),
+    CopyMessage(ExecuteError),
+    CreateLink(MessageLinkServiceError),
+    FindLink(MessageLinkServiceError),
+    GetAssignee(UserServiceError),
+    GetConversation(ConversationServiceError),
+    GetSettings(SettingsServiceError),
+    Greet(GreetingError),
+    MarkAwaiting(ConversationServiceError),
+    MarkSeen(ReceiptError),
+    MarkAwayMessageSent(UserServiceError),
+    NoContext,
+    NoUser,
+    Reject(ExecuteError),
+    SendAutoAnswer(ExecuteError),
+    SendAwayMessage(ExecuteError),
+    SendHeader(ExecuteError),
+    SendMediaGroup(MediaGroupError),
+    SendSubscriberButton(ExecuteError),
+    SetSource(UserServiceError),
+}
+
+impl fmt::Display for SubscriberError {
+    fn fmt(&self, out: &mut fmt::Formatter) -> fmt::Result {
+        use self::SubscriberError::*;
+        match self {
+            BuildHeader(err) => err.fmt(out),
+            CopyMessage(err) => err.fmt(out),
+            CreateLink(err) => err.fmt(out),
+            FindLink(err) => err.fmt(out),
+            GetAssignee(err) => err.fmt(out),
+            GetConversation(err) => err.fmt(out),
+            GetSettings(err) => err.fmt(out),
+            Greet(err) => err.fmt(out),
+            MarkAwaiting(err) => err.fmt(out),
+            MarkSeen(err) => err.fmt(out),
+            MarkAwayMessageSent(err) => err.fmt(out),
+            NoContext => write!(out, "could not find services in context"),
+            NoUser => write!(out, "incoming message has no user"),
+            Reject(err) => err.fmt(out),
+            SendAutoAnswer(err) => err.fmt(out),
+            SendAwayMessage(err) => err.fmt(out),
+            SendHeader(err) => err.fmt(out),
+            SendMediaGroup(err) => err.fmt(out),
+            SendSubscriberButton(err) => err.fmt(out),
+            SetSource(err) => err.fmt(out),
+        }
+    }
+}
+
+impl Error for SubscriberError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        use self::SubscriberError::*;
+        Some(match self {
+            BuildHeader(err) => err,
+            CopyMessage(err) => err,
+            CreateLink(err) => err,
+            FindLink(err) => err,
+            GetAssignee(err) => err,
+            GetConversation(err) => err,
+            GetSettings(err) => err,
+            Greet(err) => err,
+            MarkAwaiting(err) => err,
+            MarkSeen(err) => err,
+            MarkAwayMessageSent(err) => err,
+            NoContext => return None,
+            NoUser => return None,
+            Reject(err) => err,
+            SendAutoAnswer(err) => err,
+            SendAwayMessage(err) => err,
+            SendHeader(err) => err,
+            SendMediaGroup(err) => err,
+            SendSubscriberButton(err) => err,
+            SetSource(err) => err,
+        })
+    }
+}
+
+impl ErrorVariant for SubscriberError {
+    fn variant(&self) -> &'static str {
+        use self::SubscriberError::*;
+        match self {
+            BuildHeader(_) => "BuildHeader",
+            CopyMessage(_) => "CopyMessage",
+            CreateLink(_) => "CreateLink",
+            FindLink(_) => "FindLink",
+            GetAssignee(_) => "GetAssignee",
+            GetConversation(_) => "GetConversation",
+            GetSettings(_) => "GetSettings",
+            Greet(_) => "Greet",
+            MarkAwaiting(_) => "MarkAwaiting",
+            MarkSeen(_) => "MarkSeen",
+            MarkAwayMessageSent(_) => "MarkAwayMessageSent",
+            NoContext => "NoContext",
+            NoUser => "NoUser",
+            Reject(_) => "Reject",
+            SendAutoAnswer(_) => "SendAutoAnswer",
+            SendAwayMessage(_) => "SendAwayMessage",
+            SendHeader(_) => "SendHeader",
+            SendMediaGroup(_) => "SendMediaGroup",
+            SendSubscriberButton(_) => "SendSubscriberButton",
+            SetSource(_) => "SetSource",
+        }
+    }
+}
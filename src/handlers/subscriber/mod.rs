@@ -0,0 +1,29 @@
+use crate::metrics::TrackErrorsExt;
+use carapax::{Chain, CommandExt, PredicateExt};
+
+mod captcha;
+mod greeting;
+mod header;
+mod menu;
+mod message;
+mod rating;
+mod route;
+
+pub use self::{
+    captcha::{forward_held_messages, CaptchaError},
+    greeting::{send_greeting, GreetingError},
+    message::{build_button, get_subscriber_url, Pipeline},
+    rating::request_rating,
+};
+
+pub fn setup() -> Chain {
+    Chain::once()
+        .add(captcha::handle_answer.track_errors())
+        .add(rating::handle_answer.track_errors())
+        .add(menu::handle_query.track_errors())
+        .add(message::handle_start.track_errors().command("/start"))
+        .add(menu::handle.track_errors().predicate(menu::is_menu_request))
+        .add(rating::handle_comment.track_errors().predicate(rating::is_comment))
+        .add(captcha::handle_message.track_errors().predicate(captcha::is_required))
+        .add(message::handle_message.track_errors())
+}
@@ -0,0 +1,333 @@
+use crate::{
+    api::Api,
+    config::{Config, Ratings},
+    metrics::ErrorVariant,
+    services::{Rating, RatingService, RatingServiceError, UserService, UserServiceError},
+};
+use carapax::{
+    methods::{AnswerCallbackQuery, EditMessageText, SendMessage},
+    types::{CallbackQuery, CallbackQueryError, InlineKeyboardButton, InlineKeyboardError, Integer, Message, User},
+    ExecuteError, HandlerInput, Ref, TryFromInput,
+};
+use futures_util::future::BoxFuture;
+use serde::{Deserialize, Serialize};
+use std::{error::Error, fmt};
+
+const MESSAGE_ALREADY_RATED: &str = "You have already rated this conversation";
+const MESSAGE_COMMENT: &str = "Thank you! Reply to this message if you want to leave a comment.";
+const MESSAGE_COMMENT_SAVED: &str = "Thank you for your comment!";
+const MESSAGE_THANKS: &str = "Thank you for your feedback!";
+const MAX_SCORE: i32 = 5;
+
+pub async fn request_rating(
+    api: &Api,
+    config: &Config,
+    rating_service: &RatingService,
+    ratings: &Ratings,
+    subscriber: Option<&User>,
+    subscriber_chat_id: Integer,
+    rating: Rating,
+) -> Result<(), RatingError> {
+    let keyboard = build_keyboard(rating.conversation_id()).map_err(RatingError::BuildKeyboard)?;
+    let language_code = subscriber.and_then(|subscriber| subscriber.language_code.as_deref());
+    let text = config.subscriber_text(language_code, "rating_prompt", ratings.text());
+    let prompt = api
+        .execute(SendMessage::new(subscriber_chat_id, text).reply_markup(keyboard))
+        .await
+        .map_err(RatingError::SendPrompt)?;
+    rating_service
+        .create(&rating, subscriber_chat_id, prompt.id)
+        .await
+        .map_err(RatingError::Create)
+}
+
+pub async fn handle_answer(
+    api: Ref<Api>,
+    config: Ref<Config>,
+    rating_service: Ref<RatingService>,
+    user_service: Ref<UserService>,
+    query: RatingQuery,
+) -> Result<(), RatingError> {
+    let language_code = query.user.language_code.as_deref();
+    let rating = match rating_service
+        .rate(query.conversation_id, query.user.id, query.score)
+        .await
+        .map_err(RatingError::Rate)?
+    {
+        Some(rating) => rating,
+        None => {
+            let text = config.subscriber_text(language_code, "rating_already_rated", MESSAGE_ALREADY_RATED);
+            api.execute(AnswerCallbackQuery::new(query.id).text(text))
+                .await
+                .map_err(RatingError::AnswerCallbackQuery)?;
+            return Ok(());
+        }
+    };
+    api.execute(AnswerCallbackQuery::new(query.id))
+        .await
+        .map_err(RatingError::AnswerCallbackQuery)?;
+    let request_comment = config
+        .ratings
+        .as_ref()
+        .map(|ratings| ratings.request_comment())
+        .unwrap_or(false);
+    let text = if request_comment {
+        config.subscriber_text(language_code, "rating_comment", MESSAGE_COMMENT)
+    } else {
+        config.subscriber_text(language_code, "rating_thanks", MESSAGE_THANKS)
+    };
+    api.execute(EditMessageText::new(query.chat_id, query.message_id, text))
+        .await
+        .map_err(RatingError::EditMessage)?;
+    let score = rating.score().unwrap_or(query.score);
+    let mut text = format!(
+        "{} {} rated conversation #c{}: {}/{}",
+        "⭐".repeat(score as usize),
+        format_user(&query.user),
+        rating.conversation_id(),
+        score,
+        MAX_SCORE
+    );
+    if let Some(admin_user_id) = rating.admin_user_id() {
+        let admin = user_service
+            .get(admin_user_id)
+            .await
+            .map_err(RatingError::GetAdmin)?
+            .map(|admin| admin.get_full_name())
+            .unwrap_or_else(|| admin_user_id.to_string());
+        text = format!("{} (admin: {})", text, admin);
+    }
+    api.execute(SendMessage::new(rating.admin_chat_id(), text))
+        .await
+        .map_err(RatingError::SendToAdmin)?;
+    Ok(())
+}
+
+pub async fn is_comment(rating_service: Ref<RatingService>, message: Message) -> Result<bool, RatingServiceError> {
+    Ok(match (message.reply_to.as_ref(), message.get_text()) {
+        (Some(reply_to), Some(_)) => rating_service
+            .find_commentable(message.get_chat_id(), reply_to.id)
+            .await?
+            .is_some(),
+        _ => false,
+    })
+}
+
+pub async fn handle_comment(
+    api: Ref<Api>,
+    config: Ref<Config>,
+    rating_service: Ref<RatingService>,
+    message: Message,
+) -> Result<(), RatingError> {
+    let (reply_to, text, user) = match (message.reply_to.as_ref(), message.get_text(), message.get_user()) {
+        (Some(reply_to), Some(text), Some(user)) => (reply_to, &text.data, user),
+        _ => return Ok(()),
+    };
+    let chat_id = message.get_chat_id();
+    let rating = match rating_service
+        .find_commentable(chat_id, reply_to.id)
+        .await
+        .map_err(RatingError::FindRating)?
+    {
+        Some(rating) => rating,
+        None => return Ok(()),
+    };
+    rating_service
+        .set_comment(rating.conversation_id(), text)
+        .await
+        .map_err(RatingError::SetComment)?;
+    let text = config.subscriber_text(
+        user.language_code.as_deref(),
+        "rating_comment_saved",
+        MESSAGE_COMMENT_SAVED,
+    );
+    api.execute(SendMessage::new(chat_id, text).reply_to_message_id(message.id))
+        .await
+        .map_err(RatingError::SendMessage)?;
+    let text = format!(
+        "💬 {} commented on conversation #c{}:\n{}",
+        format_user(user),
+        rating.conversation_id(),
+        text
+    );
+    api.execute(SendMessage::new(rating.admin_chat_id(), text))
+        .await
+        .map_err(RatingError::SendToAdmin)?;
+    Ok(())
+}
+
+fn format_user(user: &User) -> String {
+    format!("{} #id{}", user.get_full_name(), user.id)
+}
+
+fn build_keyboard(conversation_id: i32) -> Result<Vec<Vec<InlineKeyboardButton>>, InlineKeyboardError> {
+    Ok(vec![(1..=MAX_SCORE)
+        .map(|score| {
+            InlineKeyboardButton::with_callback_data_struct(
+                format!("{} ⭐", score),
+                &RatingAnswer {
+                    rating: conversation_id,
+                    score,
+                },
+            )
+        })
+        .collect::<Result<Vec<InlineKeyboardButton>, InlineKeyboardError>>()?])
+}
+
+#[derive(Serialize, Deserialize)]
+struct RatingAnswer {
+    rating: i32,
+    score: i32,
+}
+
+pub struct RatingQuery {
+    id: String,
+    user: User,
+    chat_id: Integer,
+    message_id: Integer,
+    conversation_id: i32,
+    score: i32,
+}
+
+impl TryFrom<CallbackQuery> for RatingQuery {
+    type Error = RatingQueryError;
+
+    fn try_from(query: CallbackQuery) -> Result<Self, Self::Error> {
+        let RatingAnswer { rating, score } = query
+            .parse_data()
+            .map_err(RatingQueryError::ParseData)
+            .and_then(|data: Option<RatingAnswer>| data.ok_or(RatingQueryError::NoData))?;
+        if !(1..=MAX_SCORE).contains(&score) {
+            return Err(RatingQueryError::InvalidScore(score));
+        }
+        let message = query.message.ok_or(RatingQueryError::NoMessage)?;
+        Ok(Self {
+            id: query.id,
+            user: query.from,
+            chat_id: message.get_chat_id(),
+            message_id: message.id,
+            conversation_id: rating,
+            score,
+        })
+    }
+}
+
+#[derive(Debug)]
+pub enum RatingQueryError {
+    InvalidScore(i32),
+    NoData,
+    NoMessage,
+    ParseData(CallbackQueryError),
+}
+
+impl fmt::Display for RatingQueryError {
+    fn fmt(&self, out: &mut fmt::Formatter) -> fmt::Result {
+        use self::RatingQueryError::*;
+        match self {
+            InvalidScore(score) => write!(out, "invalid score: {}", score),
+            NoData => write!(out, "callback query has no data"),
+            NoMessage => write!(out, "callback query has no message"),
+            ParseData(err) => write!(out, "could not parse query data: {}", err),
+        }
+    }
+}
+
+impl Error for RatingQueryError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        use self::RatingQueryError::*;
+        match self {
+            InvalidScore(_) => None,
+            NoData => None,
+            NoMessage => None,
+            ParseData(err) => Some(err),
+        }
+    }
+}
+
+impl TryFromInput for RatingQuery {
+    type Error = RatingQueryError;
+
+    type Future = BoxFuture<'static, Result<Option<Self>, Self::Error>>;
+
+    fn try_from_input(input: HandlerInput) -> Self::Future {
+        Box::pin(async move {
+            Ok(CallbackQuery::try_from_input(input)
+                .await
+                .ok()
+                .flatten()
+                .and_then(|query| RatingQuery::try_from(query).ok()))
+        })
+    }
+}
+
+#[derive(Debug)]
+pub enum RatingError {
+    AnswerCallbackQuery(ExecuteError),
+    BuildKeyboard(InlineKeyboardError),
+    Create(RatingServiceError),
+    EditMessage(ExecuteError),
+    FindRating(RatingServiceError),
+    GetAdmin(UserServiceError),
+    Rate(RatingServiceError),
+    SendMessage(ExecuteError),
+    SendPrompt(ExecuteError),
+    SendToAdmin(ExecuteError),
+    SetComment(RatingServiceError),
+}
+
+impl fmt::Display for RatingError {
+    fn fmt(&self, out: &mut fmt::Formatter) -> fmt::Result {
+        use self::RatingError::*;
+        match self {
+            AnswerCallbackQuery(err) => err.fmt(out),
+            BuildKeyboard(err) => write!(out, "could not build inline keyboard: {}", err),
+            Create(err) => err.fmt(out),
+            EditMessage(err) => err.fmt(out),
+            FindRating(err) => err.fmt(out),
+            GetAdmin(err) => err.fmt(out),
+            Rate(err) => err.fmt(out),
+            SendMessage(err) => err.fmt(out),
+            SendPrompt(err) => write!(out, "could not send rating prompt: {}", err),
+            SendToAdmin(err) => write!(out, "could not send rating to admin chat: {}", err),
+            SetComment(err) => err.fmt(out),
+        }
+    }
+}
+
+impl Error for RatingError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        use self::RatingError::*;
+        Some(match self {
+            AnswerCallbackQuery(err) => err,
+            BuildKeyboard(err) => err,
+            Create(err) => err,
+            EditMessage(err) => err,
+            FindRating(err) => err,
+            GetAdmin(err) => err,
+            Rate(err) => err,
+            SendMessage(err) => err,
+            SendPrompt(err) => err,
+            SendToAdmin(err) => err,
+            SetComment(err) => err,
+        })
+    }
+}
+
+impl ErrorVariant for RatingError {
+    fn variant(&self) -> &'static str {
+        use self::RatingError::*;
+        match self {
+            AnswerCallbackQuery(_) => "AnswerCallbackQuery",
+            BuildKeyboard(_) => "BuildKeyboard",
+            Create(_) => "Create",
+            EditMessage(_) => "EditMessage",
+            FindRating(_) => "FindRating",
+            GetAdmin(_) => "GetAdmin",
+            Rate(_) => "Rate",
+            SendMessage(_) => "SendMessage",
+            SendPrompt(_) => "SendPrompt",
+            SendToAdmin(_) => "SendToAdmin",
+            SetComment(_) => "SetComment",
+        }
+    }
+}
@@ -0,0 +1,29 @@
+use crate::{
+    config::Config,
+    services::{ConversationService, ConversationServiceError},
+};
+use carapax::types::{Integer, Message, User};
+
+pub async fn get_admin_chat_id(
+    config: &Config,
+    conversation_service: &ConversationService,
+    subscriber: &User,
+    message: Option<&Message>,
+) -> Result<Integer, ConversationServiceError> {
+    if config.routing.is_empty() {
+        return Ok(config.chat_id);
+    }
+    let conversation_id = conversation_service.get_or_create(subscriber.id).await?;
+    if let Some(admin_chat_id) = conversation_service
+        .get_admin_chat_id(conversation_id)
+        .await?
+        .filter(|chat_id| config.admin_chat_ids().contains(chat_id))
+    {
+        return Ok(admin_chat_id);
+    }
+    let admin_chat_id = config.route(subscriber, message, conversation_id);
+    conversation_service
+        .set_admin_chat_id(conversation_id, admin_chat_id)
+        .await?;
+    Ok(admin_chat_id)
+}
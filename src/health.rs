@@ -0,0 +1,167 @@
+use crate::{api::Api, db::Client, migrations};
+use carapax::{methods::GetMe, webhook::WebhookServiceFactory, ExecuteError, UpdateHandler};
+use hyper::{
+    service::{make_service_fn, service_fn, Service},
+    Body, Method, Request, Response, Server, StatusCode,
+};
+use std::{
+    convert::Infallible,
+    error::Error,
+    fmt,
+    future::Future,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use tokio_postgres::Error as PgError;
+
+pub use hyper::Error as HyperError;
+
+const PATH_HEALTH: &str = "/healthz";
+const PATH_READY: &str = "/readyz";
+// a revoked token is noticed within this time without calling getMe on every probe
+const API_CHECK_TTL: Duration = Duration::from_secs(60);
+
+#[derive(Clone)]
+pub struct Health {
+    api: Api,
+    client: Arc<Client>,
+    api_checked_at: Arc<Mutex<Option<Instant>>>,
+}
+
+impl Health {
+    pub fn new(api: Api, client: Arc<Client>) -> Self {
+        Self {
+            api,
+            client,
+            api_checked_at: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    async fn check(&self) -> Result<(), HealthError> {
+        if !migrations::is_up_to_date(&self.client)
+            .await
+            .map_err(HealthError::CheckMigrations)?
+        {
+            return Err(HealthError::PendingMigrations);
+        }
+        let checked_at = *self.api_checked_at.lock().expect("api check time is poisoned");
+        if checked_at.is_none_or(|checked_at| checked_at.elapsed() >= API_CHECK_TTL) {
+            self.api.execute(GetMe).await.map_err(HealthError::GetMe)?;
+            *self.api_checked_at.lock().expect("api check time is poisoned") = Some(Instant::now());
+        }
+        Ok(())
+    }
+
+    async fn handle(&self, request: &Request<Body>) -> Option<Response<Body>> {
+        if request.method() != Method::GET {
+            return None;
+        }
+        match request.uri().path() {
+            PATH_HEALTH => Some(build_response(StatusCode::OK, "OK")),
+            PATH_READY => Some(match self.check().await {
+                Ok(()) => build_response(StatusCode::OK, "OK"),
+                Err(err) => {
+                    log::warn!("Not ready: {}", err);
+                    build_response(StatusCode::SERVICE_UNAVAILABLE, err.to_string())
+                }
+            }),
+            _ => None,
+        }
+    }
+}
+
+pub async fn run_server<S>(address: SocketAddr, health: Health, shutdown: S) -> Result<(), HyperError>
+where
+    S: Future<Output = ()>,
+{
+    let make_service = make_service_fn(move |_| {
+        let health = health.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |request| {
+                let health = health.clone();
+                async move {
+                    Ok::<_, Infallible>(match health.handle(&request).await {
+                        Some(response) => response,
+                        None => build_response(StatusCode::NOT_FOUND, ""),
+                    })
+                }
+            }))
+        }
+    });
+    Server::bind(&address)
+        .serve(make_service)
+        .with_graceful_shutdown(shutdown)
+        .await
+}
+
+pub async fn run_webhook_server<H, S>(
+    address: SocketAddr,
+    path: String,
+    handler: H,
+    health: Health,
+    shutdown: S,
+) -> Result<(), HyperError>
+where
+    H: UpdateHandler + Send + Sync + 'static,
+    H::Future: Send,
+    S: Future<Output = ()>,
+{
+    let webhook = match WebhookServiceFactory::new(path, handler).call(()).await {
+        Ok(webhook) => webhook,
+        Err(err) => match err {},
+    };
+    let make_service = make_service_fn(move |_| {
+        let (health, webhook) = (health.clone(), webhook.clone());
+        async move {
+            Ok::<_, Infallible>(service_fn(move |request| {
+                let (health, mut webhook) = (health.clone(), webhook.clone());
+                async move {
+                    match health.handle(&request).await {
+                        Some(response) => Ok(response),
+                        None => webhook.call(request).await,
+                    }
+                }
+            }))
+        }
+    });
+    Server::bind(&address)
+        .serve(make_service)
+        .with_graceful_shutdown(shutdown)
+        .await
+}
+
+fn build_response<T: Into<Body>>(status: StatusCode, body: T) -> Response<Body> {
+    let mut response = Response::new(body.into());
+    *response.status_mut() = status;
+    response
+}
+
+#[derive(Debug)]
+enum HealthError {
+    CheckMigrations(PgError),
+    GetMe(ExecuteError),
+    PendingMigrations,
+}
+
+impl fmt::Display for HealthError {
+    fn fmt(&self, out: &mut fmt::Formatter) -> fmt::Result {
+        use self::HealthError::*;
+        match self {
+            CheckMigrations(err) => write!(out, "could not check migrations: {}", err),
+            GetMe(err) => write!(out, "could not get bot info: {}", err),
+            PendingMigrations => write!(out, "migrations are not up to date"),
+        }
+    }
+}
+
+impl Error for HealthError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        use self::HealthError::*;
+        match self {
+            CheckMigrations(err) => Some(err),
+            GetMe(err) => Some(err),
+            PendingMigrations => None,
+        }
+    }
+}
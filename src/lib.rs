@@ -1,8 +1,14 @@
 mod access;
+mod api;
 mod app;
 mod config;
+mod db;
 mod handlers;
+mod health;
+mod metrics;
 mod migrations;
+mod scheduler;
 mod services;
+mod shutdown;
 
 pub use self::app::run;
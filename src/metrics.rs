@@ -0,0 +1,241 @@
+use crate::services::UserService;
+use carapax::{
+    types::{Update, UpdateKind},
+    Handler, TryFromInput,
+};
+use futures_util::future::BoxFuture;
+use hyper::{
+    service::{make_service_fn, service_fn},
+    Body, Method, Request, Response, Server, StatusCode,
+};
+use once_cell::sync::Lazy;
+use prometheus::{
+    Encoder, Histogram, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGauge, Opts, Registry, TextEncoder,
+};
+use std::{any::type_name, convert::Infallible, future::Future, net::SocketAddr, sync::Arc, time::Duration};
+
+pub use hyper::Error as HyperError;
+
+static METRICS: Lazy<Metrics> = Lazy::new(Metrics::new);
+
+struct Metrics {
+    registry: Registry,
+    updates: IntCounterVec,
+    forwarded_messages: IntCounterVec,
+    handler_errors: IntCounterVec,
+    api_request_duration: HistogramVec,
+    api_errors: IntCounterVec,
+    db_query_duration: Histogram,
+    db_errors: IntCounter,
+    blocked_users: IntGauge,
+}
+
+impl Metrics {
+    fn new() -> Self {
+        let registry = Registry::new_custom(Some(String::from("vincent")), None).expect("valid registry");
+        let metrics = Self {
+            registry,
+            updates: IntCounterVec::new(Opts::new("updates_total", "Incoming updates"), &["kind"])
+                .expect("valid metric"),
+            forwarded_messages: IntCounterVec::new(
+                Opts::new(
+                    "forwarded_messages_total",
+                    "Messages forwarded between subscribers and admins",
+                ),
+                &["direction"],
+            )
+            .expect("valid metric"),
+            handler_errors: IntCounterVec::new(
+                Opts::new(
+                    "handler_errors_total",
+                    "Errors returned by handlers and background tasks",
+                ),
+                &["error", "variant"],
+            )
+            .expect("valid metric"),
+            api_request_duration: HistogramVec::new(
+                HistogramOpts::new("api_request_duration_seconds", "Telegram Bot API request latency"),
+                &["method"],
+            )
+            .expect("valid metric"),
+            api_errors: IntCounterVec::new(
+                Opts::new("api_errors_total", "Failed Telegram Bot API requests"),
+                &["method"],
+            )
+            .expect("valid metric"),
+            db_query_duration: Histogram::with_opts(HistogramOpts::new(
+                "db_query_duration_seconds",
+                "PostgreSQL query latency",
+            ))
+            .expect("valid metric"),
+            db_errors: IntCounter::new("db_errors_total", "Failed PostgreSQL queries").expect("valid metric"),
+            blocked_users: IntGauge::new("blocked_users", "Users blocked by admins").expect("valid metric"),
+        };
+        for collector in [
+            Box::new(metrics.updates.clone()) as Box<dyn prometheus::core::Collector>,
+            Box::new(metrics.forwarded_messages.clone()),
+            Box::new(metrics.handler_errors.clone()),
+            Box::new(metrics.api_request_duration.clone()),
+            Box::new(metrics.api_errors.clone()),
+            Box::new(metrics.db_query_duration.clone()),
+            Box::new(metrics.db_errors.clone()),
+            Box::new(metrics.blocked_users.clone()),
+        ] {
+            metrics.registry.register(collector).expect("unique metric");
+        }
+        metrics
+    }
+}
+
+pub fn record_update(update: &Update) {
+    let kind = match update.kind {
+        UpdateKind::Message(_) => "message",
+        UpdateKind::EditedMessage(_) => "edited_message",
+        UpdateKind::ChannelPost(_) => "channel_post",
+        UpdateKind::EditedChannelPost(_) => "edited_channel_post",
+        UpdateKind::InlineQuery(_) => "inline_query",
+        UpdateKind::ChosenInlineResult(_) => "chosen_inline_result",
+        UpdateKind::CallbackQuery(_) => "callback_query",
+        UpdateKind::ShippingQuery(_) => "shipping_query",
+        UpdateKind::PreCheckoutQuery(_) => "pre_checkout_query",
+        UpdateKind::Poll(_) => "poll",
+        UpdateKind::PollAnswer(_) => "poll_answer",
+        UpdateKind::BotStatus(_) => "my_chat_member",
+        UpdateKind::UserStatus(_) => "chat_member",
+        UpdateKind::ChatJoinRequest(_) => "chat_join_request",
+        UpdateKind::Unknown(_) => "unknown",
+    };
+    METRICS.updates.with_label_values(&[kind]).inc();
+}
+
+pub fn record_forwarded_to_admins(count: usize) {
+    METRICS
+        .forwarded_messages
+        .with_label_values(&["to_admins"])
+        .inc_by(count as u64);
+}
+
+pub fn record_forwarded_to_subscriber(count: usize) {
+    METRICS
+        .forwarded_messages
+        .with_label_values(&["to_subscriber"])
+        .inc_by(count as u64);
+}
+
+/// An error which can be counted by its variant
+pub trait ErrorVariant {
+    /// Returns the name of the variant, used as a metric label
+    fn variant(&self) -> &'static str;
+}
+
+pub fn record_error<E: ErrorVariant>(err: &E) {
+    let error = type_name::<E>().rsplit("::").next().unwrap_or_default();
+    METRICS.handler_errors.with_label_values(&[error, err.variant()]).inc();
+}
+
+#[derive(Clone)]
+pub struct TrackErrors<H> {
+    handler: H,
+}
+
+impl<H, I, E> Handler<I> for TrackErrors<H>
+where
+    H: Handler<I, Output = Result<(), E>>,
+    H::Future: 'static,
+    I: TryFromInput,
+    E: ErrorVariant + Send + 'static,
+{
+    type Output = Result<(), E>;
+    type Future = BoxFuture<'static, Self::Output>;
+
+    fn handle(&self, input: I) -> Self::Future {
+        let future = self.handler.handle(input);
+        Box::pin(async move {
+            let result = future.await;
+            if let Err(ref err) = result {
+                record_error(err);
+            }
+            result
+        })
+    }
+}
+
+pub trait TrackErrorsExt<I>: Sized {
+    fn track_errors(self) -> TrackErrors<Self>;
+}
+
+impl<H, I> TrackErrorsExt<I> for H
+where
+    H: Handler<I>,
+    I: TryFromInput,
+{
+    fn track_errors(self) -> TrackErrors<Self> {
+        TrackErrors { handler: self }
+    }
+}
+
+pub fn observe_api_request(method: &str, duration: Duration, is_failed: bool) {
+    METRICS
+        .api_request_duration
+        .with_label_values(&[method])
+        .observe(duration.as_secs_f64());
+    if is_failed {
+        METRICS.api_errors.with_label_values(&[method]).inc();
+    }
+}
+
+pub fn observe_db_query(duration: Duration, is_failed: bool) {
+    METRICS.db_query_duration.observe(duration.as_secs_f64());
+    if is_failed {
+        METRICS.db_errors.inc();
+    }
+}
+
+pub async fn run_server<S>(
+    address: SocketAddr,
+    path: String,
+    user_service: UserService,
+    shutdown: S,
+) -> Result<(), HyperError>
+where
+    S: Future<Output = ()>,
+{
+    let path = Arc::new(path);
+    let make_service = make_service_fn(move |_| {
+        let (path, user_service) = (path.clone(), user_service.clone());
+        async move {
+            Ok::<_, Infallible>(service_fn(move |request| {
+                let (path, user_service) = (path.clone(), user_service.clone());
+                async move { Ok::<_, Infallible>(handle_request(&path, &user_service, request).await) }
+            }))
+        }
+    });
+    Server::bind(&address)
+        .serve(make_service)
+        .with_graceful_shutdown(shutdown)
+        .await
+}
+
+async fn handle_request(path: &str, user_service: &UserService, request: Request<Body>) -> Response<Body> {
+    if request.method() != Method::GET || request.uri().path() != path {
+        return build_response(StatusCode::NOT_FOUND, Body::empty());
+    }
+    match user_service.count_blocked().await {
+        Ok(count) => METRICS.blocked_users.set(count),
+        Err(err) => log::error!("Metrics: {}", err),
+    }
+    let mut buffer = Vec::new();
+    match TextEncoder::new().encode(&METRICS.registry.gather(), &mut buffer) {
+        Ok(()) => build_response(StatusCode::OK, Body::from(buffer)),
+        Err(err) => {
+            log::error!("Metrics: could not encode metrics: {}", err);
+            build_response(StatusCode::INTERNAL_SERVER_ERROR, Body::empty())
+        }
+    }
+}
+
+fn build_response(status: StatusCode, body: Body) -> Response<Body> {
+    let mut response = Response::new(body);
+    *response.status_mut() = status;
+    response
+}
@@ -1,9 +1,12 @@
+use crate::db::Client as DbClient;
 use refinery::{Error, Migration, Report, Runner};
-use tokio_postgres::Client;
+use tokio_postgres::{Client, Error as PgError};
 
 mod types;
 mod versions;
 
+// the error type comes from refinery and can not be made smaller here
+#[allow(clippy::result_large_err)]
 pub async fn run(client: &mut Client) -> Result<Report, Error> {
     let migrations: Result<Vec<Migration>, Error> = self::versions::build()
         .into_iter()
@@ -13,3 +16,12 @@ pub async fn run(client: &mut Client) -> Result<Report, Error> {
     let runner = Runner::new(&migrations?);
     runner.run_async(client).await
 }
+
+pub async fn is_up_to_date(client: &DbClient) -> Result<bool, PgError> {
+    let row = client
+        .query_one("SELECT MAX(version) FROM refinery_schema_history", &[])
+        .await?;
+    let version: Option<i32> = row.get(0);
+    let latest = self::versions::build().len() as i32 - 1;
+    Ok(version.map(|version| version >= latest).unwrap_or(false))
+}
@@ -8,7 +8,36 @@ macro_rules! version {
 }
 
 pub fn build() -> Vec<Version> {
-    vec![version!(create_users), version!(create_message_links)]
+    vec![
+        version!(create_users),
+        version!(create_message_links),
+        version!(create_snippets),
+        version!(create_scheduled_messages),
+        version!(add_users_away_message_sent_at),
+        version!(create_settings),
+        version!(create_rate_limits),
+        version!(create_verifications),
+        version!(create_conversations),
+        version!(create_admins),
+        version!(create_notes),
+        version!(create_pending_replies),
+        version!(add_conversations_admin_chat_id),
+        version!(add_admins_role),
+        version!(add_conversations_assignee_user_id),
+        version!(add_conversations_awaiting),
+        version!(create_ratings),
+        version!(add_message_links_status),
+        version!(create_greetings),
+        version!(add_users_source),
+        version!(add_scheduled_messages_reply_to),
+        version!(add_scheduled_messages_admin_user_id),
+        version!(add_pending_replies_sources),
+        version!(add_conversations_answered_by_user_id),
+        version!(widen_users_source),
+        version!(add_scheduled_messages_broadcast_progress),
+        version!(add_verifications_challenged_at),
+        version!(add_pending_replies_admin_user_id),
+    ]
 }
 
 pub struct Version {
@@ -66,12 +95,312 @@ fn create_message_links() -> Migration {
         table.add_foreign_key(&["subscriber_user_id"], "users", &["id"]);
         table.add_index(
             "message_links_subscriber_idx",
-            types::index(&["subscriber_chat_id", "subscriber_message_id"]),
+            types::index(["subscriber_chat_id", "subscriber_message_id"]),
         );
         table.add_index(
             "message_links_admin_idx",
-            types::index(&["admin_chat_id", "admin_message_id"]),
+            types::index(["admin_chat_id", "admin_message_id"]),
         );
     });
     migration
 }
+
+fn create_snippets() -> Migration {
+    let mut migration = Migration::new();
+    migration.create_table("snippets", |table| {
+        table.add_column("id", types::primary());
+        table.add_column("name", types::varchar(255).unique(true));
+        table.add_column("chat_id", types::bigint());
+        table.add_column("message_id", types::bigint());
+        table.add_column("created_at", types::utc_timestamp());
+    });
+    migration
+}
+
+fn create_scheduled_messages() -> Migration {
+    let mut migration = Migration::new();
+    migration.create_table("scheduled_messages", |table| {
+        table.add_column("id", types::primary());
+        table.add_column("chat_id", types::bigint());
+        table.add_column("message_id", types::bigint());
+        table.add_column("subscriber_user_id", types::bigint().nullable(true));
+        table.add_column("send_at", types::utc_timestamp());
+        table.add_column("created_at", types::utc_timestamp());
+        table.add_foreign_key(&["subscriber_user_id"], "users", &["id"]);
+        table.add_index("scheduled_messages_send_at_idx", types::index(["send_at"]));
+    });
+    migration
+}
+
+fn add_users_away_message_sent_at() -> Migration {
+    let mut migration = Migration::new();
+    migration.change_table("users", |table| {
+        table.add_column("away_message_sent_at", types::utc_timestamp().nullable(true));
+    });
+    migration
+}
+
+fn create_settings() -> Migration {
+    let mut migration = Migration::new();
+    migration.create_table("settings", |table| {
+        table.add_column("key", types::varchar(255).primary(true));
+        table.add_column("value", types::text());
+        table.add_column("updated_at", types::utc_timestamp());
+    });
+    migration
+}
+
+fn create_rate_limits() -> Migration {
+    let mut migration = Migration::new();
+    migration.change_table("users", |table| {
+        table.add_column("blocked_until", types::utc_timestamp().nullable(true));
+    });
+    migration.create_table("rate_limits", |table| {
+        table.add_column("user_id", types::bigint().primary(true));
+        table.add_column("window_start", types::utc_timestamp());
+        table.add_column("messages", types::integer());
+        table.add_column("violations", types::integer().default(0));
+        table.add_foreign_key(&["user_id"], "users", &["id"]);
+    });
+    migration
+}
+
+fn create_verifications() -> Migration {
+    let mut migration = Migration::new();
+    migration.create_table("verifications", |table| {
+        table.add_column("user_id", types::bigint().primary(true));
+        table.add_column("answer", types::integer().nullable(true));
+        table.add_column("is_verified", types::boolean().default(false));
+        table.add_column("created_at", types::utc_timestamp());
+        table.add_column("verified_at", types::utc_timestamp().nullable(true));
+        table.add_foreign_key(&["user_id"], "users", &["id"]);
+    });
+    migration.create_table("held_messages", |table| {
+        table.add_column("id", types::primary());
+        table.add_column("user_id", types::bigint());
+        table.add_column("chat_id", types::bigint());
+        table.add_column("message_id", types::bigint());
+        table.add_column("created_at", types::utc_timestamp());
+        table.add_foreign_key(&["user_id"], "users", &["id"]);
+        table.add_index("held_messages_user_idx", types::index(["user_id"]));
+    });
+    migration
+}
+
+fn create_conversations() -> Migration {
+    let mut migration = Migration::new();
+    migration.change_table("users", |table| {
+        table.add_column("language_code", types::varchar(255).nullable(true));
+    });
+    migration.create_table("conversations", |table| {
+        table.add_column("id", types::primary());
+        table.add_column("subscriber_user_id", types::bigint());
+        table.add_column("created_at", types::utc_timestamp());
+        table.add_column("closed_at", types::utc_timestamp().nullable(true));
+        table.add_foreign_key(&["subscriber_user_id"], "users", &["id"]);
+    });
+    migration.inject_custom(
+        "CREATE UNIQUE INDEX conversations_open_idx ON conversations (subscriber_user_id) WHERE closed_at IS NULL",
+    );
+    migration
+}
+
+fn create_admins() -> Migration {
+    let mut migration = Migration::new();
+    migration.create_table("admins", |table| {
+        table.add_column("user_id", types::bigint().primary(true));
+        table.add_column("signature", types::varchar(255).nullable(true));
+        table.add_column("created_at", types::utc_timestamp());
+        table.add_column("updated_at", types::utc_timestamp().nullable(true));
+    });
+    migration
+}
+
+fn create_notes() -> Migration {
+    let mut migration = Migration::new();
+    migration.create_table("notes", |table| {
+        table.add_column("id", types::primary());
+        table.add_column("conversation_id", types::integer());
+        table.add_column("admin_user_id", types::bigint().nullable(true));
+        table.add_column("text", types::text());
+        table.add_column("created_at", types::utc_timestamp());
+        table.add_foreign_key(&["conversation_id"], "conversations", &["id"]);
+        table.add_index("notes_conversation_idx", types::index(["conversation_id"]));
+    });
+    migration
+}
+
+fn create_pending_replies() -> Migration {
+    let mut migration = Migration::new();
+    migration.create_table("pending_replies", |table| {
+        table.add_column("id", types::primary());
+        table.add_column("chat_id", types::bigint());
+        table.add_column("message_id", types::bigint());
+        table.add_column("reply_to_message_id", types::bigint());
+        table.add_column("preview_message_id", types::bigint().nullable(true));
+        table.add_column("expires_at", types::utc_timestamp());
+        table.add_column("created_at", types::utc_timestamp());
+        table.add_index("pending_replies_expires_at_idx", types::index(["expires_at"]));
+    });
+    migration
+}
+
+fn add_conversations_admin_chat_id() -> Migration {
+    let mut migration = Migration::new();
+    migration.change_table("conversations", |table| {
+        table.add_column("admin_chat_id", types::bigint().nullable(true));
+    });
+    migration
+}
+
+fn add_admins_role() -> Migration {
+    let mut migration = Migration::new();
+    migration.change_table("admins", |table| {
+        table.add_column("role", types::varchar(16).nullable(true));
+    });
+    migration
+}
+
+fn add_conversations_assignee_user_id() -> Migration {
+    let mut migration = Migration::new();
+    migration.change_table("conversations", |table| {
+        table.add_column("assignee_user_id", types::bigint().nullable(true));
+    });
+    migration
+}
+
+fn add_conversations_awaiting() -> Migration {
+    let mut migration = Migration::new();
+    migration.change_table("conversations", |table| {
+        table.add_column("awaiting_since", types::utc_timestamp().nullable(true));
+        table.add_column("awaiting_admin_chat_id", types::bigint().nullable(true));
+        table.add_column("awaiting_admin_message_id", types::bigint().nullable(true));
+        table.add_column("reminded_at", types::utc_timestamp().nullable(true));
+        table.add_column("escalated_at", types::utc_timestamp().nullable(true));
+    });
+    migration
+}
+
+fn create_ratings() -> Migration {
+    let mut migration = Migration::new();
+    migration.change_table("conversations", |table| {
+        table.add_column("closed_by_user_id", types::bigint().nullable(true));
+    });
+    migration.create_table("ratings", |table| {
+        table.add_column("conversation_id", types::integer().primary(true));
+        table.add_column("subscriber_user_id", types::bigint());
+        table.add_column("admin_user_id", types::bigint().nullable(true));
+        table.add_column("admin_chat_id", types::bigint());
+        table.add_column("chat_id", types::bigint());
+        table.add_column("message_id", types::bigint());
+        table.add_column("score", types::integer().nullable(true));
+        table.add_column("comment", types::text().nullable(true));
+        table.add_column("created_at", types::utc_timestamp());
+        table.add_column("rated_at", types::utc_timestamp().nullable(true));
+        table.add_foreign_key(&["conversation_id"], "conversations", &["id"]);
+        table.add_foreign_key(&["subscriber_user_id"], "users", &["id"]);
+        table.add_index("ratings_message_idx", types::index(["chat_id", "message_id"]));
+    });
+    migration
+}
+
+fn add_message_links_status() -> Migration {
+    let mut migration = Migration::new();
+    migration.change_table("message_links", |table| {
+        table.add_column("status", types::varchar(16).nullable(true));
+        table.add_column("status_message_id", types::bigint().nullable(true));
+    });
+    migration.inject_custom(
+        "CREATE INDEX message_links_delivered_idx ON message_links (subscriber_user_id) WHERE status = 'delivered'",
+    );
+    migration
+}
+
+fn create_greetings() -> Migration {
+    let mut migration = Migration::new();
+    migration.create_table("greetings", |table| {
+        table.add_column("name", types::varchar(255).primary(true));
+        table.add_column("chat_id", types::bigint());
+        table.add_column("message_id", types::bigint());
+        table.add_column("reply_markup", types::text().nullable(true));
+        table.add_column("created_at", types::utc_timestamp());
+    });
+    migration
+}
+
+fn add_users_source() -> Migration {
+    let mut migration = Migration::new();
+    migration.change_table("users", |table| {
+        table.add_column("source", types::varchar(32).nullable(true));
+        table.add_index("users_source_idx", types::index(["source"]));
+    });
+    migration
+}
+
+fn add_scheduled_messages_reply_to() -> Migration {
+    let mut migration = Migration::new();
+    migration.change_table("scheduled_messages", |table| {
+        table.add_column("prompt_message_id", types::bigint().nullable(true));
+        table.add_column("reply_to_message_id", types::bigint().nullable(true));
+    });
+    migration.inject_custom("ALTER TABLE scheduled_messages ALTER COLUMN message_id DROP NOT NULL");
+    migration
+}
+
+fn add_scheduled_messages_admin_user_id() -> Migration {
+    let mut migration = Migration::new();
+    migration.change_table("scheduled_messages", |table| {
+        table.add_column("admin_user_id", types::bigint().nullable(true));
+    });
+    migration
+}
+
+fn add_pending_replies_sources() -> Migration {
+    let mut migration = Migration::new();
+    migration.change_table("pending_replies", |table| {
+        table.add_column("source_chat_id", types::bigint().nullable(true));
+        table.add_column("source_message_id", types::bigint().nullable(true));
+    });
+    migration.inject_custom("ALTER TABLE pending_replies ADD COLUMN part_message_ids bigint[]");
+    migration
+}
+
+fn add_conversations_answered_by_user_id() -> Migration {
+    let mut migration = Migration::new();
+    migration.change_table("conversations", |table| {
+        table.add_column("answered_by_user_id", types::bigint().nullable(true));
+    });
+    migration
+}
+
+fn widen_users_source() -> Migration {
+    let mut migration = Migration::new();
+    migration.inject_custom("ALTER TABLE users ALTER COLUMN source TYPE varchar(64)");
+    migration
+}
+
+fn add_scheduled_messages_broadcast_progress() -> Migration {
+    let mut migration = Migration::new();
+    migration.change_table("scheduled_messages", |table| {
+        table.add_column("broadcast_user_id", types::bigint().nullable(true));
+        table.add_column("broadcast_sent", types::integer().default(0));
+        table.add_column("broadcast_failed", types::integer().default(0));
+    });
+    migration
+}
+
+fn add_verifications_challenged_at() -> Migration {
+    let mut migration = Migration::new();
+    migration.change_table("verifications", |table| {
+        table.add_column("challenged_at", types::utc_timestamp().nullable(true));
+    });
+    migration
+}
+
+fn add_pending_replies_admin_user_id() -> Migration {
+    let mut migration = Migration::new();
+    migration.change_table("pending_replies", |table| {
+        table.add_column("admin_user_id", types::bigint().nullable(true));
+    });
+    migration
+}
@@ -0,0 +1,492 @@
+use crate::{
+    api::Api,
+    config::{Config, Role},
+    handlers::{
+        admin::{send_reply, MessageError, Reply, ReplyTarget, MESSAGE_REPLY_EXPIRED},
+        receipt::{report_delivery, ReceiptError},
+    },
+    metrics::{self, ErrorVariant},
+    services::{
+        AdminService, AdminServiceError, AwaitingConversation, BroadcastProgress, ConversationService,
+        ConversationServiceError, MessageLinkDirection, MessageLinkService, MessageLinkServiceError,
+        PendingReplyService, PendingReplyServiceError, ScheduleService, ScheduleServiceError, ScheduledMessage,
+        UserService, UserServiceError,
+    },
+};
+use carapax::{
+    methods::{CopyMessage, EditMessageText, SendMessage},
+    types::{Integer, ParseMode},
+    ExecuteError,
+};
+use std::{error::Error, fmt, future::Future, time::Duration};
+use tokio::{
+    pin, select,
+    time::{interval, sleep},
+};
+
+const INTERVAL: Duration = Duration::from_secs(10);
+// broadcasts are sent in batches, so that a tick stays short and a shutdown does not wait for all subscribers
+const BROADCAST_BATCH_SIZE: i64 = 100;
+// keeps broadcasts below the limit of 30 messages per second
+const BROADCAST_DELAY: Duration = Duration::from_millis(50);
+const MESSAGE_BROADCAST_SENT: &str = "Scheduled broadcast has been sent to {sent}/{total} subscribers";
+const MESSAGE_ESCALATION: &str = "🚨 Escalation: {conversation}";
+const MESSAGE_REMINDER: &str = "⏰ No reply for {time}";
+const MESSAGE_SCHEDULED_FAILED: &str = "Could not send scheduled message: {error}";
+const MESSAGE_SCHEDULED_SENT: &str = "Scheduled message has been sent";
+
+pub struct Scheduler {
+    api: Api,
+    config: Config,
+    admin_service: AdminService,
+    conversation_service: ConversationService,
+    message_link_service: MessageLinkService,
+    pending_reply_service: PendingReplyService,
+    schedule_service: ScheduleService,
+    user_service: UserService,
+}
+
+impl Scheduler {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        api: Api,
+        config: Config,
+        admin_service: AdminService,
+        conversation_service: ConversationService,
+        message_link_service: MessageLinkService,
+        pending_reply_service: PendingReplyService,
+        schedule_service: ScheduleService,
+        user_service: UserService,
+    ) -> Self {
+        Self {
+            api,
+            config,
+            admin_service,
+            conversation_service,
+            message_link_service,
+            pending_reply_service,
+            schedule_service,
+            user_service,
+        }
+    }
+
+    pub async fn run<S>(self, shutdown: S)
+    where
+        S: Future<Output = ()>,
+    {
+        let mut interval = interval(INTERVAL);
+        pin!(shutdown);
+        loop {
+            // a tick in progress is completed before stopping
+            select! {
+                _ = interval.tick() => {}
+                _ = &mut shutdown => break,
+            }
+            if let Err(err) = self.send_scheduled_messages().await {
+                metrics::record_error(&err);
+                log::error!("Scheduler: {}", err);
+            }
+            if let Err(err) = self.expire_pending_replies().await {
+                metrics::record_error(&err);
+                log::error!("Scheduler: {}", err);
+            }
+            if let Err(err) = self.send_reminders().await {
+                metrics::record_error(&err);
+                log::error!("Scheduler: {}", err);
+            }
+        }
+    }
+
+    async fn send_reminders(&self) -> Result<(), SchedulerError> {
+        let reminders = match self.config.reminders {
+            Some(ref reminders) => reminders,
+            None => return Ok(()),
+        };
+        for conversation in self
+            .conversation_service
+            .get_due_reminders(reminders.remind_after())
+            .await
+            .map_err(SchedulerError::GetDueReminders)?
+        {
+            let text = self
+                .config
+                .admin_text("reminder", MESSAGE_REMINDER)
+                .replace("{time}", &conversation.waiting_time());
+            // a reminder that could not be sent is retried on the next tick
+            if let Err(err) = self
+                .api
+                .execute(
+                    SendMessage::new(conversation.admin_chat_id(), text)
+                        .reply_to_message_id(conversation.admin_message_id()),
+                )
+                .await
+            {
+                log::error!("Scheduler: could not send reminder: {}", err);
+                continue;
+            }
+            self.conversation_service
+                .mark_reminded(conversation.id())
+                .await
+                .map_err(SchedulerError::MarkReminded)?;
+        }
+        let escalate_after = match reminders.escalate_after() {
+            Some(escalate_after) => escalate_after,
+            None => return Ok(()),
+        };
+        let conversations = self
+            .conversation_service
+            .get_due_escalations(escalate_after)
+            .await
+            .map_err(SchedulerError::GetDueEscalations)?;
+        if conversations.is_empty() {
+            return Ok(());
+        }
+        let supervisors = self.get_supervisor_mentions().await?;
+        for conversation in conversations {
+            if let Err(err) = self
+                .send_escalation(&conversation, reminders.escalation_chat_id(), &supervisors)
+                .await
+            {
+                log::error!("Scheduler: could not send escalation: {}", err);
+                continue;
+            }
+            self.conversation_service
+                .mark_escalated(conversation.id())
+                .await
+                .map_err(SchedulerError::MarkEscalated)?;
+        }
+        Ok(())
+    }
+
+    async fn send_escalation(
+        &self,
+        conversation: &AwaitingConversation,
+        escalation_chat_id: Option<Integer>,
+        supervisors: &[String],
+    ) -> Result<(), ExecuteError> {
+        let mut text = self
+            .config
+            .admin_text("escalation", MESSAGE_ESCALATION)
+            .replace("{conversation}", &conversation.to_string());
+        if !supervisors.is_empty() {
+            text = format!("{}\n{}", text, supervisors.join(", "));
+        }
+        let method = match escalation_chat_id {
+            Some(chat_id) => SendMessage::new(chat_id, text),
+            None => SendMessage::new(conversation.admin_chat_id(), text)
+                .reply_to_message_id(conversation.admin_message_id()),
+        };
+        self.api.execute(method.parse_mode(ParseMode::Html)).await?;
+        Ok(())
+    }
+
+    async fn get_supervisor_mentions(&self) -> Result<Vec<String>, SchedulerError> {
+        // roles from the config take precedence over roles assigned with /role
+        let mut roles = self.admin_service.get_roles().await.map_err(SchedulerError::GetRoles)?;
+        roles.extend(self.config.roles.clone());
+        let mut mentions = Vec::new();
+        for (user_id, role) in roles {
+            if role != Role::Supervisor {
+                continue;
+            }
+            let mention = match self.user_service.get(user_id).await.map_err(SchedulerError::GetUser)? {
+                Some(user) => user
+                    .get_mention(ParseMode::Html)
+                    .unwrap_or_else(|_| user.get_full_name()),
+                None => format!(r#"<a href="tg://user?id={}">{}</a>"#, user_id, user_id),
+            };
+            mentions.push(mention);
+        }
+        Ok(mentions)
+    }
+
+    async fn expire_pending_replies(&self) -> Result<(), SchedulerError> {
+        for pending_reply in self
+            .pending_reply_service
+            .take_expired()
+            .await
+            .map_err(SchedulerError::TakeExpired)?
+        {
+            if let Some(preview_message_id) = pending_reply.preview_message_id() {
+                // the preview could have been deleted, the rest of the replies are expired anyway
+                if let Err(err) = self
+                    .api
+                    .execute(EditMessageText::new(
+                        pending_reply.chat_id(),
+                        preview_message_id,
+                        self.config.admin_text("confirm_expired", MESSAGE_REPLY_EXPIRED),
+                    ))
+                    .await
+                {
+                    log::error!("Scheduler: could not edit pending reply preview: {}", err);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    async fn send_scheduled_messages(&self) -> Result<(), SchedulerError> {
+        for message in self.schedule_service.get_due().await.map_err(SchedulerError::GetDue)? {
+            // a failed job stays in the queue and is retried on the next tick
+            if let Err(err) = self.send_scheduled_message(&message).await {
+                metrics::record_error(&err);
+                log::error!("Scheduler: could not send scheduled message: {}", err);
+            }
+        }
+        Ok(())
+    }
+
+    async fn send_scheduled_message(&self, message: &ScheduledMessage) -> Result<(), SchedulerError> {
+        let (id, message_id) = match (message.id(), message.message_id()) {
+            (Some(id), Some(message_id)) => (id, message_id),
+            _ => return Ok(()),
+        };
+        let report = match message.subscriber_user_id() {
+            Some(user_id) => match self.send_reply(message, message_id, user_id).await {
+                Ok(()) => String::from(self.config.admin_text("schedule_sent", MESSAGE_SCHEDULED_SENT)),
+                // rejected by Telegram, there is no point in retrying
+                Err(MessageError::CopyMessage(ExecuteError::Response(response)))
+                | Err(MessageError::FetchMessage(ExecuteError::Response(response)))
+                | Err(MessageError::SendMessage(ExecuteError::Response(response))) => self
+                    .config
+                    .admin_text("schedule_failed", MESSAGE_SCHEDULED_FAILED)
+                    .replace("{error}", response.description()),
+                Err(err) => return Err(SchedulerError::SendReply(err)),
+            },
+            None => match self.send_broadcast(id, message, message_id).await? {
+                Some(progress) => self
+                    .config
+                    .admin_text("schedule_broadcast_sent", MESSAGE_BROADCAST_SENT)
+                    .replace("{sent}", &progress.sent().to_string())
+                    .replace("{total}", &progress.total().to_string()),
+                // the rest of the subscribers get the broadcast on the next tick
+                None => return Ok(()),
+            },
+        };
+        self.schedule_service.delete(id).await.map_err(SchedulerError::Delete)?;
+        let mut method = SendMessage::new(message.chat_id(), report);
+        if let Some(report_message_id) = message.report_message_id() {
+            method = method.reply_to_message_id(report_message_id);
+        }
+        self.api.execute(method).await.map_err(SchedulerError::SendReport)?;
+        Ok(())
+    }
+
+    async fn send_reply(
+        &self,
+        message: &ScheduledMessage,
+        message_id: Integer,
+        user_id: Integer,
+    ) -> Result<(), MessageError> {
+        let result = send_reply(
+            &self.api,
+            &self.config,
+            &self.admin_service,
+            &self.message_link_service,
+            &ReplyTarget::new(user_id, message.reply_to_message_id()),
+            Reply::Stored {
+                chat_id: message.chat_id(),
+                message_id,
+                admin_chat_id: message.chat_id(),
+                admin_message_id: message_id,
+            },
+            message.admin_user_id(),
+        )
+        .await;
+        if let Err(err) = self.report_delivery(message, message_id, user_id, &result).await {
+            metrics::record_error(&err);
+            log::error!("Scheduler: {}", err);
+        }
+        match result {
+            Ok(()) => {}
+            // the reply has been delivered, so the job must not be retried because of the bookkeeping
+            Err(MessageError::CreateLink(err)) => log::error!("Scheduler: {}", err),
+            Err(err) => return Err(err),
+        }
+        if let Err(err) = self
+            .conversation_service
+            .mark_answered(user_id, message.admin_user_id())
+            .await
+        {
+            log::error!("Scheduler: {}", err);
+        }
+        Ok(())
+    }
+
+    async fn report_delivery(
+        &self,
+        message: &ScheduledMessage,
+        message_id: Integer,
+        user_id: Integer,
+        result: &Result<(), MessageError>,
+    ) -> Result<(), SchedulerError> {
+        // only threaded replies have a subscriber message to show the receipt under
+        let reply_to_message_id = match message.reply_to_message_id() {
+            Some(reply_to_message_id) => reply_to_message_id,
+            None => return Ok(()),
+        };
+        let link = match self
+            .message_link_service
+            .find(user_id, reply_to_message_id, MessageLinkDirection::Subscriber)
+            .await
+            .map_err(SchedulerError::FindLink)?
+        {
+            Some(link) => link,
+            None => return Ok(()),
+        };
+        report_delivery(
+            &self.api,
+            &self.config,
+            &self.conversation_service,
+            &self.message_link_service,
+            &link,
+            (message.chat_id(), message_id),
+            result,
+        )
+        .await
+        .map_err(SchedulerError::Receipt)
+    }
+
+    /// Sends a broadcast to the next batch of subscribers
+    ///
+    /// Returns the progress when every subscriber has got the broadcast.
+    async fn send_broadcast(
+        &self,
+        id: i32,
+        message: &ScheduledMessage,
+        message_id: Integer,
+    ) -> Result<Option<BroadcastProgress>, SchedulerError> {
+        let mut progress = message.broadcast_progress();
+        let user_ids = self
+            .user_service
+            .get_subscriber_ids(progress.user_id(), BROADCAST_BATCH_SIZE)
+            .await
+            .map_err(SchedulerError::GetSubscribers)?;
+        let is_finished = (user_ids.len() as i64) < BROADCAST_BATCH_SIZE;
+        for user_id in user_ids {
+            let result = self.copy_broadcast(message.chat_id(), message_id, user_id).await;
+            if let Err(ref err) = result {
+                log::error!("Scheduler: {} (user_id={})", err, user_id);
+            }
+            progress.add(user_id, result.is_ok());
+            // saved after every subscriber, so that nobody gets the broadcast twice after a restart
+            self.schedule_service
+                .set_broadcast_progress(id, progress)
+                .await
+                .map_err(SchedulerError::SetBroadcastProgress)?;
+            sleep(BROADCAST_DELAY).await;
+        }
+        Ok(if is_finished { Some(progress) } else { None })
+    }
+
+    async fn copy_broadcast(
+        &self,
+        chat_id: Integer,
+        message_id: Integer,
+        user_id: Integer,
+    ) -> Result<(), ExecuteError> {
+        if let Err(err) = self.api.execute(CopyMessage::new(user_id, chat_id, message_id)).await {
+            let retry_after = match err {
+                ExecuteError::Response(ref response) => response.retry_after(),
+                _ => None,
+            };
+            // flood control, the message can be sent again after the given time
+            match retry_after {
+                Some(seconds) => {
+                    sleep(Duration::from_secs(seconds)).await;
+                    self.api.execute(CopyMessage::new(user_id, chat_id, message_id)).await?;
+                }
+                None => return Err(err),
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+enum SchedulerError {
+    Delete(ScheduleServiceError),
+    FindLink(MessageLinkServiceError),
+    GetDue(ScheduleServiceError),
+    GetDueEscalations(ConversationServiceError),
+    GetDueReminders(ConversationServiceError),
+    GetRoles(AdminServiceError),
+    GetSubscribers(UserServiceError),
+    GetUser(UserServiceError),
+    MarkEscalated(ConversationServiceError),
+    MarkReminded(ConversationServiceError),
+    Receipt(ReceiptError),
+    SendReply(MessageError),
+    SendReport(ExecuteError),
+    SetBroadcastProgress(ScheduleServiceError),
+    TakeExpired(PendingReplyServiceError),
+}
+
+impl fmt::Display for SchedulerError {
+    fn fmt(&self, out: &mut fmt::Formatter) -> fmt::Result {
+        use self::SchedulerError::*;
+        match self {
+            Delete(err) => err.fmt(out),
+            FindLink(err) => err.fmt(out),
+            GetDue(err) => err.fmt(out),
+            GetDueEscalations(err) => err.fmt(out),
+            GetDueReminders(err) => err.fmt(out),
+            GetRoles(err) => err.fmt(out),
+            GetSubscribers(err) => err.fmt(out),
+            GetUser(err) => err.fmt(out),
+            MarkEscalated(err) => err.fmt(out),
+            MarkReminded(err) => err.fmt(out),
+            Receipt(err) => err.fmt(out),
+            SendReply(err) => err.fmt(out),
+            SendReport(err) => err.fmt(out),
+            SetBroadcastProgress(err) => err.fmt(out),
+            TakeExpired(err) => err.fmt(out),
+        }
+    }
+}
+
+impl Error for SchedulerError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        use self::SchedulerError::*;
+        Some(match self {
+            Delete(err) => err,
+            FindLink(err) => err,
+            GetDue(err) => err,
+            GetDueEscalations(err) => err,
+            GetDueReminders(err) => err,
+            GetRoles(err) => err,
+            GetSubscribers(err) => err,
+            GetUser(err) => err,
+            MarkEscalated(err) => err,
+            MarkReminded(err) => err,
+            Receipt(err) => err,
+            SendReply(err) => err,
+            SendReport(err) => err,
+            SetBroadcastProgress(err) => err,
+            TakeExpired(err) => err,
+        })
+    }
+}
+
+impl ErrorVariant for SchedulerError {
+    fn variant(&self) -> &'static str {
+        use self::SchedulerError::*;
+        match self {
+            Delete(_) => "Delete",
+            FindLink(_) => "FindLink",
+            GetDue(_) => "GetDue",
+            GetDueEscalations(_) => "GetDueEscalations",
+            GetDueReminders(_) => "GetDueReminders",
+            GetRoles(_) => "GetRoles",
+            GetSubscribers(_) => "GetSubscribers",
+            GetUser(_) => "GetUser",
+            MarkEscalated(_) => "MarkEscalated",
+            MarkReminded(_) => "MarkReminded",
+            Receipt(_) => "Receipt",
+            SendReply(_) => "SendReply",
+            SendReport(_) => "SendReport",
+            SetBroadcastProgress(_) => "SetBroadcastProgress",
+            TakeExpired(_) => "TakeExpired",
+        }
+    }
+}
@@ -0,0 +1,134 @@
+use crate::{config::Role, db::Client};
+use carapax::types::Integer;
+use chrono::Utc;
+use std::{collections::HashMap, error::Error, fmt, sync::Arc};
+use tokio_postgres::Error as ClientError;
+
+#[derive(Clone)]
+pub struct AdminService {
+    client: Arc<Client>,
+}
+
+impl AdminService {
+    pub fn new(client: Arc<Client>) -> Self {
+        Self { client }
+    }
+
+    pub async fn get_signature(&self, user_id: Integer) -> Result<Option<String>, AdminServiceError> {
+        let row = self
+            .client
+            .query_opt("SELECT signature FROM admins WHERE user_id = $1", &[&user_id])
+            .await
+            .map_err(|source| AdminServiceError::GetSignature { source, user_id })?;
+        Ok(row.and_then(|row| row.get(0)))
+    }
+
+    pub async fn set_signature(&self, user_id: Integer, signature: Option<&str>) -> Result<(), AdminServiceError> {
+        let now = Utc::now().naive_utc();
+        self.client
+            .execute(
+                r#"
+                INSERT INTO admins
+                    (user_id, signature, created_at)
+                VALUES
+                    ($1, $2, $3)
+                ON CONFLICT (user_id) DO UPDATE SET
+                    signature = EXCLUDED.signature,
+                    updated_at = EXCLUDED.created_at
+                "#,
+                &[&user_id, &signature, &now],
+            )
+            .await
+            .map_err(|source| AdminServiceError::SetSignature { source, user_id })?;
+        Ok(())
+    }
+
+    pub async fn get_role(&self, user_id: Integer) -> Result<Option<Role>, AdminServiceError> {
+        let row = self
+            .client
+            .query_opt("SELECT role FROM admins WHERE user_id = $1", &[&user_id])
+            .await
+            .map_err(|source| AdminServiceError::GetRole { source, user_id })?;
+        Ok(row
+            .and_then(|row| row.get::<_, Option<String>>(0))
+            .and_then(|role| role.parse().ok()))
+    }
+
+    pub async fn get_roles(&self) -> Result<HashMap<Integer, Role>, AdminServiceError> {
+        let rows = self
+            .client
+            .query("SELECT user_id, role FROM admins WHERE role IS NOT NULL", &[])
+            .await
+            .map_err(|source| AdminServiceError::GetRoles { source })?;
+        Ok(rows
+            .into_iter()
+            .filter_map(|row| {
+                let role: String = row.get(1);
+                role.parse().ok().map(|role| (row.get(0), role))
+            })
+            .collect())
+    }
+
+    pub async fn set_role(&self, user_id: Integer, role: Option<Role>) -> Result<(), AdminServiceError> {
+        let now = Utc::now().naive_utc();
+        self.client
+            .execute(
+                r#"
+                INSERT INTO admins
+                    (user_id, role, created_at)
+                VALUES
+                    ($1, $2, $3)
+                ON CONFLICT (user_id) DO UPDATE SET
+                    role = EXCLUDED.role,
+                    updated_at = EXCLUDED.created_at
+                "#,
+                &[&user_id, &role.map(|role| role.as_str()), &now],
+            )
+            .await
+            .map_err(|source| AdminServiceError::SetRole { source, user_id })?;
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+pub enum AdminServiceError {
+    GetRole { source: ClientError, user_id: Integer },
+    GetRoles { source: ClientError },
+    GetSignature { source: ClientError, user_id: Integer },
+    SetRole { source: ClientError, user_id: Integer },
+    SetSignature { source: ClientError, user_id: Integer },
+}
+
+impl fmt::Display for AdminServiceError {
+    fn fmt(&self, out: &mut fmt::Formatter) -> fmt::Result {
+        use self::AdminServiceError::*;
+        match self {
+            GetRole { source, user_id } => {
+                write!(out, "failed to get role for admin with id {}: {}", user_id, source)
+            }
+            GetRoles { source } => write!(out, "failed to get admin roles: {}", source),
+            GetSignature { source, user_id } => {
+                write!(out, "failed to get signature for admin with id {}: {}", user_id, source)
+            }
+            SetRole { source, user_id } => {
+                write!(out, "failed to set role for admin with id {}: {}", user_id, source)
+            }
+            SetSignature { source, user_id } => {
+                write!(out, "failed to set signature for admin with id {}: {}", user_id, source)
+            }
+        }
+    }
+}
+
+impl Error for AdminServiceError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        use self::AdminServiceError::*;
+        Some(match self {
+            GetRole { source, .. } => source,
+            GetRoles { source } => source,
+            GetSignature { source, .. } => source,
+            SetRole { source, .. } => source,
+            SetSignature { source, .. } => source,
+        })
+    }
+}
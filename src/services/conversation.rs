@@ -0,0 +1,724 @@
+use crate::db::Client;
+use carapax::types::{Integer, ParseMode, User};
+use chrono::{Duration, NaiveDateTime, Utc};
+use std::{error::Error, fmt, sync::Arc};
+use tokio_postgres::{Error as ClientError, Row};
+
+const AWAITING_COLUMNS: &str = r#"
+    c.id, c.awaiting_admin_chat_id, c.awaiting_admin_message_id, c.awaiting_since,
+    u.id, u.first_name, u.last_name, u.username, u.language_code
+"#;
+
+#[derive(Clone)]
+pub struct ConversationService {
+    client: Arc<Client>,
+}
+
+impl ConversationService {
+    pub fn new(client: Arc<Client>) -> Self {
+        Self { client }
+    }
+
+    pub async fn get_or_create(&self, subscriber_user_id: Integer) -> Result<i32, ConversationServiceError> {
+        self.client
+            .execute(
+                r#"
+                INSERT INTO conversations
+                    (subscriber_user_id, created_at)
+                VALUES
+                    ($1, $2)
+                ON CONFLICT (subscriber_user_id) WHERE closed_at IS NULL DO NOTHING
+                "#,
+                &[&subscriber_user_id, &Utc::now().naive_utc()],
+            )
+            .await
+            .map_err(|source| ConversationServiceError::Create {
+                source,
+                subscriber_user_id,
+            })?;
+        let row = self
+            .client
+            .query_one(
+                "SELECT id FROM conversations WHERE subscriber_user_id = $1 AND closed_at IS NULL",
+                &[&subscriber_user_id],
+            )
+            .await
+            .map_err(|source| ConversationServiceError::Get {
+                source,
+                subscriber_user_id,
+            })?;
+        Ok(row.get(0))
+    }
+
+    pub async fn get_admin_chat_id(&self, conversation_id: i32) -> Result<Option<Integer>, ConversationServiceError> {
+        let row = self
+            .client
+            .query_one(
+                "SELECT admin_chat_id FROM conversations WHERE id = $1",
+                &[&conversation_id],
+            )
+            .await
+            .map_err(|source| ConversationServiceError::GetAdminChat {
+                source,
+                conversation_id,
+            })?;
+        Ok(row.get(0))
+    }
+
+    pub async fn set_admin_chat_id(
+        &self,
+        conversation_id: i32,
+        admin_chat_id: Integer,
+    ) -> Result<(), ConversationServiceError> {
+        self.client
+            .execute(
+                "UPDATE conversations SET admin_chat_id = $1 WHERE id = $2",
+                &[&admin_chat_id, &conversation_id],
+            )
+            .await
+            .map_err(|source| ConversationServiceError::SetAdminChat {
+                source,
+                conversation_id,
+            })?;
+        Ok(())
+    }
+
+    pub async fn get_assignee(&self, conversation_id: i32) -> Result<Option<Integer>, ConversationServiceError> {
+        let row = self
+            .client
+            .query_one(
+                "SELECT assignee_user_id FROM conversations WHERE id = $1",
+                &[&conversation_id],
+            )
+            .await
+            .map_err(|source| ConversationServiceError::GetAssignee {
+                source,
+                conversation_id,
+            })?;
+        Ok(row.get(0))
+    }
+
+    pub async fn set_assignee(
+        &self,
+        conversation_id: i32,
+        assignee_user_id: Option<Integer>,
+    ) -> Result<(), ConversationServiceError> {
+        self.client
+            .execute(
+                "UPDATE conversations SET assignee_user_id = $1 WHERE id = $2",
+                &[&assignee_user_id, &conversation_id],
+            )
+            .await
+            .map_err(|source| ConversationServiceError::SetAssignee {
+                source,
+                conversation_id,
+            })?;
+        Ok(())
+    }
+
+    pub async fn mark_awaiting(
+        &self,
+        conversation_id: i32,
+        admin_chat_id: Integer,
+        admin_message_id: Integer,
+    ) -> Result<(), ConversationServiceError> {
+        self.client
+            .execute(
+                r#"
+                UPDATE conversations SET
+                    awaiting_since = $1, awaiting_admin_chat_id = $2, awaiting_admin_message_id = $3
+                WHERE id = $4 AND awaiting_since IS NULL
+                "#,
+                &[
+                    &Utc::now().naive_utc(),
+                    &admin_chat_id,
+                    &admin_message_id,
+                    &conversation_id,
+                ],
+            )
+            .await
+            .map_err(|source| ConversationServiceError::MarkAwaiting {
+                source,
+                conversation_id,
+            })?;
+        Ok(())
+    }
+
+    pub async fn mark_answered(
+        &self,
+        subscriber_user_id: Integer,
+        admin_user_id: Option<Integer>,
+    ) -> Result<(), ConversationServiceError> {
+        self.client
+            .execute(
+                r#"
+                UPDATE conversations SET
+                    awaiting_since = NULL, awaiting_admin_chat_id = NULL, awaiting_admin_message_id = NULL,
+                    reminded_at = NULL, escalated_at = NULL,
+                    answered_by_user_id = COALESCE($2, answered_by_user_id)
+                WHERE subscriber_user_id = $1 AND closed_at IS NULL
+                "#,
+                &[&subscriber_user_id, &admin_user_id],
+            )
+            .await
+            .map_err(|source| ConversationServiceError::MarkAnswered {
+                source,
+                subscriber_user_id,
+            })?;
+        Ok(())
+    }
+
+    pub async fn close(
+        &self,
+        subscriber_user_id: Integer,
+        closed_by_user_id: Integer,
+    ) -> Result<Option<ClosedConversation>, ConversationServiceError> {
+        let row = self
+            .client
+            .query_opt(
+                r#"
+                UPDATE conversations SET
+                    closed_at = $1, closed_by_user_id = $2,
+                    awaiting_since = NULL, awaiting_admin_chat_id = NULL, awaiting_admin_message_id = NULL,
+                    reminded_at = NULL, escalated_at = NULL
+                WHERE subscriber_user_id = $3 AND closed_at IS NULL
+                RETURNING id, admin_chat_id, assignee_user_id, answered_by_user_id
+                "#,
+                &[&Utc::now().naive_utc(), &closed_by_user_id, &subscriber_user_id],
+            )
+            .await
+            .map_err(|source| ConversationServiceError::Close {
+                source,
+                subscriber_user_id,
+            })?;
+        Ok(row.map(|row| ClosedConversation {
+            id: row.get(0),
+            admin_chat_id: row.get(1),
+            assignee_user_id: row.get(2),
+            answered_by_user_id: row.get(3),
+        }))
+    }
+
+    pub async fn count_closed(
+        &self,
+        since: Option<NaiveDateTime>,
+        source: Option<&str>,
+    ) -> Result<i64, ConversationServiceError> {
+        let row = self
+            .client
+            .query_one(
+                r#"
+                SELECT COUNT(*) FROM conversations c JOIN users u ON u.id = c.subscriber_user_id
+                WHERE c.closed_at IS NOT NULL
+                AND ($1::timestamp IS NULL OR c.closed_at >= $1)
+                AND ($2::varchar IS NULL OR u.source = $2)
+                "#,
+                &[&since, &source],
+            )
+            .await
+            .map_err(|source| ConversationServiceError::CountClosed { source })?;
+        Ok(row.get(0))
+    }
+
+    pub async fn get_awaiting(&self) -> Result<Vec<AwaitingConversation>, ConversationServiceError> {
+        Ok(self
+            .client
+            .query(
+                &format!(
+                    r#"
+                    SELECT {} FROM conversations c JOIN users u ON u.id = c.subscriber_user_id
+                    WHERE c.awaiting_since IS NOT NULL AND c.closed_at IS NULL
+                    ORDER BY c.awaiting_since
+                    "#,
+                    AWAITING_COLUMNS
+                ),
+                &[],
+            )
+            .await
+            .map_err(|source| ConversationServiceError::GetAwaiting { source })?
+            .into_iter()
+            .map(AwaitingConversation::from)
+            .collect())
+    }
+
+    /// Returns the subscriber and the assignee of the open conversation
+    pub async fn get_participants(
+        &self,
+        subscriber_user_id: Integer,
+    ) -> Result<Option<(User, Option<User>)>, ConversationServiceError> {
+        let row = self
+            .client
+            .query_opt(
+                r#"
+                SELECT
+                    u.first_name, u.last_name, u.username, u.language_code,
+                    a.id, a.first_name, a.last_name, a.username, a.language_code
+                FROM users u
+                LEFT JOIN conversations c ON c.subscriber_user_id = u.id AND c.closed_at IS NULL
+                LEFT JOIN users a ON a.id = c.assignee_user_id
+                WHERE u.id = $1
+                "#,
+                &[&subscriber_user_id],
+            )
+            .await
+            .map_err(|source| ConversationServiceError::GetParticipants {
+                source,
+                subscriber_user_id,
+            })?;
+        Ok(row.map(|row| {
+            let subscriber = User {
+                id: subscriber_user_id,
+                is_bot: false,
+                first_name: row.get(0),
+                last_name: row.get(1),
+                username: row.get(2),
+                language_code: row.get(3),
+            };
+            let assignee = row.get::<_, Option<Integer>>(4).map(|id| User {
+                id,
+                is_bot: false,
+                first_name: row.get(5),
+                last_name: row.get(6),
+                username: row.get(7),
+                language_code: row.get(8),
+            });
+            (subscriber, assignee)
+        }))
+    }
+
+    pub async fn get_due_reminders(
+        &self,
+        delay: Duration,
+    ) -> Result<Vec<AwaitingConversation>, ConversationServiceError> {
+        self.get_due("reminded_at", delay).await
+    }
+
+    pub async fn get_due_escalations(
+        &self,
+        delay: Duration,
+    ) -> Result<Vec<AwaitingConversation>, ConversationServiceError> {
+        self.get_due("escalated_at", delay).await
+    }
+
+    async fn get_due(
+        &self,
+        column: &str,
+        delay: Duration,
+    ) -> Result<Vec<AwaitingConversation>, ConversationServiceError> {
+        Ok(self
+            .client
+            .query(
+                &format!(
+                    r#"
+                    SELECT {columns}
+                    FROM conversations c JOIN users u ON u.id = c.subscriber_user_id
+                    WHERE c.awaiting_since <= $1 AND c.{column} IS NULL AND c.closed_at IS NULL
+                    "#,
+                    column = column,
+                    columns = AWAITING_COLUMNS
+                ),
+                &[&(Utc::now().naive_utc() - delay)],
+            )
+            .await
+            .map_err(|source| ConversationServiceError::GetDue { source })?
+            .into_iter()
+            .map(AwaitingConversation::from)
+            .collect())
+    }
+
+    pub async fn mark_reminded(&self, conversation_id: i32) -> Result<(), ConversationServiceError> {
+        self.mark_due("reminded_at", conversation_id).await
+    }
+
+    pub async fn mark_escalated(&self, conversation_id: i32) -> Result<(), ConversationServiceError> {
+        self.mark_due("escalated_at", conversation_id).await
+    }
+
+    async fn mark_due(&self, column: &str, conversation_id: i32) -> Result<(), ConversationServiceError> {
+        self.client
+            .execute(
+                &format!("UPDATE conversations SET {} = $2 WHERE id = $1", column),
+                &[&conversation_id, &Utc::now().naive_utc()],
+            )
+            .await
+            .map_err(|source| ConversationServiceError::MarkDue {
+                source,
+                conversation_id,
+            })?;
+        Ok(())
+    }
+
+    pub async fn add_note(
+        &self,
+        conversation_id: i32,
+        admin_user_id: Option<Integer>,
+        text: &str,
+    ) -> Result<(), ConversationServiceError> {
+        self.client
+            .execute(
+                r#"
+                INSERT INTO notes
+                    (conversation_id, admin_user_id, text, created_at)
+                VALUES
+                    ($1, $2, $3, $4)
+                "#,
+                &[&conversation_id, &admin_user_id, &text, &Utc::now().naive_utc()],
+            )
+            .await
+            .map_err(|source| ConversationServiceError::AddNote {
+                source,
+                conversation_id,
+            })?;
+        Ok(())
+    }
+
+    pub async fn get_notes(&self, subscriber_user_id: Integer) -> Result<Vec<Note>, ConversationServiceError> {
+        Ok(self
+            .client
+            .query(
+                r#"
+                SELECT n.conversation_id, n.admin_user_id, n.text, n.created_at
+                FROM notes n JOIN conversations c ON c.id = n.conversation_id
+                WHERE c.subscriber_user_id = $1
+                ORDER BY n.created_at DESC
+                "#,
+                &[&subscriber_user_id],
+            )
+            .await
+            .map_err(|source| ConversationServiceError::GetNotes {
+                source,
+                subscriber_user_id,
+            })?
+            .into_iter()
+            .map(Note::from)
+            .collect())
+    }
+}
+
+pub struct Note {
+    conversation_id: i32,
+    admin_user_id: Option<Integer>,
+    text: String,
+    created_at: NaiveDateTime,
+}
+
+impl fmt::Display for Note {
+    fn fmt(&self, out: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            out,
+            "#c{} {}",
+            self.conversation_id,
+            self.created_at.format("%Y-%m-%d %H:%M")
+        )?;
+        if let Some(admin_user_id) = self.admin_user_id {
+            write!(out, " #id{}", admin_user_id)?;
+        }
+        write!(out, ": {}", self.text)
+    }
+}
+
+impl From<Row> for Note {
+    fn from(row: Row) -> Self {
+        Self {
+            conversation_id: row.get(0),
+            admin_user_id: row.get(1),
+            text: row.get(2),
+            created_at: row.get(3),
+        }
+    }
+}
+
+pub struct ClosedConversation {
+    id: i32,
+    admin_chat_id: Option<Integer>,
+    assignee_user_id: Option<Integer>,
+    answered_by_user_id: Option<Integer>,
+}
+
+impl ClosedConversation {
+    pub fn id(&self) -> i32 {
+        self.id
+    }
+
+    pub fn admin_chat_id(&self) -> Option<Integer> {
+        self.admin_chat_id
+    }
+
+    pub fn assignee_user_id(&self) -> Option<Integer> {
+        self.assignee_user_id
+    }
+
+    /// Returns the ID of the admin who replied last
+    pub fn answered_by_user_id(&self) -> Option<Integer> {
+        self.answered_by_user_id
+    }
+}
+
+pub struct AwaitingConversation {
+    id: i32,
+    admin_chat_id: Integer,
+    admin_message_id: Integer,
+    since: NaiveDateTime,
+    subscriber: User,
+}
+
+impl AwaitingConversation {
+    pub fn id(&self) -> i32 {
+        self.id
+    }
+
+    pub fn admin_chat_id(&self) -> Integer {
+        self.admin_chat_id
+    }
+
+    pub fn admin_message_id(&self) -> Integer {
+        self.admin_message_id
+    }
+
+    pub fn waiting_time(&self) -> String {
+        let minutes = (Utc::now().naive_utc() - self.since).num_minutes();
+        match (minutes / 60, minutes % 60) {
+            (0, minutes) => format!("{}m", minutes),
+            (hours, minutes) => format!("{}h {}m", hours, minutes),
+        }
+    }
+}
+
+impl fmt::Display for AwaitingConversation {
+    fn fmt(&self, out: &mut fmt::Formatter) -> fmt::Result {
+        let mention = self
+            .subscriber
+            .get_mention(ParseMode::Html)
+            .unwrap_or_else(|_| self.subscriber.get_full_name());
+        write!(
+            out,
+            "{} — waiting for {} (since {} UTC)",
+            mention,
+            self.waiting_time(),
+            self.since.format("%d/%m/%y %H:%M")
+        )
+    }
+}
+
+impl From<Row> for AwaitingConversation {
+    fn from(row: Row) -> Self {
+        AwaitingConversation {
+            id: row.get(0),
+            admin_chat_id: row.get(1),
+            admin_message_id: row.get(2),
+            since: row.get(3),
+            subscriber: User {
+                id: row.get(4),
+                is_bot: false,
+                first_name: row.get(5),
+                last_name: row.get(6),
+                username: row.get(7),
+                language_code: row.get(8),
+            },
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ConversationServiceError {
+    AddNote {
+        source: ClientError,
+        conversation_id: i32,
+    },
+    Close {
+        source: ClientError,
+        subscriber_user_id: Integer,
+    },
+    CountClosed {
+        source: ClientError,
+    },
+    Create {
+        source: ClientError,
+        subscriber_user_id: Integer,
+    },
+    Get {
+        source: ClientError,
+        subscriber_user_id: Integer,
+    },
+    GetAdminChat {
+        source: ClientError,
+        conversation_id: i32,
+    },
+    GetAssignee {
+        source: ClientError,
+        conversation_id: i32,
+    },
+    GetAwaiting {
+        source: ClientError,
+    },
+    GetDue {
+        source: ClientError,
+    },
+    GetNotes {
+        source: ClientError,
+        subscriber_user_id: Integer,
+    },
+    GetParticipants {
+        source: ClientError,
+        subscriber_user_id: Integer,
+    },
+    MarkAnswered {
+        source: ClientError,
+        subscriber_user_id: Integer,
+    },
+    MarkAwaiting {
+        source: ClientError,
+        conversation_id: i32,
+    },
+    MarkDue {
+        source: ClientError,
+        conversation_id: i32,
+    },
+    SetAdminChat {
+        source: ClientError,
+        conversation_id: i32,
+    },
+    SetAssignee {
+        source: ClientError,
+        conversation_id: i32,
+    },
+}
+
+impl fmt::Display for ConversationServiceError {
+    fn fmt(&self, out: &mut fmt::Formatter) -> fmt::Result {
+        use self::ConversationServiceError::*;
+        match self {
+            AddNote {
+                source,
+                conversation_id,
+            } => write!(
+                out,
+                "failed to add note to conversation with id {}: {}",
+                conversation_id, source
+            ),
+            Close {
+                source,
+                subscriber_user_id,
+            } => write!(
+                out,
+                "failed to close conversation for user with id {}: {}",
+                subscriber_user_id, source
+            ),
+            CountClosed { source } => write!(out, "failed to count closed conversations: {}", source),
+            Create {
+                source,
+                subscriber_user_id,
+            } => write!(
+                out,
+                "failed to create conversation for user with id {}: {}",
+                subscriber_user_id, source
+            ),
+            Get {
+                source,
+                subscriber_user_id,
+            } => write!(
+                out,
+                "failed to get conversation for user with id {}: {}",
+                subscriber_user_id, source
+            ),
+            GetAdminChat {
+                source,
+                conversation_id,
+            } => write!(
+                out,
+                "failed to get admin chat for conversation with id {}: {}",
+                conversation_id, source
+            ),
+            GetAssignee {
+                source,
+                conversation_id,
+            } => write!(
+                out,
+                "failed to get assignee for conversation with id {}: {}",
+                conversation_id, source
+            ),
+            GetAwaiting { source } => write!(out, "failed to get awaiting conversations: {}", source),
+            GetDue { source } => write!(out, "failed to get due awaiting conversations: {}", source),
+            GetNotes {
+                source,
+                subscriber_user_id,
+            } => write!(
+                out,
+                "failed to get notes for user with id {}: {}",
+                subscriber_user_id, source
+            ),
+            GetParticipants {
+                source,
+                subscriber_user_id,
+            } => write!(
+                out,
+                "failed to get participants of conversation for user with id {}: {}",
+                subscriber_user_id, source
+            ),
+            MarkAnswered {
+                source,
+                subscriber_user_id,
+            } => write!(
+                out,
+                "failed to mark conversation for user with id {} as answered: {}",
+                subscriber_user_id, source
+            ),
+            MarkAwaiting {
+                source,
+                conversation_id,
+            } => write!(
+                out,
+                "failed to mark conversation with id {} as awaiting: {}",
+                conversation_id, source
+            ),
+            MarkDue {
+                source,
+                conversation_id,
+            } => write!(
+                out,
+                "failed to mark reminder as sent for conversation with id {}: {}",
+                conversation_id, source
+            ),
+            SetAdminChat {
+                source,
+                conversation_id,
+            } => write!(
+                out,
+                "failed to set admin chat for conversation with id {}: {}",
+                conversation_id, source
+            ),
+            SetAssignee {
+                source,
+                conversation_id,
+            } => write!(
+                out,
+                "failed to set assignee for conversation with id {}: {}",
+                conversation_id, source
+            ),
+        }
+    }
+}
+
+impl Error for ConversationServiceError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        use self::ConversationServiceError::*;
+        Some(match self {
+            AddNote { source, .. } => source,
+            Close { source, .. } => source,
+            CountClosed { source } => source,
+            Create { source, .. } => source,
+            Get { source, .. } => source,
+            GetAdminChat { source, .. } => source,
+            GetAssignee { source, .. } => source,
+            GetAwaiting { source } => source,
+            GetDue { source } => source,
+            GetNotes { source, .. } => source,
+            GetParticipants { source, .. } => source,
+            MarkAnswered { source, .. } => source,
+            MarkAwaiting { source, .. } => source,
+            MarkDue { source, .. } => source,
+            SetAdminChat { source, .. } => source,
+            SetAssignee { source, .. } => source,
+        })
+    }
+}
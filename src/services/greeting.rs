@@ -0,0 +1,162 @@
+use crate::db::Client;
+use carapax::types::{InlineKeyboardMarkup, Integer};
+use chrono::Utc;
+use std::{collections::HashMap, error::Error, fmt, sync::Arc};
+use tokio_postgres::{Error as ClientError, Row};
+
+pub const DEFAULT_GREETING: &str = "default";
+
+#[derive(Clone)]
+pub struct GreetingService {
+    client: Arc<Client>,
+}
+
+impl GreetingService {
+    pub fn new(client: Arc<Client>) -> Self {
+        Self { client }
+    }
+
+    pub async fn save(&self, greeting: Greeting) -> Result<(), GreetingServiceError> {
+        let reply_markup = greeting
+            .reply_markup()
+            .map(serde_json::to_string)
+            .transpose()
+            .map_err(GreetingServiceError::SerializeMarkup)?;
+        self.client
+            .execute(
+                r#"
+                INSERT INTO greetings
+                    (name, chat_id, message_id, reply_markup, created_at)
+                VALUES
+                    ($1, $2, $3, $4, $5)
+                ON CONFLICT (name) DO UPDATE SET
+                    chat_id = EXCLUDED.chat_id,
+                    message_id = EXCLUDED.message_id,
+                    reply_markup = EXCLUDED.reply_markup,
+                    created_at = EXCLUDED.created_at
+                "#,
+                &[
+                    &greeting.name(),
+                    &greeting.chat_id(),
+                    &greeting.message_id(),
+                    &reply_markup,
+                    &Utc::now().naive_utc(),
+                ],
+            )
+            .await
+            .map_err(|source| GreetingServiceError::Save { source, greeting })?;
+        Ok(())
+    }
+
+    pub async fn find(&self, name: &str) -> Result<Option<Greeting>, GreetingServiceError> {
+        let row = self
+            .client
+            .query_opt("SELECT * FROM greetings WHERE name = $1", &[&name])
+            .await
+            .map_err(|source| GreetingServiceError::Find {
+                source,
+                name: name.to_string(),
+            })?;
+        Ok(row.map(Greeting::from))
+    }
+
+    pub async fn delete(&self, name: &str) -> Result<bool, GreetingServiceError> {
+        let affected_rows = self
+            .client
+            .execute("DELETE FROM greetings WHERE name = $1", &[&name])
+            .await
+            .map_err(|source| GreetingServiceError::Delete {
+                source,
+                name: name.to_string(),
+            })?;
+        Ok(affected_rows != 0)
+    }
+}
+
+#[derive(Debug)]
+pub struct Greeting {
+    name: String,
+    chat_id: Integer,
+    message_id: Integer,
+    reply_markup: Option<InlineKeyboardMarkup>,
+}
+
+impl Greeting {
+    pub fn new<N>(name: N, chat_id: Integer, message_id: Integer, reply_markup: Option<InlineKeyboardMarkup>) -> Self
+    where
+        N: Into<String>,
+    {
+        Self {
+            name: name.into(),
+            chat_id,
+            message_id,
+            reply_markup,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn chat_id(&self) -> Integer {
+        self.chat_id
+    }
+
+    pub fn message_id(&self) -> Integer {
+        self.message_id
+    }
+
+    pub fn reply_markup(&self) -> Option<&InlineKeyboardMarkup> {
+        self.reply_markup.as_ref()
+    }
+}
+
+impl From<Row> for Greeting {
+    fn from(row: Row) -> Self {
+        let indexes: HashMap<&str, usize> = row
+            .columns()
+            .iter()
+            .enumerate()
+            .map(|(idx, column)| (column.name(), idx))
+            .collect();
+        let reply_markup: Option<String> = row.get(indexes["reply_markup"]);
+        Greeting::new::<String>(
+            row.get(indexes["name"]),
+            row.get(indexes["chat_id"]),
+            row.get(indexes["message_id"]),
+            reply_markup.and_then(|data| serde_json::from_str(&data).ok()),
+        )
+    }
+}
+
+#[derive(Debug)]
+pub enum GreetingServiceError {
+    Delete { source: ClientError, name: String },
+    Find { source: ClientError, name: String },
+    Save { source: ClientError, greeting: Greeting },
+    SerializeMarkup(serde_json::Error),
+}
+
+impl fmt::Display for GreetingServiceError {
+    fn fmt(&self, out: &mut fmt::Formatter) -> fmt::Result {
+        use self::GreetingServiceError::*;
+        match self {
+            Delete { source, name } => write!(out, "could not delete greeting '{}': {}", name, source),
+            Find { source, name } => write!(out, "could not find greeting '{}': {}", name, source),
+            Save { source, greeting } => write!(out, "could not save greeting: {} ({:?})", source, greeting),
+            SerializeMarkup(err) => write!(out, "could not serialize greeting markup: {}", err),
+        }
+    }
+}
+
+impl Error for GreetingServiceError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        use self::GreetingServiceError::*;
+        Some(match self {
+            Delete { source, .. } => source,
+            Find { source, .. } => source,
+            Save { source, .. } => source,
+            SerializeMarkup(err) => err,
+        })
+    }
+}
@@ -0,0 +1,35 @@
+use carapax::types::Message;
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+#[derive(Clone, Default)]
+pub struct MediaGroupService {
+    groups: Arc<Mutex<HashMap<String, Vec<Message>>>>,
+}
+
+impl MediaGroupService {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add(&self, media_group_id: &str, message: Message) -> bool {
+        // returns true for the first part, the caller is responsible for sending the whole group
+        let mut groups = self.groups.lock().expect("media groups are poisoned");
+        let is_new = !groups.contains_key(media_group_id);
+        groups.entry(media_group_id.to_string()).or_default().push(message);
+        is_new
+    }
+
+    pub fn take(&self, media_group_id: &str) -> Vec<Message> {
+        let mut parts = self
+            .groups
+            .lock()
+            .expect("media groups are poisoned")
+            .remove(media_group_id)
+            .unwrap_or_default();
+        parts.sort_by_key(|message| message.id);
+        parts
+    }
+}
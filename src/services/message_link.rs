@@ -1,6 +1,7 @@
+use crate::db::Client;
 use carapax::types::Integer;
 use std::{collections::HashMap, error::Error, fmt, sync::Arc};
-use tokio_postgres::{Client, Error as ClientError, Row};
+use tokio_postgres::{Error as ClientError, Row};
 
 #[derive(Clone)]
 pub struct MessageLinkService {
@@ -45,10 +46,18 @@ impl MessageLinkService {
             .query_opt(
                 match direction {
                     MessageLinkDirection::Admin => {
-                        "SELECT * FROM message_links WHERE admin_chat_id = $1 AND admin_message_id = $2"
+                        r#"
+                        SELECT * FROM message_links
+                        WHERE admin_chat_id = $1 AND admin_message_id = $2
+                        ORDER BY id DESC LIMIT 1
+                        "#
                     }
                     MessageLinkDirection::Subscriber => {
-                        "SELECT * FROM message_links WHERE subscriber_chat_id = $1 AND subscriber_message_id = $2"
+                        r#"
+                        SELECT * FROM message_links
+                        WHERE subscriber_chat_id = $1 AND subscriber_message_id = $2
+                        ORDER BY id DESC LIMIT 1
+                        "#
                     }
                 },
                 &[&chat_id, &message_id],
@@ -62,6 +71,53 @@ impl MessageLinkService {
             })?;
         Ok(row.map(MessageLink::from))
     }
+
+    pub async fn set_delivered(
+        &self,
+        admin_chat_id: Integer,
+        admin_message_id: Integer,
+        status_message_id: Integer,
+    ) -> Result<(), MessageLinkServiceError> {
+        self.client
+            .execute(
+                r#"
+                UPDATE message_links SET status = 'delivered', status_message_id = $3
+                WHERE admin_chat_id = $1 AND admin_message_id = $2
+                "#,
+                &[&admin_chat_id, &admin_message_id, &status_message_id],
+            )
+            .await
+            .map_err(|source| MessageLinkServiceError::SetDelivered {
+                source,
+                admin_chat_id,
+                admin_message_id,
+            })?;
+        Ok(())
+    }
+
+    pub async fn mark_seen(
+        &self,
+        subscriber_user_id: Integer,
+    ) -> Result<Vec<(Integer, Integer)>, MessageLinkServiceError> {
+        Ok(self
+            .client
+            .query(
+                r#"
+                UPDATE message_links SET status = 'seen'
+                WHERE subscriber_user_id = $1 AND status = 'delivered'
+                RETURNING admin_chat_id, status_message_id
+                "#,
+                &[&subscriber_user_id],
+            )
+            .await
+            .map_err(|source| MessageLinkServiceError::MarkSeen {
+                source,
+                subscriber_user_id,
+            })?
+            .into_iter()
+            .map(|row| (row.get(0), row.get(1)))
+            .collect())
+    }
 }
 
 #[derive(Debug)]
@@ -157,6 +213,15 @@ pub enum MessageLinkServiceError {
         message_id: Integer,
         direction: MessageLinkDirection,
     },
+    MarkSeen {
+        source: ClientError,
+        subscriber_user_id: Integer,
+    },
+    SetDelivered {
+        source: ClientError,
+        admin_chat_id: Integer,
+        admin_message_id: Integer,
+    },
 }
 
 impl fmt::Display for MessageLinkServiceError {
@@ -174,6 +239,23 @@ impl fmt::Display for MessageLinkServiceError {
                 "could not find message link for {}: {} (chat_id={}, message_id={})",
                 direction, source, chat_id, message_id
             ),
+            MarkSeen {
+                source,
+                subscriber_user_id,
+            } => write!(
+                out,
+                "could not mark message links of user with id {} as seen: {}",
+                subscriber_user_id, source
+            ),
+            SetDelivered {
+                source,
+                admin_chat_id,
+                admin_message_id,
+            } => write!(
+                out,
+                "could not mark message link as delivered: {} (chat_id={}, message_id={})",
+                source, admin_chat_id, admin_message_id
+            ),
         }
     }
 }
@@ -184,6 +266,8 @@ impl Error for MessageLinkServiceError {
         Some(match self {
             Create { source, .. } => source,
             Find { source, .. } => source,
+            MarkSeen { source, .. } => source,
+            SetDelivered { source, .. } => source,
         })
     }
 }
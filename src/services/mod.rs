@@ -1,7 +1,29 @@
+mod admin;
+mod conversation;
+mod greeting;
+mod media_group;
 mod message_link;
+mod pending_reply;
+mod rate_limit;
+mod rating;
+mod schedule;
+mod settings;
+mod snippet;
 mod user;
+mod verification;
 
 pub use self::{
+    admin::{AdminService, AdminServiceError},
+    conversation::{AwaitingConversation, ConversationService, ConversationServiceError},
+    greeting::{Greeting, GreetingService, GreetingServiceError, DEFAULT_GREETING},
+    media_group::MediaGroupService,
     message_link::{MessageLink, MessageLinkDirection, MessageLinkService, MessageLinkServiceError},
-    user::{UserBlockFilter, UserBlockFilterError, UserInfoList, UserService, UserServiceError},
+    pending_reply::{PendingReply, PendingReplyService, PendingReplyServiceError},
+    rate_limit::{RateLimitService, RateLimitServiceError, RateLimitStatus},
+    rating::{Rating, RatingService, RatingServiceError},
+    schedule::{BroadcastProgress, ScheduleService, ScheduleServiceError, ScheduledMessage},
+    settings::{SettingsService, SettingsServiceError, SETTING_AUTO_ANSWERS_ENABLED},
+    snippet::{Snippet, SnippetService, SnippetServiceError},
+    user::{is_valid_source, UserBlockFilter, UserBlockFilterError, UserInfoList, UserService, UserServiceError},
+    verification::{HeldMessage, VerificationService, VerificationServiceError},
 };
@@ -0,0 +1,318 @@
+use crate::db::Client;
+use carapax::types::{Integer, Message};
+use chrono::{NaiveDateTime, Utc};
+use std::{
+    collections::HashMap,
+    error::Error,
+    fmt,
+    sync::{Arc, Mutex},
+};
+use tokio_postgres::{Error as ClientError, Row};
+
+#[derive(Clone)]
+pub struct PendingReplyService {
+    client: Arc<Client>,
+    // messages can not be stored in the database, so only ids of media group parts survive a restart
+    media_groups: Arc<Mutex<HashMap<i32, Vec<Message>>>>,
+}
+
+impl PendingReplyService {
+    pub fn new(client: Arc<Client>) -> Self {
+        Self {
+            client,
+            media_groups: Default::default(),
+        }
+    }
+
+    pub async fn create(&self, mut reply: PendingReply) -> Result<i32, PendingReplyServiceError> {
+        let parts = reply.parts.take();
+        let row = self
+            .client
+            .query_one(
+                r#"
+                INSERT INTO pending_replies
+                    (chat_id, message_id, admin_user_id, reply_to_message_id, source_chat_id, source_message_id,
+                    part_message_ids, expires_at, created_at)
+                VALUES
+                    ($1, $2, $3, $4, $5, $6, $7, $8, $9)
+                RETURNING id
+                "#,
+                &[
+                    &reply.chat_id(),
+                    &reply.message_id(),
+                    &reply.admin_user_id(),
+                    &reply.reply_to_message_id(),
+                    &reply.source_chat_id,
+                    &reply.source_message_id,
+                    &reply.part_message_ids,
+                    &reply.expires_at(),
+                    &Utc::now().naive_utc(),
+                ],
+            )
+            .await
+            .map_err(|source| PendingReplyServiceError::Create { source, reply })?;
+        let id = row.get(0);
+        if let Some(parts) = parts {
+            self.media_groups
+                .lock()
+                .expect("pending media groups are poisoned")
+                .insert(id, parts);
+        }
+        Ok(id)
+    }
+
+    /// Puts back a reply which could not be sent, so it can be confirmed again
+    pub async fn restore(&self, mut reply: PendingReply) -> Result<(), PendingReplyServiceError> {
+        let parts = reply.parts.take();
+        self.client
+            .execute(
+                r#"
+                INSERT INTO pending_replies
+                    (id, chat_id, message_id, admin_user_id, reply_to_message_id, preview_message_id, source_chat_id,
+                    source_message_id, part_message_ids, expires_at, created_at)
+                VALUES
+                    ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11)
+                "#,
+                &[
+                    &reply.id,
+                    &reply.chat_id(),
+                    &reply.message_id(),
+                    &reply.admin_user_id(),
+                    &reply.reply_to_message_id(),
+                    &reply.preview_message_id(),
+                    &reply.source_chat_id,
+                    &reply.source_message_id,
+                    &reply.part_message_ids,
+                    &reply.expires_at(),
+                    &Utc::now().naive_utc(),
+                ],
+            )
+            .await
+            .map_err(|source| PendingReplyServiceError::Restore { source, id: reply.id })?;
+        if let (Some(id), Some(parts)) = (reply.id, parts) {
+            self.media_groups
+                .lock()
+                .expect("pending media groups are poisoned")
+                .insert(id, parts);
+        }
+        Ok(())
+    }
+
+    pub async fn set_preview(&self, id: i32, preview_message_id: Integer) -> Result<(), PendingReplyServiceError> {
+        self.client
+            .execute(
+                "UPDATE pending_replies SET preview_message_id = $1 WHERE id = $2",
+                &[&preview_message_id, &id],
+            )
+            .await
+            .map_err(|source| PendingReplyServiceError::SetPreview { source, id })?;
+        Ok(())
+    }
+
+    pub async fn take(&self, id: i32) -> Result<Option<PendingReply>, PendingReplyServiceError> {
+        Ok(self
+            .client
+            .query_opt("DELETE FROM pending_replies WHERE id = $1 RETURNING *", &[&id])
+            .await
+            .map_err(|source| PendingReplyServiceError::Take { source, id })?
+            .map(|row| self.attach_parts(PendingReply::from(row))))
+    }
+
+    pub async fn take_expired(&self) -> Result<Vec<PendingReply>, PendingReplyServiceError> {
+        Ok(self
+            .client
+            .query(
+                "DELETE FROM pending_replies WHERE expires_at <= $1 RETURNING *",
+                &[&Utc::now().naive_utc()],
+            )
+            .await
+            .map_err(|source| PendingReplyServiceError::TakeExpired { source })?
+            .into_iter()
+            .map(|row| self.attach_parts(PendingReply::from(row)))
+            .collect())
+    }
+
+    fn attach_parts(&self, mut reply: PendingReply) -> PendingReply {
+        if let Some(id) = reply.id {
+            reply.parts = self
+                .media_groups
+                .lock()
+                .expect("pending media groups are poisoned")
+                .remove(&id);
+        }
+        reply
+    }
+}
+
+#[derive(Debug)]
+pub struct PendingReply {
+    id: Option<i32>,
+    chat_id: Integer,
+    message_id: Integer,
+    admin_user_id: Option<Integer>,
+    reply_to_message_id: Integer,
+    preview_message_id: Option<Integer>,
+    source_chat_id: Option<Integer>,
+    source_message_id: Option<Integer>,
+    part_message_ids: Option<Vec<Integer>>,
+    parts: Option<Vec<Message>>,
+    expires_at: NaiveDateTime,
+}
+
+impl PendingReply {
+    pub fn new(
+        chat_id: Integer,
+        message_id: Integer,
+        admin_user_id: Integer,
+        reply_to_message_id: Integer,
+        expires_at: NaiveDateTime,
+    ) -> Self {
+        Self {
+            id: None,
+            chat_id,
+            message_id,
+            admin_user_id: Some(admin_user_id),
+            reply_to_message_id,
+            preview_message_id: None,
+            source_chat_id: None,
+            source_message_id: None,
+            part_message_ids: None,
+            parts: None,
+            expires_at,
+        }
+    }
+
+    /// A reply with a snippet, message_id is the ID of the message which called the snippet
+    pub fn new_snippet(
+        chat_id: Integer,
+        message_id: Integer,
+        admin_user_id: Integer,
+        reply_to_message_id: Integer,
+        source_chat_id: Integer,
+        source_message_id: Integer,
+        expires_at: NaiveDateTime,
+    ) -> Self {
+        Self {
+            source_chat_id: Some(source_chat_id),
+            source_message_id: Some(source_message_id),
+            ..Self::new(chat_id, message_id, admin_user_id, reply_to_message_id, expires_at)
+        }
+    }
+
+    /// A reply with a media group, message_id is the ID of the first part
+    pub fn new_media_group(
+        chat_id: Integer,
+        parts: Vec<Message>,
+        admin_user_id: Integer,
+        reply_to_message_id: Integer,
+        expires_at: NaiveDateTime,
+    ) -> Self {
+        let message_id = parts.first().map(|part| part.id).unwrap_or_default();
+        Self {
+            part_message_ids: Some(parts.iter().map(|part| part.id).collect()),
+            parts: Some(parts),
+            ..Self::new(chat_id, message_id, admin_user_id, reply_to_message_id, expires_at)
+        }
+    }
+
+    pub fn chat_id(&self) -> Integer {
+        self.chat_id
+    }
+
+    pub fn message_id(&self) -> Integer {
+        self.message_id
+    }
+
+    /// Admin who wrote the reply, unknown for replies created before it was stored
+    pub fn admin_user_id(&self) -> Option<Integer> {
+        self.admin_user_id
+    }
+
+    pub fn reply_to_message_id(&self) -> Integer {
+        self.reply_to_message_id
+    }
+
+    pub fn preview_message_id(&self) -> Option<Integer> {
+        self.preview_message_id
+    }
+
+    pub fn source(&self) -> Option<(Integer, Integer)> {
+        self.source_chat_id.zip(self.source_message_id)
+    }
+
+    pub fn part_message_ids(&self) -> Option<&[Integer]> {
+        self.part_message_ids.as_deref()
+    }
+
+    /// Parts of a media group, they are available until a restart
+    pub fn parts(&self) -> Option<&[Message]> {
+        self.parts.as_deref()
+    }
+
+    pub fn expires_at(&self) -> NaiveDateTime {
+        self.expires_at
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.expires_at <= Utc::now().naive_utc()
+    }
+}
+
+impl From<Row> for PendingReply {
+    fn from(row: Row) -> Self {
+        let indexes: HashMap<&str, usize> = row
+            .columns()
+            .iter()
+            .enumerate()
+            .map(|(idx, column)| (column.name(), idx))
+            .collect();
+        PendingReply {
+            id: Some(row.get(indexes["id"])),
+            chat_id: row.get(indexes["chat_id"]),
+            message_id: row.get(indexes["message_id"]),
+            admin_user_id: row.get(indexes["admin_user_id"]),
+            reply_to_message_id: row.get(indexes["reply_to_message_id"]),
+            preview_message_id: row.get(indexes["preview_message_id"]),
+            source_chat_id: row.get(indexes["source_chat_id"]),
+            source_message_id: row.get(indexes["source_message_id"]),
+            part_message_ids: row.get(indexes["part_message_ids"]),
+            parts: None,
+            expires_at: row.get(indexes["expires_at"]),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum PendingReplyServiceError {
+    Create { source: ClientError, reply: PendingReply },
+    Restore { source: ClientError, id: Option<i32> },
+    SetPreview { source: ClientError, id: i32 },
+    Take { source: ClientError, id: i32 },
+    TakeExpired { source: ClientError },
+}
+
+impl fmt::Display for PendingReplyServiceError {
+    fn fmt(&self, out: &mut fmt::Formatter) -> fmt::Result {
+        use self::PendingReplyServiceError::*;
+        match self {
+            Create { source, reply } => write!(out, "could not create pending reply: {} ({:?})", source, reply),
+            Restore { source, id } => write!(out, "could not restore pending reply {:?}: {}", id, source),
+            SetPreview { source, id } => write!(out, "could not set preview for pending reply {}: {}", id, source),
+            Take { source, id } => write!(out, "could not take pending reply {}: {}", id, source),
+            TakeExpired { source } => write!(out, "could not take expired pending replies: {}", source),
+        }
+    }
+}
+
+impl Error for PendingReplyServiceError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        use self::PendingReplyServiceError::*;
+        Some(match self {
+            Create { source, .. } => source,
+            Restore { source, .. } => source,
+            SetPreview { source, .. } => source,
+            Take { source, .. } => source,
+            TakeExpired { source } => source,
+        })
+    }
+}
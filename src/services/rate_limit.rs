@@ -0,0 +1,237 @@
+use crate::db::Client;
+use carapax::types::Integer;
+use chrono::{Duration, NaiveDateTime, Utc};
+use std::{
+    collections::HashMap,
+    error::Error,
+    fmt,
+    sync::{Arc, Mutex},
+};
+use tokio_postgres::Error as ClientError;
+
+// violations are kept after a window ends, so that repeated flooding leads to a block
+const VIOLATIONS_TTL_HOURS: i64 = 24;
+
+#[derive(Clone)]
+pub struct RateLimitService {
+    backend: RateLimitBackend,
+}
+
+#[derive(Clone)]
+enum RateLimitBackend {
+    Memory(Arc<Mutex<MemoryCounters>>),
+    Postgres(Arc<Client>),
+}
+
+impl RateLimitService {
+    pub fn in_memory() -> Self {
+        Self {
+            backend: RateLimitBackend::Memory(Default::default()),
+        }
+    }
+
+    pub fn persistent(client: Arc<Client>) -> Self {
+        Self {
+            backend: RateLimitBackend::Postgres(client),
+        }
+    }
+
+    pub async fn hit(
+        &self,
+        user_id: Integer,
+        messages: i32,
+        window: Duration,
+    ) -> Result<RateLimitStatus, RateLimitServiceError> {
+        let now = Utc::now().naive_utc();
+        let counter = match self.backend {
+            RateLimitBackend::Memory(ref counters) => {
+                let mut counters = counters.lock().expect("rate limit counters are poisoned");
+                counters.evict_stale(now, window);
+                let counter = counters
+                    .items
+                    .entry(user_id)
+                    .or_insert_with(|| RateLimitCounter::new(now));
+                counter.hit(now, window);
+                *counter
+            }
+            RateLimitBackend::Postgres(ref client) => {
+                let row = client
+                    .query_one(
+                        r#"
+                        INSERT INTO rate_limits
+                            (user_id, window_start, messages, violations)
+                        VALUES
+                            ($1, $2, 1, 0)
+                        ON CONFLICT (user_id) DO UPDATE SET
+                            window_start = CASE
+                                WHEN rate_limits.window_start <= $3 THEN $2
+                                ELSE rate_limits.window_start
+                            END,
+                            messages = CASE
+                                WHEN rate_limits.window_start <= $3 THEN 1
+                                ELSE rate_limits.messages + 1
+                            END
+                        RETURNING window_start, messages, violations
+                        "#,
+                        &[&user_id, &now, &(now - window)],
+                    )
+                    .await
+                    .map_err(|source| RateLimitServiceError::Hit { source, user_id })?;
+                RateLimitCounter {
+                    window_start: row.get(0),
+                    messages: row.get(1),
+                    violations: row.get(2),
+                }
+            }
+        };
+        Ok(match counter.messages {
+            x if x <= messages => RateLimitStatus::Allowed,
+            x if x == messages + 1 => RateLimitStatus::Violation(self.add_violation(user_id).await?),
+            _ => RateLimitStatus::Exceeded,
+        })
+    }
+
+    pub async fn reset_violations(&self, user_id: Integer) -> Result<(), RateLimitServiceError> {
+        match self.backend {
+            RateLimitBackend::Memory(ref counters) => {
+                if let Some(counter) = counters
+                    .lock()
+                    .expect("rate limit counters are poisoned")
+                    .items
+                    .get_mut(&user_id)
+                {
+                    counter.violations = 0;
+                }
+            }
+            RateLimitBackend::Postgres(ref client) => {
+                client
+                    .execute("UPDATE rate_limits SET violations = 0 WHERE user_id = $1", &[&user_id])
+                    .await
+                    .map_err(|source| RateLimitServiceError::ResetViolations { source, user_id })?;
+            }
+        }
+        Ok(())
+    }
+
+    async fn add_violation(&self, user_id: Integer) -> Result<i32, RateLimitServiceError> {
+        Ok(match self.backend {
+            RateLimitBackend::Memory(ref counters) => {
+                let mut counters = counters.lock().expect("rate limit counters are poisoned");
+                match counters.items.get_mut(&user_id) {
+                    Some(counter) => {
+                        counter.violations += 1;
+                        counter.violations
+                    }
+                    None => 0,
+                }
+            }
+            RateLimitBackend::Postgres(ref client) => client
+                .query_one(
+                    "UPDATE rate_limits SET violations = violations + 1 WHERE user_id = $1 RETURNING violations",
+                    &[&user_id],
+                )
+                .await
+                .map_err(|source| RateLimitServiceError::AddViolation { source, user_id })?
+                .get(0),
+        })
+    }
+}
+
+#[derive(Default)]
+struct MemoryCounters {
+    items: HashMap<Integer, RateLimitCounter>,
+    evicted_at: Option<NaiveDateTime>,
+}
+
+impl MemoryCounters {
+    fn evict_stale(&mut self, now: NaiveDateTime, window: Duration) {
+        if self
+            .evicted_at
+            .map(|evicted_at| evicted_at > now - window)
+            .unwrap_or(false)
+        {
+            return;
+        }
+        self.items.retain(|_, counter| !counter.is_stale(now, window));
+        self.evicted_at = Some(now);
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+struct RateLimitCounter {
+    window_start: NaiveDateTime,
+    messages: i32,
+    violations: i32,
+}
+
+impl RateLimitCounter {
+    fn new(now: NaiveDateTime) -> Self {
+        Self {
+            window_start: now,
+            messages: 0,
+            violations: 0,
+        }
+    }
+
+    fn is_stale(&self, now: NaiveDateTime, window: Duration) -> bool {
+        let window_end = self.window_start + window;
+        window_end <= now && (self.violations == 0 || window_end <= now - Duration::hours(VIOLATIONS_TTL_HOURS))
+    }
+
+    fn hit(&mut self, now: NaiveDateTime, window: Duration) {
+        if self.window_start <= now - window {
+            self.window_start = now;
+            self.messages = 1;
+        } else {
+            self.messages += 1;
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum RateLimitStatus {
+    Allowed,
+    Exceeded,
+    Violation(i32),
+}
+
+#[derive(Debug)]
+pub enum RateLimitServiceError {
+    AddViolation { source: ClientError, user_id: Integer },
+    Hit { source: ClientError, user_id: Integer },
+    ResetViolations { source: ClientError, user_id: Integer },
+}
+
+impl fmt::Display for RateLimitServiceError {
+    fn fmt(&self, out: &mut fmt::Formatter) -> fmt::Result {
+        use self::RateLimitServiceError::*;
+        match self {
+            AddViolation { source, user_id } => write!(
+                out,
+                "failed to add rate limit violation for user with id {}: {}",
+                user_id, source
+            ),
+            Hit { source, user_id } => write!(
+                out,
+                "failed to update rate limit counter for user with id {}: {}",
+                user_id, source
+            ),
+            ResetViolations { source, user_id } => write!(
+                out,
+                "failed to reset rate limit violations for user with id {}: {}",
+                user_id, source
+            ),
+        }
+    }
+}
+
+impl Error for RateLimitServiceError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        use self::RateLimitServiceError::*;
+        Some(match self {
+            AddViolation { source, .. } => source,
+            Hit { source, .. } => source,
+            ResetViolations { source, .. } => source,
+        })
+    }
+}
@@ -0,0 +1,299 @@
+use crate::db::Client;
+use carapax::types::Integer;
+use chrono::{NaiveDateTime, Utc};
+use std::{error::Error, fmt, sync::Arc};
+use tokio_postgres::{Error as ClientError, Row};
+
+const COLUMNS: &str = "conversation_id, subscriber_user_id, admin_user_id, admin_chat_id, score";
+
+#[derive(Clone)]
+pub struct RatingService {
+    client: Arc<Client>,
+}
+
+impl RatingService {
+    pub fn new(client: Arc<Client>) -> Self {
+        Self { client }
+    }
+
+    pub async fn create(
+        &self,
+        rating: &Rating,
+        chat_id: Integer,
+        message_id: Integer,
+    ) -> Result<(), RatingServiceError> {
+        self.client
+            .execute(
+                r#"
+                INSERT INTO ratings
+                    (conversation_id, subscriber_user_id, admin_user_id, admin_chat_id, chat_id, message_id, created_at)
+                VALUES
+                    ($1, $2, $3, $4, $5, $6, $7)
+                "#,
+                &[
+                    &rating.conversation_id,
+                    &rating.subscriber_user_id,
+                    &rating.admin_user_id,
+                    &rating.admin_chat_id,
+                    &chat_id,
+                    &message_id,
+                    &Utc::now().naive_utc(),
+                ],
+            )
+            .await
+            .map_err(|source| RatingServiceError::Create {
+                source,
+                conversation_id: rating.conversation_id,
+            })?;
+        Ok(())
+    }
+
+    pub async fn rate(
+        &self,
+        conversation_id: i32,
+        subscriber_user_id: Integer,
+        score: i32,
+    ) -> Result<Option<Rating>, RatingServiceError> {
+        let row = self
+            .client
+            .query_opt(
+                &format!(
+                    r#"
+                    UPDATE ratings SET score = $1, rated_at = $2
+                    WHERE conversation_id = $3 AND subscriber_user_id = $4 AND score IS NULL
+                    RETURNING {}
+                    "#,
+                    COLUMNS
+                ),
+                &[&score, &Utc::now().naive_utc(), &conversation_id, &subscriber_user_id],
+            )
+            .await
+            .map_err(|source| RatingServiceError::Rate {
+                source,
+                conversation_id,
+            })?;
+        Ok(row.map(Rating::from))
+    }
+
+    pub async fn find_commentable(
+        &self,
+        chat_id: Integer,
+        message_id: Integer,
+    ) -> Result<Option<Rating>, RatingServiceError> {
+        let row = self
+            .client
+            .query_opt(
+                &format!(
+                    r#"
+                    SELECT {} FROM ratings
+                    WHERE chat_id = $1 AND message_id = $2 AND score IS NOT NULL AND comment IS NULL
+                    "#,
+                    COLUMNS
+                ),
+                &[&chat_id, &message_id],
+            )
+            .await
+            .map_err(|source| RatingServiceError::FindCommentable {
+                source,
+                chat_id,
+                message_id,
+            })?;
+        Ok(row.map(Rating::from))
+    }
+
+    pub async fn set_comment(&self, conversation_id: i32, comment: &str) -> Result<(), RatingServiceError> {
+        self.client
+            .execute(
+                "UPDATE ratings SET comment = $1 WHERE conversation_id = $2",
+                &[&comment, &conversation_id],
+            )
+            .await
+            .map_err(|source| RatingServiceError::SetComment {
+                source,
+                conversation_id,
+            })?;
+        Ok(())
+    }
+
+    pub async fn get_stats(
+        &self,
+        since: Option<NaiveDateTime>,
+        source: Option<&str>,
+    ) -> Result<Vec<RatingStats>, RatingServiceError> {
+        Ok(self
+            .client
+            .query(
+                r#"
+                SELECT r.admin_user_id, COUNT(*), AVG(r.score)::float8
+                FROM ratings r JOIN users u ON u.id = r.subscriber_user_id
+                WHERE r.score IS NOT NULL
+                AND ($1::timestamp IS NULL OR r.rated_at >= $1)
+                AND ($2::varchar IS NULL OR u.source = $2)
+                GROUP BY r.admin_user_id
+                ORDER BY COUNT(*) DESC
+                "#,
+                &[&since, &source],
+            )
+            .await
+            .map_err(|source| RatingServiceError::GetStats { source })?
+            .into_iter()
+            .map(|row| RatingStats {
+                admin_user_id: row.get(0),
+                count: row.get(1),
+                average: row.get(2),
+            })
+            .collect())
+    }
+}
+
+pub struct Rating {
+    conversation_id: i32,
+    subscriber_user_id: Integer,
+    admin_user_id: Option<Integer>,
+    admin_chat_id: Integer,
+    score: Option<i32>,
+}
+
+impl Rating {
+    pub fn new(
+        conversation_id: i32,
+        subscriber_user_id: Integer,
+        admin_user_id: Option<Integer>,
+        admin_chat_id: Integer,
+    ) -> Self {
+        Self {
+            conversation_id,
+            subscriber_user_id,
+            admin_user_id,
+            admin_chat_id,
+            score: None,
+        }
+    }
+
+    pub fn conversation_id(&self) -> i32 {
+        self.conversation_id
+    }
+
+    pub fn admin_user_id(&self) -> Option<Integer> {
+        self.admin_user_id
+    }
+
+    pub fn admin_chat_id(&self) -> Integer {
+        self.admin_chat_id
+    }
+
+    pub fn score(&self) -> Option<i32> {
+        self.score
+    }
+}
+
+impl From<Row> for Rating {
+    fn from(row: Row) -> Self {
+        Rating {
+            conversation_id: row.get(0),
+            subscriber_user_id: row.get(1),
+            admin_user_id: row.get(2),
+            admin_chat_id: row.get(3),
+            score: row.get(4),
+        }
+    }
+}
+
+pub struct RatingStats {
+    admin_user_id: Option<Integer>,
+    count: i64,
+    average: f64,
+}
+
+impl RatingStats {
+    pub fn admin_user_id(&self) -> Option<Integer> {
+        self.admin_user_id
+    }
+
+    pub fn count(&self) -> i64 {
+        self.count
+    }
+
+    pub fn average(&self) -> f64 {
+        self.average
+    }
+}
+
+#[derive(Debug)]
+pub enum RatingServiceError {
+    Create {
+        source: ClientError,
+        conversation_id: i32,
+    },
+    FindCommentable {
+        source: ClientError,
+        chat_id: Integer,
+        message_id: Integer,
+    },
+    GetStats {
+        source: ClientError,
+    },
+    Rate {
+        source: ClientError,
+        conversation_id: i32,
+    },
+    SetComment {
+        source: ClientError,
+        conversation_id: i32,
+    },
+}
+
+impl fmt::Display for RatingServiceError {
+    fn fmt(&self, out: &mut fmt::Formatter) -> fmt::Result {
+        use self::RatingServiceError::*;
+        match self {
+            Create {
+                source,
+                conversation_id,
+            } => write!(
+                out,
+                "failed to create rating for conversation with id {}: {}",
+                conversation_id, source
+            ),
+            FindCommentable {
+                source,
+                chat_id,
+                message_id,
+            } => write!(
+                out,
+                "failed to find rating for message {} in chat {}: {}",
+                message_id, chat_id, source
+            ),
+            GetStats { source } => write!(out, "failed to get rating stats: {}", source),
+            Rate {
+                source,
+                conversation_id,
+            } => write!(
+                out,
+                "failed to rate conversation with id {}: {}",
+                conversation_id, source
+            ),
+            SetComment {
+                source,
+                conversation_id,
+            } => write!(
+                out,
+                "failed to set rating comment for conversation with id {}: {}",
+                conversation_id, source
+            ),
+        }
+    }
+}
+
+impl Error for RatingServiceError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        use self::RatingServiceError::*;
+        Some(match self {
+            Create { source, .. } => source,
+            FindCommentable { source, .. } => source,
+            GetStats { source } => source,
+            Rate { source, .. } => source,
+            SetComment { source, .. } => source,
+        })
+    }
+}
@@ -0,0 +1,402 @@
+use crate::db::Client;
+use carapax::types::Integer;
+use chrono::{NaiveDateTime, Utc};
+use std::{collections::HashMap, error::Error, fmt, sync::Arc};
+use tokio_postgres::{Error as ClientError, Row};
+
+#[derive(Clone)]
+pub struct ScheduleService {
+    client: Arc<Client>,
+}
+
+impl ScheduleService {
+    pub fn new(client: Arc<Client>) -> Self {
+        Self { client }
+    }
+
+    pub async fn create(&self, message: ScheduledMessage) -> Result<i32, ScheduleServiceError> {
+        let row = self
+            .client
+            .query_one(
+                r#"
+                INSERT INTO scheduled_messages
+                    (chat_id, message_id, prompt_message_id, subscriber_user_id, reply_to_message_id, admin_user_id,
+                    send_at, created_at)
+                VALUES
+                    ($1, $2, $3, $4, $5, $6, $7, $8)
+                RETURNING id
+                "#,
+                &[
+                    &message.chat_id(),
+                    &message.message_id(),
+                    &message.prompt_message_id,
+                    &message.subscriber_user_id(),
+                    &message.reply_to_message_id(),
+                    &message.admin_user_id(),
+                    &message.send_at(),
+                    &Utc::now().naive_utc(),
+                ],
+            )
+            .await
+            .map_err(|source| ScheduleServiceError::Create { source, message })?;
+        Ok(row.get(0))
+    }
+
+    pub async fn get_list(&self) -> Result<Vec<ScheduledMessage>, ScheduleServiceError> {
+        Ok(self
+            .client
+            .query("SELECT * FROM scheduled_messages ORDER BY send_at", &[])
+            .await
+            .map_err(|source| ScheduleServiceError::GetList { source })?
+            .into_iter()
+            .map(ScheduledMessage::from)
+            .collect())
+    }
+
+    pub async fn find(&self, id: i32) -> Result<Option<ScheduledMessage>, ScheduleServiceError> {
+        let row = self
+            .client
+            .query_opt("SELECT * FROM scheduled_messages WHERE id = $1", &[&id])
+            .await
+            .map_err(|source| ScheduleServiceError::Find { source, id })?;
+        Ok(row.map(ScheduledMessage::from))
+    }
+
+    pub async fn delete(&self, id: i32) -> Result<bool, ScheduleServiceError> {
+        let affected_rows = self
+            .client
+            .execute("DELETE FROM scheduled_messages WHERE id = $1", &[&id])
+            .await
+            .map_err(|source| ScheduleServiceError::Delete { source, id })?;
+        Ok(affected_rows != 0)
+    }
+
+    pub async fn set_broadcast_progress(
+        &self,
+        id: i32,
+        progress: BroadcastProgress,
+    ) -> Result<(), ScheduleServiceError> {
+        self.client
+            .execute(
+                r#"
+                UPDATE scheduled_messages SET broadcast_user_id = $2, broadcast_sent = $3, broadcast_failed = $4
+                WHERE id = $1
+                "#,
+                &[&id, &progress.user_id, &progress.sent, &progress.failed],
+            )
+            .await
+            .map_err(|source| ScheduleServiceError::SetBroadcastProgress { source, id })?;
+        Ok(())
+    }
+
+    pub async fn is_awaiting_reply(
+        &self,
+        chat_id: Integer,
+        prompt_message_id: Integer,
+    ) -> Result<bool, ScheduleServiceError> {
+        let row = self
+            .client
+            .query_opt(
+                r#"
+                SELECT id FROM scheduled_messages
+                WHERE chat_id = $1 AND prompt_message_id = $2 AND message_id IS NULL
+                "#,
+                &[&chat_id, &prompt_message_id],
+            )
+            .await
+            .map_err(|source| ScheduleServiceError::FindPrompt { source })?;
+        Ok(row.is_some())
+    }
+
+    pub async fn find_prompt(
+        &self,
+        chat_id: Integer,
+        prompt_message_id: Integer,
+    ) -> Result<Option<ScheduledMessage>, ScheduleServiceError> {
+        let row = self
+            .client
+            .query_opt(
+                r#"
+                SELECT * FROM scheduled_messages
+                WHERE chat_id = $1 AND prompt_message_id = $2 AND message_id IS NULL
+                "#,
+                &[&chat_id, &prompt_message_id],
+            )
+            .await
+            .map_err(|source| ScheduleServiceError::FindPrompt { source })?;
+        Ok(row.map(ScheduledMessage::from))
+    }
+
+    pub async fn set_reply(
+        &self,
+        chat_id: Integer,
+        prompt_message_id: Integer,
+        message_id: Integer,
+        admin_user_id: Option<Integer>,
+    ) -> Result<Option<i32>, ScheduleServiceError> {
+        let row = self
+            .client
+            .query_opt(
+                r#"
+                UPDATE scheduled_messages SET message_id = $3, admin_user_id = $4
+                WHERE chat_id = $1 AND prompt_message_id = $2 AND message_id IS NULL
+                RETURNING id
+                "#,
+                &[&chat_id, &prompt_message_id, &message_id, &admin_user_id],
+            )
+            .await
+            .map_err(|source| ScheduleServiceError::SetReply { source })?;
+        Ok(row.map(|row| row.get(0)))
+    }
+
+    pub async fn get_due(&self) -> Result<Vec<ScheduledMessage>, ScheduleServiceError> {
+        Ok(self
+            .client
+            .query(
+                r#"
+                SELECT * FROM scheduled_messages
+                WHERE send_at <= $1 AND message_id IS NOT NULL
+                ORDER BY send_at
+                "#,
+                &[&Utc::now().naive_utc()],
+            )
+            .await
+            .map_err(|source| ScheduleServiceError::GetDue { source })?
+            .into_iter()
+            .map(ScheduledMessage::from)
+            .collect())
+    }
+}
+
+#[derive(Debug)]
+pub struct ScheduledMessage {
+    id: Option<i32>,
+    chat_id: Integer,
+    message_id: Option<Integer>,
+    prompt_message_id: Option<Integer>,
+    subscriber_user_id: Option<Integer>,
+    reply_to_message_id: Option<Integer>,
+    admin_user_id: Option<Integer>,
+    send_at: NaiveDateTime,
+    broadcast_progress: BroadcastProgress,
+}
+
+impl ScheduledMessage {
+    pub fn new(
+        chat_id: Integer,
+        message_id: Integer,
+        subscriber_user_id: Option<Integer>,
+        admin_user_id: Option<Integer>,
+        send_at: NaiveDateTime,
+    ) -> Self {
+        Self {
+            id: None,
+            chat_id,
+            message_id: Some(message_id),
+            prompt_message_id: None,
+            subscriber_user_id,
+            reply_to_message_id: None,
+            admin_user_id,
+            send_at,
+            broadcast_progress: BroadcastProgress::default(),
+        }
+    }
+
+    pub fn new_reply(
+        chat_id: Integer,
+        prompt_message_id: Integer,
+        subscriber_user_id: Integer,
+        reply_to_message_id: Integer,
+        send_at: NaiveDateTime,
+    ) -> Self {
+        Self {
+            id: None,
+            chat_id,
+            message_id: None,
+            prompt_message_id: Some(prompt_message_id),
+            subscriber_user_id: Some(subscriber_user_id),
+            reply_to_message_id: Some(reply_to_message_id),
+            admin_user_id: None,
+            send_at,
+            broadcast_progress: BroadcastProgress::default(),
+        }
+    }
+
+    pub fn id(&self) -> Option<i32> {
+        self.id
+    }
+
+    pub fn chat_id(&self) -> Integer {
+        self.chat_id
+    }
+
+    pub fn message_id(&self) -> Option<Integer> {
+        self.message_id
+    }
+
+    pub fn report_message_id(&self) -> Option<Integer> {
+        self.message_id.or(self.prompt_message_id)
+    }
+
+    pub fn subscriber_user_id(&self) -> Option<Integer> {
+        self.subscriber_user_id
+    }
+
+    pub fn reply_to_message_id(&self) -> Option<Integer> {
+        self.reply_to_message_id
+    }
+
+    /// Admin whose signature is added to a reply
+    pub fn admin_user_id(&self) -> Option<Integer> {
+        self.admin_user_id
+    }
+
+    pub fn send_at(&self) -> NaiveDateTime {
+        self.send_at
+    }
+
+    pub fn broadcast_progress(&self) -> BroadcastProgress {
+        self.broadcast_progress
+    }
+}
+
+/// Subscribers a broadcast has been sent to so far, ordered by ID
+#[derive(Clone, Copy, Debug, Default)]
+pub struct BroadcastProgress {
+    user_id: Option<Integer>,
+    sent: i32,
+    failed: i32,
+}
+
+impl BroadcastProgress {
+    /// The last subscriber a broadcast has been sent to
+    pub fn user_id(&self) -> Option<Integer> {
+        self.user_id
+    }
+
+    pub fn sent(&self) -> i32 {
+        self.sent
+    }
+
+    pub fn total(&self) -> i32 {
+        self.sent + self.failed
+    }
+
+    pub fn add(&mut self, user_id: Integer, is_sent: bool) {
+        self.user_id = Some(user_id);
+        if is_sent {
+            self.sent += 1;
+        } else {
+            self.failed += 1;
+        }
+    }
+}
+
+impl fmt::Display for ScheduledMessage {
+    fn fmt(&self, out: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(id) = self.id {
+            write!(out, "<code>{}</code> ", id)?;
+        }
+        write!(out, "{}", self.send_at.format("%Y-%m-%d %H:%M"))?;
+        match self.subscriber_user_id {
+            Some(user_id) => write!(out, r#" → <a href="tg://user?id={id}">{id}</a>"#, id = user_id),
+            None => write!(out, " → all"),
+        }?;
+        if self.message_id.is_none() {
+            write!(out, " (awaiting reply)")?;
+        }
+        Ok(())
+    }
+}
+
+impl From<Row> for ScheduledMessage {
+    fn from(row: Row) -> Self {
+        let indexes: HashMap<&str, usize> = row
+            .columns()
+            .iter()
+            .enumerate()
+            .map(|(idx, column)| (column.name(), idx))
+            .collect();
+        ScheduledMessage {
+            id: Some(row.get(indexes["id"])),
+            chat_id: row.get(indexes["chat_id"]),
+            message_id: row.get(indexes["message_id"]),
+            prompt_message_id: row.get(indexes["prompt_message_id"]),
+            subscriber_user_id: row.get(indexes["subscriber_user_id"]),
+            reply_to_message_id: row.get(indexes["reply_to_message_id"]),
+            admin_user_id: row.get(indexes["admin_user_id"]),
+            send_at: row.get(indexes["send_at"]),
+            broadcast_progress: BroadcastProgress {
+                user_id: row.get(indexes["broadcast_user_id"]),
+                sent: row.get(indexes["broadcast_sent"]),
+                failed: row.get(indexes["broadcast_failed"]),
+            },
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ScheduleServiceError {
+    Create {
+        source: ClientError,
+        message: ScheduledMessage,
+    },
+    Delete {
+        source: ClientError,
+        id: i32,
+    },
+    Find {
+        source: ClientError,
+        id: i32,
+    },
+    FindPrompt {
+        source: ClientError,
+    },
+    GetDue {
+        source: ClientError,
+    },
+    GetList {
+        source: ClientError,
+    },
+    SetBroadcastProgress {
+        source: ClientError,
+        id: i32,
+    },
+    SetReply {
+        source: ClientError,
+    },
+}
+
+impl fmt::Display for ScheduleServiceError {
+    fn fmt(&self, out: &mut fmt::Formatter) -> fmt::Result {
+        use self::ScheduleServiceError::*;
+        match self {
+            Create { source, message } => write!(out, "could not create scheduled message: {} ({:?})", source, message),
+            Delete { source, id } => write!(out, "could not delete scheduled message {}: {}", id, source),
+            Find { source, id } => write!(out, "could not find scheduled message {}: {}", id, source),
+            FindPrompt { source } => write!(out, "could not find scheduled reply prompt: {}", source),
+            GetDue { source } => write!(out, "could not get due scheduled messages: {}", source),
+            GetList { source } => write!(out, "could not get scheduled messages: {}", source),
+            SetBroadcastProgress { source, id } => {
+                write!(out, "could not save progress of scheduled broadcast {}: {}", id, source)
+            }
+            SetReply { source } => write!(out, "could not set scheduled reply: {}", source),
+        }
+    }
+}
+
+impl Error for ScheduleServiceError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        use self::ScheduleServiceError::*;
+        Some(match self {
+            Create { source, .. } => source,
+            Delete { source, .. } => source,
+            Find { source, .. } => source,
+            FindPrompt { source } => source,
+            GetDue { source } => source,
+            GetList { source } => source,
+            SetBroadcastProgress { source, .. } => source,
+            SetReply { source } => source,
+        })
+    }
+}
@@ -0,0 +1,84 @@
+use crate::db::Client;
+use chrono::Utc;
+use std::{error::Error, fmt, sync::Arc};
+use tokio_postgres::Error as ClientError;
+
+pub const SETTING_AUTO_ANSWERS_ENABLED: &str = "auto_answers_enabled";
+
+#[derive(Clone)]
+pub struct SettingsService {
+    client: Arc<Client>,
+}
+
+impl SettingsService {
+    pub fn new(client: Arc<Client>) -> Self {
+        Self { client }
+    }
+
+    pub async fn get(&self, key: &str) -> Result<Option<String>, SettingsServiceError> {
+        let row = self
+            .client
+            .query_opt("SELECT value FROM settings WHERE key = $1", &[&key])
+            .await
+            .map_err(|source| SettingsServiceError::Get {
+                source,
+                key: key.to_string(),
+            })?;
+        Ok(row.map(|row| row.get(0)))
+    }
+
+    pub async fn get_bool(&self, key: &str, default: bool) -> Result<bool, SettingsServiceError> {
+        Ok(self.get(key).await?.map(|value| value == "true").unwrap_or(default))
+    }
+
+    pub async fn set<V>(&self, key: &str, value: V) -> Result<(), SettingsServiceError>
+    where
+        V: ToString,
+    {
+        self.client
+            .execute(
+                r#"
+                INSERT INTO settings
+                    (key, value, updated_at)
+                VALUES
+                    ($1, $2, $3)
+                ON CONFLICT (key) DO UPDATE SET
+                    value = EXCLUDED.value,
+                    updated_at = EXCLUDED.updated_at
+                "#,
+                &[&key, &value.to_string(), &Utc::now().naive_utc()],
+            )
+            .await
+            .map_err(|source| SettingsServiceError::Set {
+                source,
+                key: key.to_string(),
+            })?;
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+pub enum SettingsServiceError {
+    Get { source: ClientError, key: String },
+    Set { source: ClientError, key: String },
+}
+
+impl fmt::Display for SettingsServiceError {
+    fn fmt(&self, out: &mut fmt::Formatter) -> fmt::Result {
+        use self::SettingsServiceError::*;
+        match self {
+            Get { source, key } => write!(out, "could not get setting '{}': {}", key, source),
+            Set { source, key } => write!(out, "could not set setting '{}': {}", key, source),
+        }
+    }
+}
+
+impl Error for SettingsServiceError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        use self::SettingsServiceError::*;
+        Some(match self {
+            Get { source, .. } => source,
+            Set { source, .. } => source,
+        })
+    }
+}
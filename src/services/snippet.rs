@@ -0,0 +1,156 @@
+use crate::db::Client;
+use carapax::types::Integer;
+use chrono::Utc;
+use std::{collections::HashMap, error::Error, fmt, sync::Arc};
+use tokio_postgres::{Error as ClientError, Row};
+
+#[derive(Clone)]
+pub struct SnippetService {
+    client: Arc<Client>,
+}
+
+impl SnippetService {
+    pub fn new(client: Arc<Client>) -> Self {
+        Self { client }
+    }
+
+    pub async fn save(&self, snippet: Snippet) -> Result<(), SnippetServiceError> {
+        self.client
+            .execute(
+                r#"
+                INSERT INTO snippets
+                    (name, chat_id, message_id, created_at)
+                VALUES
+                    ($1, $2, $3, $4)
+                ON CONFLICT (name) DO UPDATE SET
+                    chat_id = EXCLUDED.chat_id,
+                    message_id = EXCLUDED.message_id,
+                    created_at = EXCLUDED.created_at
+                "#,
+                &[
+                    &snippet.name(),
+                    &snippet.chat_id(),
+                    &snippet.message_id(),
+                    &Utc::now().naive_utc(),
+                ],
+            )
+            .await
+            .map_err(|source| SnippetServiceError::Save { source, snippet })?;
+        Ok(())
+    }
+
+    pub async fn find(&self, name: &str) -> Result<Option<Snippet>, SnippetServiceError> {
+        let row = self
+            .client
+            .query_opt("SELECT * FROM snippets WHERE name = $1", &[&name])
+            .await
+            .map_err(|source| SnippetServiceError::Find {
+                source,
+                name: name.to_string(),
+            })?;
+        Ok(row.map(Snippet::from))
+    }
+
+    pub async fn get_list(&self) -> Result<Vec<Snippet>, SnippetServiceError> {
+        Ok(self
+            .client
+            .query("SELECT * FROM snippets ORDER BY name", &[])
+            .await
+            .map_err(|source| SnippetServiceError::GetList { source })?
+            .into_iter()
+            .map(Snippet::from)
+            .collect())
+    }
+
+    pub async fn delete(&self, name: &str) -> Result<bool, SnippetServiceError> {
+        let affected_rows = self
+            .client
+            .execute("DELETE FROM snippets WHERE name = $1", &[&name])
+            .await
+            .map_err(|source| SnippetServiceError::Delete {
+                source,
+                name: name.to_string(),
+            })?;
+        Ok(affected_rows != 0)
+    }
+}
+
+#[derive(Debug)]
+pub struct Snippet {
+    name: String,
+    chat_id: Integer,
+    message_id: Integer,
+}
+
+impl Snippet {
+    pub fn new<N>(name: N, chat_id: Integer, message_id: Integer) -> Self
+    where
+        N: Into<String>,
+    {
+        Self {
+            name: name.into(),
+            chat_id,
+            message_id,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn chat_id(&self) -> Integer {
+        self.chat_id
+    }
+
+    pub fn message_id(&self) -> Integer {
+        self.message_id
+    }
+}
+
+impl From<Row> for Snippet {
+    fn from(row: Row) -> Self {
+        let indexes: HashMap<&str, usize> = row
+            .columns()
+            .iter()
+            .enumerate()
+            .map(|(idx, column)| (column.name(), idx))
+            .collect();
+        Snippet::new::<String>(
+            row.get(indexes["name"]),
+            row.get(indexes["chat_id"]),
+            row.get(indexes["message_id"]),
+        )
+    }
+}
+
+#[derive(Debug)]
+pub enum SnippetServiceError {
+    Delete { source: ClientError, name: String },
+    Find { source: ClientError, name: String },
+    GetList { source: ClientError },
+    Save { source: ClientError, snippet: Snippet },
+}
+
+impl fmt::Display for SnippetServiceError {
+    fn fmt(&self, out: &mut fmt::Formatter) -> fmt::Result {
+        use self::SnippetServiceError::*;
+        match self {
+            Delete { source, name } => write!(out, "could not delete snippet '{}': {}", name, source),
+            Find { source, name } => write!(out, "could not find snippet '{}': {}", name, source),
+            GetList { source } => write!(out, "could not get snippets: {}", source),
+            Save { source, snippet } => write!(out, "could not save snippet: {} ({:?})", source, snippet),
+        }
+    }
+}
+
+impl Error for SnippetServiceError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        use self::SnippetServiceError::*;
+        Some(match self {
+            Delete { source, .. } => source,
+            Find { source, .. } => source,
+            GetList { source } => source,
+            Save { source, .. } => source,
+        })
+    }
+}
@@ -1,10 +1,19 @@
+use crate::{db::Client, metrics::ErrorVariant};
 use carapax::types::{Integer, User};
-use chrono::{NaiveDateTime, Utc};
+use chrono::{Duration, NaiveDateTime, Utc};
 use serde::{Deserialize, Serialize};
 use std::{collections::HashMap, error::Error, fmt, sync::Arc};
-use tokio_postgres::{Client, Error as ClientError, Row};
+use tokio_postgres::{Error as ClientError, Row};
 
 const ITEMS_PER_PAGE: i64 = 5;
+// the same limit as for deep-link payloads
+const MAX_SOURCE_LENGTH: usize = 64;
+
+pub fn is_valid_source(value: &str) -> bool {
+    !value.is_empty()
+        && value.len() <= MAX_SOURCE_LENGTH
+        && value.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+}
 
 #[derive(Clone)]
 pub struct UserService {
@@ -20,24 +29,69 @@ impl UserService {
         &self,
         page_number: i64,
         block_filter: UserBlockFilter,
+        source: Option<String>,
     ) -> Result<UserInfoList, UserServiceError> {
-        let total_items = self.count(block_filter).await?;
+        let total_items = self.count(block_filter, source.as_deref()).await?;
         let offset = (page_number * ITEMS_PER_PAGE - ITEMS_PER_PAGE).abs();
         let items = self
             .client
             .query(
                 &format!(
-                    "SELECT * FROM users {} ORDER BY created_at DESC LIMIT $1 OFFSET $2",
+                    r#"
+                    SELECT * FROM users WHERE {} AND ($3::varchar IS NULL OR source = $3)
+                    ORDER BY created_at DESC LIMIT $1 OFFSET $2
+                    "#,
                     block_filter.as_sql()
                 ),
-                &[&ITEMS_PER_PAGE, &offset],
+                &[&ITEMS_PER_PAGE, &offset, &source],
             )
             .await
             .map_err(|source| UserServiceError::GetList { source, page_number })?
             .into_iter()
             .map(UserInfo::from)
             .collect();
-        Ok(UserInfoList::new(items, page_number, total_items, block_filter))
+        Ok(UserInfoList::new(items, page_number, total_items, block_filter, source))
+    }
+
+    pub async fn get(&self, user_id: Integer) -> Result<Option<User>, UserServiceError> {
+        let row = self
+            .client
+            .query_opt(
+                "SELECT first_name, last_name, username, language_code FROM users WHERE id = $1",
+                &[&user_id],
+            )
+            .await
+            .map_err(|source| UserServiceError::GetUser { source, user_id })?;
+        Ok(row.map(|row| User {
+            id: user_id,
+            is_bot: false,
+            first_name: row.get(0),
+            last_name: row.get(1),
+            username: row.get(2),
+            language_code: row.get(3),
+        }))
+    }
+
+    pub async fn find_by_username(&self, username: &str) -> Result<Option<User>, UserServiceError> {
+        let row = self
+            .client
+            .query_opt(
+                "SELECT id, first_name, last_name, username, language_code FROM users WHERE lower(username) = lower($1)",
+                &[&username],
+            )
+            .await
+            .map_err(|source| UserServiceError::FindByUsername {
+                source,
+                username: String::from(username),
+            })?;
+        Ok(row.map(|row| User {
+            id: row.get(0),
+            is_bot: false,
+            first_name: row.get(1),
+            last_name: row.get(2),
+            username: row.get(3),
+            language_code: row.get(4),
+        }))
     }
 
     pub async fn save(&self, user: User) -> Result<(), UserServiceError> {
@@ -57,19 +111,116 @@ impl UserService {
         self.set_block(user_id, false).await
     }
 
+    pub async fn block_until(&self, user_id: Integer, until: NaiveDateTime) -> Result<bool, UserServiceError> {
+        let affected_rows = self
+            .client
+            .execute("UPDATE users SET blocked_until = $1 WHERE id = $2", &[&until, &user_id])
+            .await
+            .map_err(|source| UserServiceError::SetBlockedUntil { source, user_id, until })?;
+        Ok(affected_rows != 0)
+    }
+
     pub async fn is_blocked(&self, user_id: Integer) -> Result<bool, UserServiceError> {
         let row = self
             .client
-            .query_opt("SELECT is_blocked FROM users WHERE id = $1", &[&user_id])
+            .query_opt(
+                "SELECT is_blocked OR COALESCE(blocked_until > $2, FALSE) FROM users WHERE id = $1",
+                &[&user_id, &Utc::now().naive_utc()],
+            )
             .await
             .map_err(|source| UserServiceError::CheckIsBlocked { source, user_id })?;
         Ok(row.map(|row| row.get(0)).unwrap_or(false))
     }
 
-    async fn count(&self, block_filter: UserBlockFilter) -> Result<i64, UserServiceError> {
+    pub async fn mark_away_message_sent(&self, user_id: Integer, interval: Duration) -> Result<bool, UserServiceError> {
+        let now = Utc::now().naive_utc();
+        let affected_rows = self
+            .client
+            .execute(
+                r#"
+                UPDATE users SET away_message_sent_at = $1
+                WHERE id = $2 AND (away_message_sent_at IS NULL OR away_message_sent_at <= $3)
+                "#,
+                &[&now, &user_id, &(now - interval)],
+            )
+            .await
+            .map_err(|source| UserServiceError::MarkAwayMessageSent { source, user_id })?;
+        Ok(affected_rows != 0)
+    }
+
+    pub async fn set_source(&self, user_id: Integer, source: &str) -> Result<bool, UserServiceError> {
+        // updated_at is set by every update after the one that created the user
+        let affected_rows = self
+            .client
+            .execute(
+                "UPDATE users SET source = $1 WHERE id = $2 AND source IS NULL AND updated_at IS NULL",
+                &[&source, &user_id],
+            )
+            .await
+            .map_err(|source| UserServiceError::SetSource { source, user_id })?;
+        Ok(affected_rows != 0)
+    }
+
+    pub async fn get_source(&self, user_id: Integer) -> Result<Option<String>, UserServiceError> {
+        let row = self
+            .client
+            .query_opt("SELECT source FROM users WHERE id = $1", &[&user_id])
+            .await
+            .map_err(|source| UserServiceError::GetSource { source, user_id })?;
+        Ok(row.and_then(|row| row.get(0)))
+    }
+
+    pub async fn count_by_source(&self, source: &str, since: Option<NaiveDateTime>) -> Result<i64, UserServiceError> {
         let row = self
             .client
-            .query_one(&format!("SELECT COUNT(*) FROM users {}", block_filter.as_sql()), &[])
+            .query_one(
+                "SELECT COUNT(*) FROM users WHERE source = $1 AND ($2::timestamp IS NULL OR created_at >= $2)",
+                &[&source, &since],
+            )
+            .await
+            .map_err(|source| UserServiceError::Count { source })?;
+        Ok(row.get(0))
+    }
+
+    pub async fn count_blocked(&self) -> Result<i64, UserServiceError> {
+        self.count(UserBlockFilter::True, None).await
+    }
+
+    /// Returns IDs of subscribers in ascending order, starting after the given one
+    pub async fn get_subscriber_ids(
+        &self,
+        after_user_id: Option<Integer>,
+        limit: i64,
+    ) -> Result<Vec<Integer>, UserServiceError> {
+        Ok(self
+            .client
+            .query(
+                r#"
+                SELECT id FROM users
+                WHERE is_blocked IS FALSE
+                AND EXISTS (SELECT 1 FROM message_links WHERE subscriber_user_id = users.id)
+                AND ($1::bigint IS NULL OR id > $1)
+                ORDER BY id LIMIT $2
+                "#,
+                &[&after_user_id, &limit],
+            )
+            .await
+            .map_err(|source| UserServiceError::GetSubscriberIds { source })?
+            .into_iter()
+            .map(|row| row.get(0))
+            .collect())
+    }
+
+    async fn count(&self, block_filter: UserBlockFilter, source: Option<&str>) -> Result<i64, UserServiceError> {
+        let row = self
+            .client
+            .query_one(
+                &format!(
+                    "SELECT COUNT(*) FROM users WHERE {} AND ($1::varchar IS NULL OR source = $1)",
+                    block_filter.as_sql()
+                ),
+                &[&source],
+            )
             .await
             .map_err(|source| UserServiceError::Count { source })?;
         Ok(row.get(0))
@@ -88,12 +239,18 @@ impl UserService {
     async fn create(&self, user: User) -> Result<(), UserServiceError> {
         self.client
             .execute(
-                "INSERT INTO users (id, first_name, last_name, username, created_at) VALUES ($1, $2, $3, $4, $5)",
+                r#"
+                INSERT INTO users
+                    (id, first_name, last_name, username, language_code, created_at)
+                VALUES
+                    ($1, $2, $3, $4, $5, $6)
+                "#,
                 &[
                     &user.id,
                     &user.first_name,
                     &user.last_name,
                     &user.username,
+                    &user.language_code,
                     &Utc::now().naive_utc(),
                 ],
             )
@@ -105,11 +262,16 @@ impl UserService {
     async fn update(&self, user: User) -> Result<(), UserServiceError> {
         self.client
             .execute(
-                "UPDATE users SET first_name = $1, last_name = $2, username = $3, updated_at = $4 WHERE id = $5",
+                r#"
+                UPDATE users SET
+                    first_name = $1, last_name = $2, username = $3, language_code = $4, updated_at = $5
+                WHERE id = $6
+                "#,
                 &[
                     &user.first_name,
                     &user.last_name,
                     &user.username,
+                    &user.language_code,
                     &Utc::now().naive_utc(),
                     &user.id,
                 ],
@@ -122,7 +284,10 @@ impl UserService {
     async fn set_block(&self, user_id: Integer, value: bool) -> Result<bool, UserServiceError> {
         let affected_rows = self
             .client
-            .execute("UPDATE users SET is_blocked = $1 WHERE id = $2", &[&value, &user_id])
+            .execute(
+                "UPDATE users SET is_blocked = $1, blocked_until = NULL WHERE id = $2",
+                &[&value, &user_id],
+            )
             .await
             .map_err(|source| UserServiceError::SetBlock { source, user_id, value })?;
         Ok(affected_rows != 0)
@@ -135,15 +300,23 @@ pub struct UserInfoList {
     page_number: i64,
     total_items: i64,
     block_filter: UserBlockFilter,
+    source: Option<String>,
 }
 
 impl UserInfoList {
-    fn new(items: Vec<UserInfo>, page_number: i64, total_items: i64, block_filter: UserBlockFilter) -> Self {
+    fn new(
+        items: Vec<UserInfo>,
+        page_number: i64,
+        total_items: i64,
+        block_filter: UserBlockFilter,
+        source: Option<String>,
+    ) -> Self {
         Self {
             items,
             page_number,
             total_items,
             block_filter,
+            source,
         }
     }
 
@@ -162,6 +335,10 @@ impl UserInfoList {
     pub fn block_filter(&self) -> UserBlockFilter {
         self.block_filter
     }
+
+    pub fn source(&self) -> Option<&str> {
+        self.source.as_deref()
+    }
 }
 
 impl fmt::Display for UserInfoList {
@@ -179,6 +356,8 @@ struct UserInfo {
     created_at: NaiveDateTime,
     updated_at: Option<NaiveDateTime>,
     is_blocked: bool,
+    blocked_until: Option<NaiveDateTime>,
+    source: Option<String>,
 }
 
 impl fmt::Display for UserInfo {
@@ -198,6 +377,11 @@ impl fmt::Display for UserInfo {
         }
         if self.is_blocked {
             write!(out, " ❌")?;
+        } else if let Some(blocked_until) = self.blocked_until.filter(|x| *x > Utc::now().naive_utc()) {
+            write!(out, " ⏳ {}", blocked_until.format("%d/%m/%y %H:%M:%S"))?;
+        }
+        if let Some(ref source) = self.source {
+            write!(out, " via {}", source)?;
         }
         Ok(())
     }
@@ -219,6 +403,8 @@ impl From<Row> for UserInfo {
             created_at: row.get(indexes["created_at"]),
             updated_at: row.get(indexes["updated_at"]),
             is_blocked: row.get(indexes["is_blocked"]),
+            blocked_until: row.get(indexes["blocked_until"]),
+            source: row.get(indexes["source"]),
         }
     }
 }
@@ -234,9 +420,9 @@ impl UserBlockFilter {
     fn as_sql(&self) -> &'static str {
         use self::UserBlockFilter::*;
         match self {
-            All => "",
-            False => "WHERE is_blocked IS FALSE",
-            True => "WHERE is_blocked IS TRUE",
+            All => "TRUE",
+            False => "is_blocked IS FALSE",
+            True => "is_blocked IS TRUE",
         }
     }
 }
@@ -282,15 +468,43 @@ pub enum UserServiceError {
         source: ClientError,
         user: User,
     },
+    FindByUsername {
+        source: ClientError,
+        username: String,
+    },
     GetList {
         source: ClientError,
         page_number: i64,
     },
+    GetSource {
+        source: ClientError,
+        user_id: Integer,
+    },
+    GetSubscriberIds {
+        source: ClientError,
+    },
+    GetUser {
+        source: ClientError,
+        user_id: Integer,
+    },
+    MarkAwayMessageSent {
+        source: ClientError,
+        user_id: Integer,
+    },
     SetBlock {
         source: ClientError,
         user_id: Integer,
         value: bool,
     },
+    SetBlockedUntil {
+        source: ClientError,
+        user_id: Integer,
+        until: NaiveDateTime,
+    },
+    SetSource {
+        source: ClientError,
+        user_id: Integer,
+    },
     UpdateUser {
         source: ClientError,
         user: User,
@@ -319,7 +533,18 @@ impl fmt::Display for UserServiceError {
             CreateUser { source, user } => {
                 write!(out, "create user error: {} (user={:?})", source, user)
             }
+            FindByUsername { source, username } => write!(out, "find user by username {}: {}", username, source),
             GetList { source, page_number } => write!(out, "get users: {} (page_number={})", source, page_number),
+            GetSource { source, user_id } => write!(out, "get source of user with id {}: {}", user_id, source),
+            GetSubscriberIds { source } => write!(out, "get subscriber ids: {}", source),
+            GetUser { source, user_id } => write!(out, "get user with id {}: {}", user_id, source),
+            MarkAwayMessageSent { source, user_id } => {
+                write!(
+                    out,
+                    "failed to mark away message as sent for user with id {}: {}",
+                    user_id, source
+                )
+            }
             SetBlock { source, user_id, value } => {
                 write!(
                     out,
@@ -327,6 +552,14 @@ impl fmt::Display for UserServiceError {
                     value, user_id, source
                 )
             }
+            SetBlockedUntil { source, user_id, until } => {
+                write!(
+                    out,
+                    "failed to block user with id {} until {}: {}",
+                    user_id, until, source
+                )
+            }
+            SetSource { source, user_id } => write!(out, "set source of user with id {}: {}", user_id, source),
             UpdateUser { source, user } => {
                 write!(out, "update user error: {} (user={:?})", source, user)
             }
@@ -342,9 +575,38 @@ impl Error for UserServiceError {
             CheckIsBlocked { source, .. } => source,
             Count { source, .. } => source,
             CreateUser { source, .. } => source,
+            FindByUsername { source, .. } => source,
             GetList { source, .. } => source,
+            GetSource { source, .. } => source,
+            GetSubscriberIds { source } => source,
+            GetUser { source, .. } => source,
+            MarkAwayMessageSent { source, .. } => source,
             SetBlock { source, .. } => source,
+            SetBlockedUntil { source, .. } => source,
+            SetSource { source, .. } => source,
             UpdateUser { source, .. } => source,
         })
     }
 }
+
+impl ErrorVariant for UserServiceError {
+    fn variant(&self) -> &'static str {
+        use self::UserServiceError::*;
+        match self {
+            CheckExists { .. } => "CheckExists",
+            CheckIsBlocked { .. } => "CheckIsBlocked",
+            Count { .. } => "Count",
+            CreateUser { .. } => "CreateUser",
+            FindByUsername { .. } => "FindByUsername",
+            GetList { .. } => "GetList",
+            GetSource { .. } => "GetSource",
+            GetSubscriberIds { .. } => "GetSubscriberIds",
+            GetUser { .. } => "GetUser",
+            MarkAwayMessageSent { .. } => "MarkAwayMessageSent",
+            SetBlock { .. } => "SetBlock",
+            SetBlockedUntil { .. } => "SetBlockedUntil",
+            SetSource { .. } => "SetSource",
+            UpdateUser { .. } => "UpdateUser",
+        }
+    }
+}
@@ -0,0 +1,286 @@
+use crate::db::Client;
+use carapax::types::{Integer, Message};
+use chrono::{Duration as ChronoDuration, Utc};
+use std::{
+    collections::HashMap,
+    error::Error,
+    fmt,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use tokio_postgres::Error as ClientError;
+
+const HELD_MESSAGE_TTL: Duration = Duration::from_secs(86400);
+// an unanswered challenge is replaced by a new one after this time
+const CHALLENGE_TTL_MINUTES: i64 = 10;
+
+type HeldMessages = HashMap<(Integer, Integer), (Instant, Message)>;
+
+#[derive(Clone)]
+pub struct VerificationService {
+    client: Arc<Client>,
+    // messages can not be stored in the database, so only their ids survive a restart
+    messages: Arc<Mutex<HeldMessages>>,
+}
+
+impl VerificationService {
+    pub fn new(client: Arc<Client>) -> Self {
+        Self {
+            client,
+            messages: Default::default(),
+        }
+    }
+
+    pub async fn is_verified(&self, user_id: Integer) -> Result<bool, VerificationServiceError> {
+        // users who have already written to the bot are considered verified
+        let row = self
+            .client
+            .query_one(
+                r#"
+                SELECT
+                    EXISTS(SELECT 1 FROM verifications WHERE user_id = $1 AND is_verified IS TRUE)
+                    OR EXISTS(SELECT 1 FROM message_links WHERE subscriber_user_id = $1)
+                "#,
+                &[&user_id],
+            )
+            .await
+            .map_err(|source| VerificationServiceError::CheckIsVerified { source, user_id })?;
+        Ok(row.get(0))
+    }
+
+    /// Sets a new challenge unless there is a pending one
+    ///
+    /// Returns `false` when the user has to answer the pending challenge.
+    pub async fn start_challenge(&self, user_id: Integer, answer: i32) -> Result<bool, VerificationServiceError> {
+        let now = Utc::now().naive_utc();
+        let expired_at = now - ChronoDuration::minutes(CHALLENGE_TTL_MINUTES);
+        let affected_rows = self
+            .client
+            .execute(
+                r#"
+                INSERT INTO verifications
+                    (user_id, answer, created_at, challenged_at)
+                VALUES
+                    ($1, $2, $3, $3)
+                ON CONFLICT (user_id) DO UPDATE SET
+                    answer = EXCLUDED.answer,
+                    challenged_at = EXCLUDED.challenged_at
+                WHERE verifications.answer IS NULL
+                    OR verifications.challenged_at IS NULL
+                    OR verifications.challenged_at < $4
+                "#,
+                &[&user_id, &answer, &now, &expired_at],
+            )
+            .await
+            .map_err(|source| VerificationServiceError::SetChallenge { source, user_id })?;
+        Ok(affected_rows != 0)
+    }
+
+    pub async fn set_challenge(&self, user_id: Integer, answer: i32) -> Result<(), VerificationServiceError> {
+        self.client
+            .execute(
+                r#"
+                INSERT INTO verifications
+                    (user_id, answer, created_at, challenged_at)
+                VALUES
+                    ($1, $2, $3, $3)
+                ON CONFLICT (user_id) DO UPDATE SET
+                    answer = EXCLUDED.answer,
+                    challenged_at = EXCLUDED.challenged_at
+                "#,
+                &[&user_id, &answer, &Utc::now().naive_utc()],
+            )
+            .await
+            .map_err(|source| VerificationServiceError::SetChallenge { source, user_id })?;
+        Ok(())
+    }
+
+    pub async fn check_answer(&self, user_id: Integer, answer: i32) -> Result<bool, VerificationServiceError> {
+        let affected_rows = self
+            .client
+            .execute(
+                r#"
+                UPDATE verifications SET is_verified = TRUE, answer = NULL, verified_at = $3
+                WHERE user_id = $1 AND answer = $2 AND is_verified IS FALSE
+                "#,
+                &[&user_id, &answer, &Utc::now().naive_utc()],
+            )
+            .await
+            .map_err(|source| VerificationServiceError::CheckAnswer { source, user_id })?;
+        Ok(affected_rows != 0)
+    }
+
+    pub async fn verify(&self, user_id: Integer) -> Result<bool, VerificationServiceError> {
+        let affected_rows = self
+            .client
+            .execute(
+                r#"
+                INSERT INTO verifications
+                    (user_id, is_verified, created_at, verified_at)
+                SELECT id, TRUE, $2, $2 FROM users WHERE id = $1
+                ON CONFLICT (user_id) DO UPDATE SET
+                    is_verified = TRUE,
+                    answer = NULL,
+                    verified_at = EXCLUDED.verified_at
+                "#,
+                &[&user_id, &Utc::now().naive_utc()],
+            )
+            .await
+            .map_err(|source| VerificationServiceError::Verify { source, user_id })?;
+        Ok(affected_rows != 0)
+    }
+
+    pub async fn hold(&self, mut message: HeldMessage) -> Result<(), VerificationServiceError> {
+        let original = message.message.take();
+        self.client
+            .execute(
+                r#"
+                INSERT INTO held_messages
+                    (user_id, chat_id, message_id, created_at)
+                VALUES
+                    ($1, $2, $3, $4)
+                "#,
+                &[
+                    &message.user_id(),
+                    &message.chat_id(),
+                    &message.message_id(),
+                    &Utc::now().naive_utc(),
+                ],
+            )
+            .await
+            .map_err(|source| VerificationServiceError::Hold { source, message })?;
+        if let Some(original) = original {
+            let mut messages = self.messages.lock().expect("held messages are poisoned");
+            let now = Instant::now();
+            messages.retain(|_, (held_at, _)| now.duration_since(*held_at) < HELD_MESSAGE_TTL);
+            messages.insert((original.get_chat_id(), original.id), (now, *original));
+        }
+        Ok(())
+    }
+
+    pub async fn take_held(&self, user_id: Integer) -> Result<Vec<HeldMessage>, VerificationServiceError> {
+        Ok(self
+            .client
+            .query(
+                r#"
+                WITH held AS (
+                    DELETE FROM held_messages WHERE user_id = $1 RETURNING id, user_id, chat_id, message_id
+                )
+                SELECT user_id, chat_id, message_id FROM held ORDER BY id
+                "#,
+                &[&user_id],
+            )
+            .await
+            .map_err(|source| VerificationServiceError::TakeHeld { source, user_id })?
+            .into_iter()
+            .map(|row| {
+                let mut message = HeldMessage::new(row.get(0), row.get(1), row.get(2));
+                message.message = self
+                    .messages
+                    .lock()
+                    .expect("held messages are poisoned")
+                    .remove(&(message.chat_id, message.message_id))
+                    .map(|(_, original)| Box::new(original));
+                message
+            })
+            .collect())
+    }
+}
+
+#[derive(Debug)]
+pub struct HeldMessage {
+    user_id: Integer,
+    chat_id: Integer,
+    message_id: Integer,
+    message: Option<Box<Message>>,
+}
+
+impl HeldMessage {
+    pub fn new(user_id: Integer, chat_id: Integer, message_id: Integer) -> Self {
+        Self {
+            user_id,
+            chat_id,
+            message_id,
+            message: None,
+        }
+    }
+
+    pub fn with_message(user_id: Integer, message: Message) -> Self {
+        Self {
+            user_id,
+            chat_id: message.get_chat_id(),
+            message_id: message.id,
+            message: Some(Box::new(message)),
+        }
+    }
+
+    pub fn user_id(&self) -> Integer {
+        self.user_id
+    }
+
+    pub fn chat_id(&self) -> Integer {
+        self.chat_id
+    }
+
+    pub fn message_id(&self) -> Integer {
+        self.message_id
+    }
+
+    pub fn message(&self) -> Option<&Message> {
+        self.message.as_deref()
+    }
+}
+
+#[derive(Debug)]
+pub enum VerificationServiceError {
+    CheckAnswer { source: ClientError, user_id: Integer },
+    CheckIsVerified { source: ClientError, user_id: Integer },
+    Hold { source: ClientError, message: HeldMessage },
+    SetChallenge { source: ClientError, user_id: Integer },
+    TakeHeld { source: ClientError, user_id: Integer },
+    Verify { source: ClientError, user_id: Integer },
+}
+
+impl fmt::Display for VerificationServiceError {
+    fn fmt(&self, out: &mut fmt::Formatter) -> fmt::Result {
+        use self::VerificationServiceError::*;
+        match self {
+            CheckAnswer { source, user_id } => write!(
+                out,
+                "failed to check captcha answer for user with id {}: {}",
+                user_id, source
+            ),
+            CheckIsVerified { source, user_id } => write!(
+                out,
+                "failed to check whether user with id {} verified: {}",
+                user_id, source
+            ),
+            Hold { source, message } => write!(out, "failed to hold message: {} ({:?})", source, message),
+            SetChallenge { source, user_id } => write!(
+                out,
+                "failed to set captcha challenge for user with id {}: {}",
+                user_id, source
+            ),
+            TakeHeld { source, user_id } => write!(
+                out,
+                "failed to take held messages for user with id {}: {}",
+                user_id, source
+            ),
+            Verify { source, user_id } => write!(out, "failed to verify user with id {}: {}", user_id, source),
+        }
+    }
+}
+
+impl Error for VerificationServiceError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        use self::VerificationServiceError::*;
+        Some(match self {
+            CheckAnswer { source, .. } => source,
+            CheckIsVerified { source, .. } => source,
+            Hold { source, .. } => source,
+            SetChallenge { source, .. } => source,
+            TakeHeld { source, .. } => source,
+            Verify { source, .. } => source,
+        })
+    }
+}
@@ -0,0 +1,140 @@
+use carapax::{types::Update, UpdateHandler};
+use futures_util::future::BoxFuture;
+use std::{
+    future::Future,
+    io::Error as IoError,
+    sync::{
+        atomic::{AtomicI64, Ordering},
+        Arc,
+    },
+};
+use tokio::{
+    select,
+    signal::{
+        ctrl_c,
+        unix::{signal, SignalKind},
+    },
+    spawn,
+    sync::{mpsc, watch},
+};
+
+pub struct Shutdown {
+    stop: watch::Sender<bool>,
+    tracker: Tracker,
+    finished: mpsc::Receiver<()>,
+    last_update_id: Arc<AtomicI64>,
+}
+
+impl Shutdown {
+    pub fn new() -> Self {
+        let (stop, stopped) = watch::channel(false);
+        let (running, finished) = mpsc::channel(1);
+        let last_update_id = Arc::new(AtomicI64::new(0));
+        Self {
+            stop,
+            tracker: Tracker {
+                stopped,
+                running,
+                last_update_id: last_update_id.clone(),
+            },
+            finished,
+            last_update_id,
+        }
+    }
+
+    pub fn tracker(&self) -> Tracker {
+        self.tracker.clone()
+    }
+
+    pub fn stop(&self) {
+        let _ = self.stop.send(true);
+    }
+
+    /// Waits for tracked tasks and returns the ID of the last handled update
+    pub async fn wait(self) -> Option<i64> {
+        let Self {
+            tracker,
+            mut finished,
+            last_update_id,
+            ..
+        } = self;
+        drop(tracker);
+        // recv returns None when every tracked task has dropped its sender
+        finished.recv().await;
+        match last_update_id.load(Ordering::Relaxed) {
+            0 => None,
+            update_id => Some(update_id),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Tracker {
+    stopped: watch::Receiver<bool>,
+    running: mpsc::Sender<()>,
+    last_update_id: Arc<AtomicI64>,
+}
+
+impl Tracker {
+    pub fn stopped(&self) -> impl Future<Output = ()> + Send + 'static {
+        let mut stopped = self.stopped.clone();
+        async move {
+            while !*stopped.borrow() {
+                if stopped.changed().await.is_err() {
+                    break;
+                }
+            }
+        }
+    }
+
+    pub fn spawn<F>(&self, future: F)
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        let running = self.running.clone();
+        spawn(async move {
+            future.await;
+            drop(running);
+        });
+    }
+
+    pub fn wrap<H>(&self, handler: H) -> TrackedHandler<H> {
+        TrackedHandler {
+            handler,
+            tracker: self.clone(),
+        }
+    }
+}
+
+pub struct TrackedHandler<H> {
+    handler: H,
+    tracker: Tracker,
+}
+
+impl<H> UpdateHandler for TrackedHandler<H>
+where
+    H: UpdateHandler,
+    H::Future: Send + 'static,
+{
+    type Future = BoxFuture<'static, ()>;
+
+    fn handle(&self, update: Update) -> Self::Future {
+        let (running, last_update_id) = (self.tracker.running.clone(), self.tracker.last_update_id.clone());
+        let update_id = update.id;
+        let future = self.handler.handle(update);
+        Box::pin(async move {
+            future.await;
+            // an update is confirmed only when it has been handled
+            last_update_id.fetch_max(update_id, Ordering::Relaxed);
+            drop(running);
+        })
+    }
+}
+
+pub async fn wait_for_signal() -> Result<(), IoError> {
+    let mut terminate = signal(SignalKind::terminate())?;
+    select! {
+        result = ctrl_c() => result,
+        _ = terminate.recv() => Ok(()),
+    }
+}